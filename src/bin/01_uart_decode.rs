@@ -2,7 +2,7 @@
 extern crate serde;
 extern crate vxi11;
 
-use std::io::{self, Error, ErrorKind};
+use std::io::{self, Error};
 
 use vxi11::devices::sds1202x::{SDS1202X, TriggerMode, protocol_decode};
 
@@ -31,7 +31,7 @@ pub fn main() -> io::Result<()> {
 
 		let ch1:Vec<i8> = sds1202x.transfer_waveform_raw(1)?;
 		let uart_rx_bytes:Vec<u8> = protocol_decode::uart(&ch1, samp_rate_sps, 9600.0, 8)?;
-		let uart_rx:&str = std::str::from_utf8(&uart_rx_bytes).map_err(|_| Error::new(ErrorKind::Other, "Unable to parse response as UTF-8"))?;
+		let uart_rx:&str = std::str::from_utf8(&uart_rx_bytes).map_err(|_| Error::other("Unable to parse response as UTF-8"))?;
 		println!("{}", uart_rx);
 	}
 
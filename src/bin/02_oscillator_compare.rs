@@ -3,7 +3,7 @@ extern crate rppal;
 extern crate serde;
 extern crate vxi11;
 
-use std::io::{self, Error, ErrorKind};
+use std::io::{self, Error};
 use std::thread;
 use std::time::{Instant, Duration};
 
@@ -37,7 +37,7 @@ pub fn main() -> io::Result<()> {
     // Get GPIO interface for reset commands
 	let device_info = match DeviceInfo::new() {
 		Ok(dev) => dev,
-		Err(_) => return Err(Error::new(ErrorKind::Other, "Unable to get device info, may not be running on Raspberry Pi"))
+		Err(_) => return Err(Error::other("Unable to get device info, may not be running on Raspberry Pi"))
 	};
 	eprintln!("Running on {} with {}", device_info.model(), device_info.soc());
 
@@ -91,8 +91,8 @@ pub fn main() -> io::Result<()> {
 		let fs:f32 = sds1202x.get_sample_rate()?;
 
 	    // Use the channel 2 midpoint for both because we're triggering off channel 2, so we know it has a transition
-		let ch2_min:i8 = *(ch2.iter().min().ok_or(Error::new(ErrorKind::Other, "Unable to find min value of CH2"))?);
-		let ch2_max:i8 = *(ch2.iter().max().ok_or(Error::new(ErrorKind::Other, "Unable to find max value of CH2"))?);
+		let ch2_min:i8 = *(ch2.iter().min().ok_or(Error::other("Unable to find min value of CH2"))?);
+		let ch2_max:i8 = *(ch2.iter().max().ok_or(Error::other("Unable to find max value of CH2"))?);
 		let ch2_mid:i8 = ch2_min + ((ch2_max - ch2_min) / 2);	// This way avoids the overflow risk
 		
 		if ch1[0] > ch2_mid || *(ch1.last().unwrap()) < ch2_mid { 
@@ -101,8 +101,8 @@ pub fn main() -> io::Result<()> {
 	        last_reset_time = Instant::now();
 		} else {
 		    // Both transitions are in view
-		    let (ch1_midx, _) = ch1.iter().enumerate().find(|(_, x)| x > &&ch2_mid).ok_or(Error::new(ErrorKind::Other, "Unable to find a crossing in CH1"))?;
-		    let (ch2_midx, _) = ch2.iter().enumerate().find(|(_, x)| x > &&ch2_mid).ok_or(Error::new(ErrorKind::Other, "Unable to find a crossing in CH2"))?;
+		    let (ch1_midx, _) = ch1.iter().enumerate().find(|(_, x)| x > &&ch2_mid).ok_or(Error::other("Unable to find a crossing in CH1"))?;
+		    let (ch2_midx, _) = ch2.iter().enumerate().find(|(_, x)| x > &&ch2_mid).ok_or(Error::other("Unable to find a crossing in CH2"))?;
 
 		    let dt:f32 = (ch2_midx as f32 - ch1_midx as f32) / fs;
 
@@ -0,0 +1,105 @@
+
+extern crate vxi11;
+
+use std::io::{self, Error};
+use std::time::{Duration, Instant};
+
+use vxi11::xdr::{Packer, Unpacker};
+use vxi11::vxi11::xdr_pack::pack_device_read_resp;
+
+// Benchmark for xdr::Unpacker on large replies.  For comparison, LegacyUnpacker reproduces the original approach of
+// removing bytes from the front of a Vec as they're consumed, which is O(n) per field and so O(n^2) per message.
+
+struct LegacyUnpacker {
+	buff: Vec<u8>,
+}
+
+impl LegacyUnpacker {
+	fn drop(&mut self, n:usize) -> io::Result<()> {
+		for _ in 0..n {
+			if self.buff.is_empty() { return Err(Error::other("Tried to drop past the end of the buffer")) }
+			self.buff.remove(0);
+		}
+		Ok(())
+	}
+
+	fn unpack_u32(&mut self) -> io::Result<u32> {
+		if self.buff.len() < 4 { return Err(Error::other("Tried to read past the end of the buffer")) }
+		let ans:u32 = u32::from_be_bytes([self.buff[0], self.buff[1], self.buff[2], self.buff[3]]);
+		self.drop(4)?;
+		Ok(ans)
+	}
+
+	fn unpack_variable_len_opaque(&mut self) -> io::Result<Vec<u8>> {
+		let n:u32 = self.unpack_u32()?;
+		let ans:Vec<u8> = self.buff.drain(..(n as usize)).collect();
+		while !self.buff.len().is_multiple_of(4) { self.buff.remove(0); }
+		Ok(ans)
+	}
+}
+
+// Sizes above this take minutes with the legacy approach when every field is a u32
+const LEGACY_MAX_WORDS:usize = 1 << 16;
+
+fn time<F: FnMut() -> io::Result<()>>(mut f:F) -> io::Result<Duration> {
+	let start = Instant::now();
+	f()?;
+	Ok(start.elapsed())
+}
+
+pub fn main() -> io::Result<()> {
+
+	// A DEVICE_READ reply carrying a waveform, like the SDS1202X sends for WF? DAT2
+	println!("DEVICE_READ reply with opaque waveform data");
+	for &size in &[1_400_000usize, 4_000_000, 16_000_000] {
+		let mut packer = Packer::new();
+		pack_device_read_resp(&mut packer, 0, 4, &vec![0x5a; size])?;
+		let reply:Vec<u8> = packer.get_buf()?;
+
+		let mut unpacker = Unpacker::new();
+		unpacker.reset_owned(reply.clone());
+		let t_new = time(|| {
+			unpacker.unpack_i32()?;
+			unpacker.unpack_i32()?;
+			unpacker.unpack_variable_len_opaque_ref(u32::MAX)?;
+			Ok(())
+		})?;
+
+		let mut legacy = LegacyUnpacker{ buff: reply.clone() };
+		let t_old = time(|| {
+			legacy.unpack_u32()?;
+			legacy.unpack_u32()?;
+			legacy.unpack_variable_len_opaque().map(|_| ())
+		})?;
+
+		println!("  {:>10} bytes: cursor {:>12?}  legacy {:>12?}", size, t_new, t_old);
+	}
+
+	// A reply made entirely of small fields, like a portmapper DUMP or a variable-length array of ints
+	println!("Reply made of u32 fields");
+	for &words in &[1usize << 12, 1 << 14, 1 << 16, 1 << 20] {
+		let mut packer = Packer::new();
+		for i in 0..words { packer.pack_u32(i as u32)?; }
+		let reply:Vec<u8> = packer.get_buf()?;
+
+		let mut unpacker = Unpacker::new();
+		unpacker.reset_owned(reply.clone());
+		let t_new = time(|| {
+			for _ in 0..words { unpacker.unpack_u32()?; }
+			Ok(())
+		})?;
+
+		if words <= LEGACY_MAX_WORDS {
+			let mut legacy = LegacyUnpacker{ buff: reply.clone() };
+			let t_old = time(|| {
+				for _ in 0..words { legacy.unpack_u32()?; }
+				Ok(())
+			})?;
+			println!("  {:>10} bytes: cursor {:>12?}  legacy {:>12?}", reply.len(), t_new, t_old);
+		} else {
+			println!("  {:>10} bytes: cursor {:>12?}  legacy skipped", reply.len(), t_new);
+		}
+	}
+
+	Ok(())
+}
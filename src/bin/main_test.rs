@@ -1,20 +1,51 @@
 
 extern crate vxi11;
 
-use std::io;
+use std::convert::TryFrom;
+use std::env;
+use std::io::{self, Error, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::Duration;
 
-use vxi11::rpc::{xdr_pack, IPPROTO_TCP};
-use vxi11::rpc::port_mapping::{PMAP_PORT, PMAP_PROG, PMAP_VERS, PMAPPROC_GETPORT};
-use vxi11::rpc::udp_clients::BroadcastUdpClient;
-use vxi11::vxi11::{DEVICE_CORE_PROG, DEVICE_CORE_VERS};
+use vxi11::rpc::udp_clients::DEFAULT_BROADCAST_WINDOW;
+use vxi11::vxi11::{discover, CoreClient, LinkOptions, DEFAULT_LOCK_TIMEOUT};
 
+// Lists the VXI-11 instruments on the network along with their identification strings
+// Usage: main_test [broadcast address] [window in milliseconds] [local address]
 pub fn main() -> io::Result<()> {
+	let invalid = |msg:&str| Error::new(ErrorKind::InvalidInput, msg.to_owned());
 
-	let mut pmap = BroadcastUdpClient::bind(PMAP_PORT, PMAP_PROG, PMAP_VERS)?;
-	pmap.start_call(PMAPPROC_GETPORT)?;
-	xdr_pack::pack_mapping(&mut pmap.packer, DEVICE_CORE_PROG, DEVICE_CORE_VERS, IPPROTO_TCP, 0)?;
+	let mut args = env::args().skip(1);
+	let broadcast_addr:IpAddr = match args.next() {
+		Some(s) => s.parse().map_err(|_| invalid("Invalid broadcast address"))?,
+		None    => Ipv4Addr::BROADCAST.into(),
+	};
+	let window:Duration = match args.next() {
+		Some(s) => Duration::from_millis(s.parse().map_err(|_| invalid("Invalid window"))?),
+		None    => DEFAULT_BROADCAST_WINDOW,
+	};
+	let local_addr:Option<IpAddr> = match args.next() {
+		Some(s) => Some(s.parse().map_err(|_| invalid("Invalid local address"))?),
+		None    => None,
+	};
+
+	for (addr, port) in discover(broadcast_addr, local_addr, window)? {
+		let host:String = addr.ip().to_string();
+		let idn:String = match identify(&host, port) {
+			Ok(idn) => idn,
+			Err(e)  => format!("({})", e),
+		};
+		println!("{} (device core on port {}): {}", host, port, idn);
+	}
 
-	pmap.make_call()?;
-	
 	Ok(())
 }
+
+// Connects on the port the instrument just gave, rather than asking its portmapper all over again
+fn identify(host:&str, port:u32) -> io::Result<String> {
+	let port:u16 = u16::try_from(port).map_err(|_| Error::new(ErrorKind::InvalidData, format!("Invalid port {}", port)))?;
+	let mut link = CoreClient::with_core_port(host, port, LinkOptions::default())?.create_link("inst0", false, DEFAULT_LOCK_TIMEOUT)?;
+	let idn:Vec<u8> = link.ask(b"*IDN?\n")?;
+	link.destroy()?;
+	Ok(String::from_utf8_lossy(&idn).trim().to_owned())
+}
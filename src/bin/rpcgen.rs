@@ -0,0 +1,33 @@
+
+extern crate vxi11;
+
+use std::env;
+use std::fs;
+use std::io::{self, Error, ErrorKind};
+
+use vxi11::rpcgen::{self, Options};
+
+// Usage: rpcgen <input.x> [output.rs] [--crate <path>]
+// Writes to stdout if no output file is given
+pub fn main() -> io::Result<()> {
+	let mut opts = Options::default();
+	let mut files:Vec<String> = vec![];
+
+	let mut args = env::args().skip(1);
+	while let Some(arg) = args.next() {
+		if arg == "--crate" {
+			opts.crate_path = args.next().ok_or_else(|| Error::new(ErrorKind::InvalidInput, "--crate needs a path"))?;
+		} else {
+			files.push(arg);
+		}
+	}
+
+	match files.as_slice() {
+		[input] => {
+			print!("{}", rpcgen::compile(&fs::read_to_string(input)?, &opts)?);
+			Ok(())
+		},
+		[input, output] => rpcgen::compile_file(input, output, &opts),
+		_ => Err(Error::new(ErrorKind::InvalidInput, "Usage: rpcgen <input.x> [output.rs] [--crate <path>]")),
+	}
+}
@@ -3,8 +3,7 @@
 extern crate regex;
 extern crate serde;
 
-use std::io::{self, Error, ErrorKind};
-use std::ops::Drop;
+use std::io::{self, Error};
 use std::str;
 use std::thread;
 use std::time::Duration;
@@ -12,7 +11,7 @@ use std::time::Duration;
 use regex::{Captures, Match, Regex};
 use serde::{Serialize, Deserialize};
 
-use crate::vxi11::CoreClient;
+use crate::vxi11::{CoreClient, DeviceLink, DEFAULT_LOCK_TIMEOUT};
 
 lazy_static! {
     static ref BSWV_RE: Regex     = Regex::new("(C[12]):BSWV\\sWVTP,(SINE|SQUARE|RAMP|PULSE|NOISE|ARB|DC)").unwrap();
@@ -26,7 +25,7 @@ lazy_static! {
 pub const DEFAULT_TX_THROTTLE_DURATION_SEC:f32 = 0.1;
 
 pub struct SDG2042X {
-	core: CoreClient,
+	core: DeviceLink,
 	tx_throttle_duration: Duration,
 	pub state: Option<State>,
 }
@@ -64,12 +63,12 @@ pub struct ChannelState {
 fn match_str(opt_match:Option<Match>, err:&str) -> io::Result<String> {
 	match opt_match {
 		Some(m) => Ok(m.as_str().to_owned()),
-		None    => Err(Error::new(ErrorKind::Other, err))
+		None    => Err(Error::other(err))
 	}
 }
 
 pub fn chan_ok(n:u8) -> io::Result<()> {
-	if n != 1 && n != 2 { Err(Error::new(ErrorKind::Other, "SDG2042X only has two channels")) }
+	if n != 1 && n != 2 { Err(Error::other("SDG2042X only has two channels")) }
 	else { Ok(()) }		
 }
 
@@ -77,16 +76,14 @@ pub fn chan_ok(n:u8) -> io::Result<()> {
 impl SDG2042X {		
 
 	pub fn new(host:&str) -> io::Result<Self> {
-		let mut core = CoreClient::new(host)?;
-
-		core.create_link()?;
+		let mut core = CoreClient::new(host)?.create_link("inst0", false, DEFAULT_LOCK_TIMEOUT)?;
 
 		match str::from_utf8(&(core.ask(b"*IDN?")?)) {
 			Ok(idn_resp) => {
 				if idn_resp.contains("SDG2042X") { /* Do nothing because this is what we expected */ }
-				else { return Err(Error::new(ErrorKind::Other, "Successfully connected to a device but it doesn't appear to be the right model")); }
+				else { return Err(Error::other("Successfully connected to a device but it doesn't appear to be the right model")); }
 			},
-			Err(_) => return Err(Error::new(ErrorKind::Other, "Received a response to *IDN? but unable to interpret as UTF-8")),
+			Err(_) => return Err(Error::other("Received a response to *IDN? but unable to interpret as UTF-8")),
 		}
 
 		// TODO: make this configurable
@@ -95,6 +92,11 @@ impl SDG2042X {
 		Ok(Self{ core, tx_throttle_duration, state: None })
 	}
 
+	// Destroys the link now rather than when this is dropped, so any error can be seen
+	pub fn close(self) -> io::Result<()> {
+		Ok(self.core.destroy()?)
+	}
+
 	pub fn get_full_state(&mut self) -> io::Result<State> {
 	    let str_idn:String      = str::from_utf8(&self.core.ask(b"*IDN?")?).map(|s| s.to_owned()).unwrap();
 		let caps_idn:Captures   = IDN_RE.captures(&str_idn).unwrap();
@@ -116,7 +118,7 @@ impl SDG2042X {
 		let bswv_res:String   = self.ask_str(&bswv_cmd)?;
 		let bswv_cap:Captures = match BSWV_RE.captures(&bswv_res) {
 			Some(c) => c,
-			_ => return Err(Error::new(ErrorKind::Other, "Unable to match expression for basic wavetype")),
+			_ => return Err(Error::other("Unable to match expression for basic wavetype")),
 		};
 
 		// TODO: check that capture 1 represents the same channel we asked for
@@ -124,18 +126,18 @@ impl SDG2042X {
 			"DC" => {
 				let cap:Captures = match BSWV_DC_RE.captures(&bswv_res) {
 					Some(c) => c,
-					_ => return Err(Error::new(ErrorKind::Other, "Unable to match expression for DC wavetype")),
+					_ => return Err(Error::other("Unable to match expression for DC wavetype")),
 				};
 
 				let offset_v:f32 = (match_str(cap.get(2), "No match for offset_v")?).parse::<f32>()
-					.map_err(|_| Error::new(ErrorKind::Other, "Unable to parse matched offset_v as an f32"))?;
+					.map_err(|_| Error::other("Unable to parse matched offset_v as an f32"))?;
 
 				Ok(ChannelState{ basic_wavetype: Wavetype::DC, freq_hz:0, amp_v:0.0, offset_v, phase_deg:0.0 })
 			},
 			s => {
 				let cap:Captures = match BSWV_DEF_RE.captures(&bswv_res) {
 					Some(c) => c,
-					_ => return Err(Error::new(ErrorKind::Other, "Unable to match expression for default wavetype")),
+					_ => return Err(Error::other("Unable to match expression for default wavetype")),
 				};
 
 				let basic_wavetype:Wavetype = match s {
@@ -145,17 +147,17 @@ impl SDG2042X {
 					"PULSE"  => Wavetype::Pulse,
 					"NOISE"  => Wavetype::Noise,
 					"ARB"    => Wavetype::Arb,
-					_        => return Err(Error::new(ErrorKind::Other, "Unrecognized basic wavetype")),	
+					_        => return Err(Error::other("Unrecognized basic wavetype")),	
 				};
 
 				let freq_hz:u32 = (match_str(cap.get(3), "No match for freq_hz")?).parse::<u32>()
-					.map_err(|_| Error::new(ErrorKind::Other, "Unable to parse matched freq_hz as a u32"))?;
+					.map_err(|_| Error::other("Unable to parse matched freq_hz as a u32"))?;
 				let amp_v:f32 = (match_str(cap.get(4), "No match for amp_v")?).parse::<f32>()
-					.map_err(|_| Error::new(ErrorKind::Other, "Unable to parse matched amp_v as an f32"))?;
+					.map_err(|_| Error::other("Unable to parse matched amp_v as an f32"))?;
 				let offset_v:f32 = (match_str(cap.get(5), "No match for offset_v")?).parse::<f32>()
-					.map_err(|_| Error::new(ErrorKind::Other, "Unable to parse matched offset_v as an f32"))?;
+					.map_err(|_| Error::other("Unable to parse matched offset_v as an f32"))?;
 				let phase_deg:f32 = (match_str(cap.get(6), "No match for phase_deg")?).trim_end().parse::<f32>()
-					.map_err(|_| Error::new(ErrorKind::Other, "Unable to parse matched phase_deg as an f32"))?;
+					.map_err(|_| Error::other("Unable to parse matched phase_deg as an f32"))?;
 
 				Ok(ChannelState{ basic_wavetype, freq_hz, amp_v, offset_v, phase_deg })
 			}
@@ -214,23 +216,17 @@ impl SDG2042X {
 
 	pub fn ask(&mut self, data:&[u8]) -> io::Result<Vec<u8>> { 
 		thread::sleep(self.tx_throttle_duration);
-		self.core.ask(data) 
+		Ok(self.core.ask(data)?)
 	}
 
 	pub fn ask_str(&mut self, data:&str) -> io::Result<String> {
 		str::from_utf8(&self.core.ask(data.as_bytes())?)
 			.map(|s| s.to_owned())
-			.map_err(|_| Error::new(ErrorKind::Other, "Unable to parse response as UTF-8"))
+			.map_err(|_| Error::other("Unable to parse response as UTF-8"))
 	}
 
 }
 
-impl Drop for SDG2042X {
-
-	fn drop(&mut self) { self.core.destroy_link().expect("Unable to destroy link for SDG2042X"); }
-
-}
-
 // Not Yet Implemented
 // *CLS *CLS SYSTEM Clears all the status data registers.
 // *ESE *ESE SYSTEM Sets or gets the Standard Event Status Enable register (ESE).
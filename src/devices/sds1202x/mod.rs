@@ -2,8 +2,7 @@
 extern crate byteorder;
 extern crate regex;
 
-use std::io::{self, Error, ErrorKind, Cursor};
-use std::ops::Drop;
+use std::io::{self, Error, Cursor};
 use std::str;
 use std::thread;
 use std::time::Duration;
@@ -12,7 +11,7 @@ use byteorder::ReadBytesExt;
 use regex::{Captures, Match, Regex};
 use serde::{Serialize, Deserialize};
 
-use crate::vxi11::CoreClient;
+use crate::vxi11::{CoreClient, DeviceLink, DEFAULT_LOCK_TIMEOUT};
 
 lazy_static! {
     static ref IDN_RE: Regex  = Regex::new("([^,]+),([^,]+),([^,]+),([^,\\s]+)").unwrap();
@@ -30,7 +29,7 @@ pub const DEFAULT_TX_THROTTLE_DURATION_SEC:f32 = 0.001;
 pub mod protocol_decode;
 
 pub struct SDS1202X {
-	core: CoreClient,
+	core: DeviceLink,
 	tx_throttle_duration: Duration,
 	pub state: Option<State>,
 }
@@ -60,11 +59,11 @@ pub enum TriggerMode { Auto, Norm, Single, Stop }
 fn match_str(opt_match:Option<Match>, err:&str) -> io::Result<String> {
 	match opt_match {
 		Some(m) => Ok(m.as_str().to_owned()),
-		None    => Err(Error::new(ErrorKind::Other, err))
+		None    => Err(Error::other(err))
 	}
 }
 
-fn err(msg:&str) -> io::Error { Error::new(ErrorKind::Other, msg) }
+fn err(msg:&str) -> io::Error { Error::other(msg) }
 
 fn chan_ok(n:u8) -> io::Result<()> {
 	if n != 1 && n != 2 { Err(err("SDS1202X only has two channels")) }
@@ -75,16 +74,14 @@ fn chan_ok(n:u8) -> io::Result<()> {
 impl SDS1202X {		
 
 	pub fn new(host:&str) -> io::Result<Self> {
-		let mut core = CoreClient::new(host)?;
-
-		core.create_link()?;
+		let mut core = CoreClient::new(host)?.create_link("inst0", false, DEFAULT_LOCK_TIMEOUT)?;
 
 		match str::from_utf8(&(core.ask(b"*IDN?")?)) {
 			Ok(idn_resp) => {
 				if idn_resp.contains("SDS1202X") { /* Do nothing because this is what we expected */ }
-				else { return Err(Error::new(ErrorKind::Other, "Successfully connected to a device but it doesn't appear to be the right model")); }
+				else { return Err(Error::other("Successfully connected to a device but it doesn't appear to be the right model")); }
 			},
-			Err(_) => return Err(Error::new(ErrorKind::Other, "Received a response to *IDN? but unable to interpret as UTF-8")),
+			Err(_) => return Err(Error::other("Received a response to *IDN? but unable to interpret as UTF-8")),
 		}
 
 		// TODO: make this configurable
@@ -93,6 +90,11 @@ impl SDS1202X {
 		Ok(Self{ core, tx_throttle_duration, state: None })
 	}
 
+	// Destroys the link now rather than when this is dropped, so any error can be seen
+	pub fn close(self) -> io::Result<()> {
+		Ok(self.core.destroy()?)
+	}
+
 	pub fn get_full_state(&mut self) -> io::Result<State> {
 	    let str_idn:String      = str::from_utf8(&self.core.ask(b"*IDN?")?).map(|s| s.to_owned()).unwrap();
 		let caps_idn:Captures   = IDN_RE.captures(&str_idn).unwrap();
@@ -123,7 +125,7 @@ impl SDS1202X {
 	pub fn get_time_division(&mut self) -> io::Result<f32> {
 	    let res:String   = self.ask_str("TDIV?")?;
 	    let cap:Captures = TDIV_RE.captures(&res).unwrap();
-    	(match_str(cap.get(1), "No match for time_division")?).parse::<f32>().map_err(|_| Error::new(ErrorKind::Other, "Unable to parse time division into f32"))
+    	(match_str(cap.get(1), "No match for time_division")?).parse::<f32>().map_err(|_| Error::other("Unable to parse time division into f32"))
 	}
 
 	pub fn set_time_division(&mut self, tdiv:f32) -> io::Result<()> {
@@ -200,7 +202,7 @@ impl SDS1202X {
 	    // println!("ch_all={:?}", ch_all);
 
 	    let cmd:String = format!("C{}:WAVEFORM? DAT2", chan_num);
-	    let ch_dat2:Vec<u8> = self.ask(&(cmd.as_bytes()))?;
+	    let ch_dat2:Vec<u8> = self.ask(cmd.as_bytes())?;
 
 		// TODO: process the rest of the header
 		let (header, body) = ch_dat2.split_at(21);
@@ -303,22 +305,16 @@ impl SDS1202X {
 
 	pub fn ask(&mut self, data:&[u8]) -> io::Result<Vec<u8>> { 
 		thread::sleep(self.tx_throttle_duration);
-		self.core.ask(data) 
+		Ok(self.core.ask(data)?)
 	}
 
 	pub fn ask_str(&mut self, data:&str) -> io::Result<String> {
 		str::from_utf8(&self.core.ask(data.as_bytes())?)
 			.map(|s| s.to_owned())
-			.map_err(|_| Error::new(ErrorKind::Other, "Unable to parse response as UTF-8"))
+			.map_err(|_| Error::other("Unable to parse response as UTF-8"))
 	}
 }
 
-impl Drop for SDS1202X {
-
-	fn drop(&mut self) { self.core.destroy_link().expect("Unable to destroy link for SDS1202X"); }
-
-}
-
 // Not Yet Implemented
 // ALST?	ALL_STATUS?			STATUS
 // ATTN	ATTENUATION			ACQUISITION
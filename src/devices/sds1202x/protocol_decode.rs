@@ -1,5 +1,5 @@
 
-use std::io::{self, Error, ErrorKind};
+use std::io::{self, Error};
 
 #[derive(PartialEq)]
 enum Transition { HighToLow, LowToHigh}
@@ -38,8 +38,8 @@ fn transitions_to_pulses(transitions:&[(usize, Transition)]) -> Vec<(usize, bool
 
 pub fn uart(data:&[i8], sample_rate_sps:f32, baud_rate:f32, bits_per_frame:usize) -> io::Result<Vec<u8>> {
 
-	let lo_level:i8 = data.iter().map(|x| *x).min().unwrap();
-	let hi_level:i8 = data.iter().map(|x| *x).max().unwrap();
+	let lo_level:i8 = data.iter().copied().min().unwrap();
+	let hi_level:i8 = data.iter().copied().max().unwrap();
 
 	let lo_thres:i8 = lo_level + ((hi_level-lo_level) / 5);
 	let hi_thres:i8 = hi_level - ((hi_level-lo_level) / 5);
@@ -50,7 +50,7 @@ pub fn uart(data:&[i8], sample_rate_sps:f32, baud_rate:f32, bits_per_frame:usize
 	let transitions = all_transitions(data, hi_thres, lo_thres);
 	let mut pulses  = transitions_to_pulses(&transitions);
 
-	'search: while pulses.len() > 0 {
+	'search: while !pulses.is_empty() {
 		// Loop until we find a high pulse at least the length of a frame
 		let (len, logic) = pulses.remove(0); 
 
@@ -76,9 +76,9 @@ pub fn uart(data:&[i8], sample_rate_sps:f32, baud_rate:f32, bits_per_frame:usize
 				// Start bits are low voltage (logical 0), so this is the start bit we were waiting for, plus potentially a few data bits
 				need_start_bit = false;
 				current_byte = 0;
-				place_val = 2u8.checked_pow((pulse_len-1) as u32).ok_or(Error::new(ErrorKind::Other, "Overflow in u8 exponentiation"))?;
+				place_val = 2u8.checked_pow((pulse_len-1) as u32).ok_or(Error::other("Overflow in u8 exponentiation"))?;
 			} else {
-				return Err(Error::new(ErrorKind::Other, "Encountered high voltage when low was expected as a start bit"))
+				return Err(Error::other("Encountered high voltage when low was expected as a start bit"))
 			}
 		} else if need_stop_bit {
 			if logic {
@@ -88,7 +88,7 @@ pub fn uart(data:&[i8], sample_rate_sps:f32, baud_rate:f32, bits_per_frame:usize
 				need_stop_bit  = false;
 				need_start_bit = true;
 			} else {
-				return Err(Error::new(ErrorKind::Other, "Encountered low voltage when high was expected as a stop bit"))
+				return Err(Error::other("Encountered low voltage when high was expected as a stop bit"))
 			}
 		} else {
 			// We're just in the middle of building a frame
@@ -112,7 +112,7 @@ pub fn uart(data:&[i8], sample_rate_sps:f32, baud_rate:f32, bits_per_frame:usize
 								need_stop_bit  = false;
 								need_start_bit = true;
 							} else { 
-								return Err(Error::new(ErrorKind::Other, "Encountered low voltage when high was expected")) 
+								return Err(Error::other("Encountered low voltage when high was expected")) 
 							}
 						} 
 
@@ -3,8 +3,7 @@
 extern crate regex;
 extern crate serde;
 
-use std::io::{self, Error, ErrorKind};
-use std::ops::Drop;
+use std::io::{self, Error};
 use std::str;
 use std::thread;
 use std::time::Duration;
@@ -12,7 +11,7 @@ use std::time::Duration;
 use regex::{Captures, Match, Regex};
 use serde::{Serialize, Deserialize};
 
-use crate::vxi11::CoreClient;
+use crate::vxi11::{CoreClient, DeviceLink, DEFAULT_LOCK_TIMEOUT};
 
 lazy_static! {
     static ref IDN_RE: Regex      = Regex::new("([^,]+),([^,]+),([^,]+),([^,\\s]+)").unwrap();
@@ -21,7 +20,7 @@ lazy_static! {
 pub const DEFAULT_TX_THROTTLE_DURATION_SEC:f32 = 1.0;
 
 pub struct SPD3303X {
-	core: CoreClient,
+	core: DeviceLink,
 	tx_throttle_duration: Duration,
 	pub state: Option<State>,
 }
@@ -47,14 +46,14 @@ pub struct State {
 fn match_str(opt_match:Option<Match>, err:&str) -> io::Result<String> {
 	match opt_match {
 		Some(m) => Ok(m.as_str().to_owned()),
-		None    => Err(Error::new(ErrorKind::Other, err))
+		None    => Err(Error::other(err))
 	}
 }
 
-fn err(msg:&str) -> io::Error { Error::new(ErrorKind::Other, msg) }
+fn err(msg:&str) -> io::Error { Error::other(msg) }
 
 pub fn chan_ok(n:u8) -> io::Result<()> {
-	if n != 1 && n != 2 { Err(Error::new(ErrorKind::Other, "SDG2042X only has two channels")) }
+	if n != 1 && n != 2 { Err(Error::other("SDG2042X only has two channels")) }
 	else { Ok(()) }		
 }
 
@@ -62,16 +61,14 @@ pub fn chan_ok(n:u8) -> io::Result<()> {
 impl SPD3303X {		
 
 	pub fn new(host:&str) -> io::Result<Self> {
-		let mut core = CoreClient::new(host)?;
-
-		core.create_link()?;
+		let mut core = CoreClient::new(host)?.create_link("inst0", false, DEFAULT_LOCK_TIMEOUT)?;
 
 		match str::from_utf8(&(core.ask(b"*IDN?")?)) {
 			Ok(idn_resp) => {
 				if idn_resp.contains("SPD3303X") { /* Do nothing because this is what we expected */ }
-				else { return Err(Error::new(ErrorKind::Other, "Successfully connected to a device but it doesn't appear to be the right model")); }
+				else { return Err(Error::other("Successfully connected to a device but it doesn't appear to be the right model")); }
 			},
-			Err(_) => return Err(Error::new(ErrorKind::Other, "Received a response to *IDN? but unable to interpret as UTF-8")),
+			Err(_) => return Err(Error::other("Received a response to *IDN? but unable to interpret as UTF-8")),
 		}
 
 		// TODO: make this configurable
@@ -80,6 +77,11 @@ impl SPD3303X {
 		Ok(Self{ core, tx_throttle_duration, state: None })
 	}
 
+	// Destroys the link now rather than when this is dropped, so any error can be seen
+	pub fn close(self) -> io::Result<()> {
+		Ok(self.core.destroy()?)
+	}
+
 	pub fn get_full_state(&mut self) -> io::Result<State> {
 	    let str_idn:String      = str::from_utf8(&self.core.ask(b"*IDN?")?).map(|s| s.to_owned()).unwrap();
 		let caps_idn:Captures   = IDN_RE.captures(&str_idn).unwrap();
@@ -111,7 +113,7 @@ impl SPD3303X {
 
 	    let cmd:String   = format!("CH{}:VOLT?", ch);
 	    let res:String   = self.ask_str(&cmd)?;
-		Ok(res.trim().parse::<f32>().map_err(|_| err("Unable to parse voltage response as a float"))?)
+		res.trim().parse::<f32>().map_err(|_| err("Unable to parse voltage response as a float"))
 	}
 
 	pub fn get_current(&mut self, ch:u8) -> io::Result<f32> {
@@ -119,7 +121,7 @@ impl SPD3303X {
 
 	    let cmd:String   = format!("CH{}:CURR?", ch);
 	    let res:String   = self.ask_str(&cmd)?;
-		Ok(res.trim().parse::<f32>().map_err(|_| err("Unable to parse current response as a float"))?)
+		res.trim().parse::<f32>().map_err(|_| err("Unable to parse current response as a float"))
 	}
 
 	pub fn get_operating_channel(&mut self) -> io::Result<u8> {
@@ -135,7 +137,7 @@ impl SPD3303X {
 
 	    let cmd:String   = format!("MEAS:CURR? CH{}", ch);
 	    let res:String   = self.ask_str(&cmd)?;
-		Ok(res.trim().parse::<f32>().map_err(|_| err("Unable to parse current response as a float"))?)
+		res.trim().parse::<f32>().map_err(|_| err("Unable to parse current response as a float"))
 	}
 
 	pub fn set_voltage(&mut self, ch:u8, voltage:f32) -> io::Result<()> {
@@ -194,23 +196,17 @@ impl SPD3303X {
 
 	pub fn ask(&mut self, data:&[u8]) -> io::Result<Vec<u8>> { 
 		thread::sleep(self.tx_throttle_duration);
-		self.core.ask(data) 
+		Ok(self.core.ask(data)?)
 	}
 
 	pub fn ask_str(&mut self, data:&str) -> io::Result<String> {
 		str::from_utf8(&self.core.ask(data.as_bytes())?)
 			.map(|s| s.to_owned())
-			.map_err(|_| Error::new(ErrorKind::Other, "Unable to parse response as UTF-8"))
+			.map_err(|_| Error::other("Unable to parse response as UTF-8"))
 	}
 
 }
 
-impl Drop for SPD3303X {
-
-	fn drop(&mut self) { self.core.destroy_link().expect("Unable to destroy link for SPD3303X"); }
-
-}
-
 // Not Yet Implemented
 
 // Partially implemented
@@ -0,0 +1,218 @@
+use std::fmt;
+use std::io;
+
+use crate::rpc::RpcError;
+use crate::xdr::XdrError;
+
+// Error codes an instrument can return in the error field of a Device Core reply, from the table in the VXI-11
+// specification
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DeviceErrorCode {
+	SyntaxError,
+	DeviceNotAccessible,
+	InvalidLinkIdentifier,
+	ParameterError,
+	ChannelNotEstablished,
+	OperationNotSupported,
+	OutOfResources,
+	DeviceLockedByAnotherLink,
+	NoLockHeldByThisLink,
+	IoTimeout,
+	IoError,
+	InvalidAddress,
+	Abort,
+	ChannelAlreadyEstablished,
+	Unknown(i32),
+}
+
+impl DeviceErrorCode {
+
+	// None for 0, which means no error
+	pub fn from_code(code:i32) -> Option<Self> {
+		Some(match code {
+			0  => return None,
+			1  => DeviceErrorCode::SyntaxError,
+			3  => DeviceErrorCode::DeviceNotAccessible,
+			4  => DeviceErrorCode::InvalidLinkIdentifier,
+			5  => DeviceErrorCode::ParameterError,
+			6  => DeviceErrorCode::ChannelNotEstablished,
+			8  => DeviceErrorCode::OperationNotSupported,
+			9  => DeviceErrorCode::OutOfResources,
+			11 => DeviceErrorCode::DeviceLockedByAnotherLink,
+			12 => DeviceErrorCode::NoLockHeldByThisLink,
+			15 => DeviceErrorCode::IoTimeout,
+			17 => DeviceErrorCode::IoError,
+			21 => DeviceErrorCode::InvalidAddress,
+			23 => DeviceErrorCode::Abort,
+			29 => DeviceErrorCode::ChannelAlreadyEstablished,
+			n  => DeviceErrorCode::Unknown(n),
+		})
+	}
+
+	pub fn to_i32(&self) -> i32 { match self {
+		DeviceErrorCode::SyntaxError               => 1,
+		DeviceErrorCode::DeviceNotAccessible       => 3,
+		DeviceErrorCode::InvalidLinkIdentifier     => 4,
+		DeviceErrorCode::ParameterError            => 5,
+		DeviceErrorCode::ChannelNotEstablished     => 6,
+		DeviceErrorCode::OperationNotSupported     => 8,
+		DeviceErrorCode::OutOfResources            => 9,
+		DeviceErrorCode::DeviceLockedByAnotherLink => 11,
+		DeviceErrorCode::NoLockHeldByThisLink      => 12,
+		DeviceErrorCode::IoTimeout                 => 15,
+		DeviceErrorCode::IoError                   => 17,
+		DeviceErrorCode::InvalidAddress            => 21,
+		DeviceErrorCode::Abort                     => 23,
+		DeviceErrorCode::ChannelAlreadyEstablished => 29,
+		DeviceErrorCode::Unknown(n)                => *n,
+	}}
+}
+
+impl fmt::Display for DeviceErrorCode {
+	fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+		match self {
+			DeviceErrorCode::SyntaxError               => write!(f, "Syntax error"),
+			DeviceErrorCode::DeviceNotAccessible       => write!(f, "Device not accessible"),
+			DeviceErrorCode::InvalidLinkIdentifier     => write!(f, "Invalid link identifier"),
+			DeviceErrorCode::ParameterError            => write!(f, "Parameter error"),
+			DeviceErrorCode::ChannelNotEstablished     => write!(f, "Channel not established"),
+			DeviceErrorCode::OperationNotSupported     => write!(f, "Operation not supported"),
+			DeviceErrorCode::OutOfResources            => write!(f, "Out of resources"),
+			DeviceErrorCode::DeviceLockedByAnotherLink => write!(f, "Device locked by another link"),
+			DeviceErrorCode::NoLockHeldByThisLink      => write!(f, "No lock held by this link"),
+			DeviceErrorCode::IoTimeout                 => write!(f, "I/O timeout"),
+			DeviceErrorCode::IoError                   => write!(f, "I/O error"),
+			DeviceErrorCode::InvalidAddress            => write!(f, "Invalid address"),
+			DeviceErrorCode::Abort                     => write!(f, "Abort"),
+			DeviceErrorCode::ChannelAlreadyEstablished => write!(f, "Channel already established"),
+			DeviceErrorCode::Unknown(n)                => write!(f, "Unknown error code {}", n),
+		}
+	}
+}
+
+// Everything that can go wrong talking to an instrument, split up so callers can tell, for example, a lock
+// conflict (worth retrying) from a dropped connection (not worth retrying)
+#[derive(Debug)]
+pub enum Error {
+	Io(io::Error),               // The connection failed or timed out
+	Xdr(XdrError),               // A message couldn't be packed or unpacked
+	Rpc(RpcError),               // The server rejected the call
+	Device(DeviceErrorCode),     // The instrument carried out the call and reported an error
+	Protocol(String),            // A reply that made no sense, or a call made in the wrong state
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+	pub fn device_code(&self) -> Option<DeviceErrorCode> {
+		match self {
+			Error::Device(code) => Some(*code),
+			_                   => None,
+		}
+	}
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Error::Io(e)       => write!(f, "{}", e),
+			Error::Xdr(e)      => write!(f, "{}", e),
+			Error::Rpc(e)      => write!(f, "{}", e),
+			Error::Device(e)   => write!(f, "{}", e),
+			Error::Protocol(s) => write!(f, "{}", s),
+		}
+	}
+}
+
+impl std::error::Error for Error {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			Error::Io(e)  => Some(e),
+			Error::Xdr(e) => Some(e),
+			Error::Rpc(e) => Some(e),
+			_             => None,
+		}
+	}
+}
+
+// The RPC layer reports RpcErrors and XdrErrors inside io::Errors, so those get taken back out
+impl From<io::Error> for Error {
+	fn from(e:io::Error) -> Self {
+		let is = |e:&io::Error, f:fn(&(dyn std::error::Error + Send + Sync + 'static)) -> bool| e.get_ref().is_some_and(f);
+
+		if is(&e, |inner| inner.is::<RpcError>()) {
+			Error::Rpc(*e.into_inner().unwrap().downcast::<RpcError>().unwrap())
+		} else if is(&e, |inner| inner.is::<XdrError>()) {
+			Error::Xdr(*e.into_inner().unwrap().downcast::<XdrError>().unwrap())
+		} else if is(&e, |inner| inner.is::<Error>()) {
+			*e.into_inner().unwrap().downcast::<Error>().unwrap()
+		} else {
+			Error::Io(e)
+		}
+	}
+}
+
+impl From<XdrError> for Error {
+	fn from(e:XdrError) -> Self { Error::Xdr(e) }
+}
+
+impl From<RpcError> for Error {
+	fn from(e:RpcError) -> Self { Error::Rpc(e) }
+}
+
+impl From<DeviceErrorCode> for Error {
+	fn from(code:DeviceErrorCode) -> Self { Error::Device(code) }
+}
+
+// For code that works in io::Result.  The Error can be recovered with get_ref and downcast_ref, except that io and
+// RPC errors go back to the form the RPC layer uses.
+impl From<Error> for io::Error {
+	fn from(e:Error) -> Self {
+		match e {
+			Error::Io(e)  => e,
+			Error::Xdr(e) => e.into(),
+			Error::Rpc(e) => e.into(),
+			Error::Device(DeviceErrorCode::IoTimeout) => io::Error::new(io::ErrorKind::TimedOut, e),
+			Error::Device(_)   => io::Error::other(e),
+			Error::Protocol(_) => io::Error::new(io::ErrorKind::InvalidData, e),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn round_trip(e:Error) -> Error { Error::from(io::Error::from(e)) }
+
+	#[test]
+	fn codes_round_trip() {
+		for code in 0..40 {
+			match DeviceErrorCode::from_code(code) {
+				None    => assert_eq!(code, 0),
+				Some(c) => assert_eq!(c.to_i32(), code),
+			}
+		}
+		assert_eq!(DeviceErrorCode::from_code(2), Some(DeviceErrorCode::Unknown(2)));
+	}
+
+	#[test]
+	fn device_errors_survive_io_error() {
+		for code in [DeviceErrorCode::DeviceLockedByAnotherLink, DeviceErrorCode::IoTimeout, DeviceErrorCode::Abort, DeviceErrorCode::Unknown(100)] {
+			let e = io::Error::from(Error::Device(code));
+			assert_eq!(e.get_ref().and_then(|inner| inner.downcast_ref::<Error>()).and_then(Error::device_code), Some(code));
+			assert_eq!(e.kind() == io::ErrorKind::TimedOut, code == DeviceErrorCode::IoTimeout);
+			assert_eq!(Error::from(e).device_code(), Some(code));
+		}
+	}
+
+	#[test]
+	fn other_errors_survive_io_error() {
+		assert!(matches!(round_trip(Error::Rpc(RpcError::ProcUnavail)), Error::Rpc(RpcError::ProcUnavail)));
+		assert!(matches!(round_trip(Error::Xdr(XdrError::InvalidBool{ offset: 4, value: 2 })), Error::Xdr(XdrError::InvalidBool{ offset: 4, value: 2 })));
+		assert!(matches!(round_trip(Error::Protocol("odd reply".to_owned())), Error::Protocol(ref s) if s == "odd reply"));
+
+		let e = round_trip(Error::Io(io::Error::new(io::ErrorKind::ConnectionReset, "reset")));
+		assert!(matches!(e, Error::Io(ref e) if e.kind() == io::ErrorKind::ConnectionReset));
+	}
+}
@@ -8,6 +8,13 @@ pub mod xdr;
 // Remote procedure call, a protocol build on top of XDR to provide something like C-style function calls over the network
 pub mod rpc;
 
+// Generates Rust types and RPC clients from rpcgen-style .x interface definitions
+pub mod rpcgen;
+
+// Errors from the VXI-11 layer, including the error codes instruments send back
+pub mod error;
+pub use error::{Error, Result};
+
 // A protocol using RPC that's meant to communicate with instruments like oscilloscopes, power supplies, waveform generators, etc
 pub mod vxi11;
 
@@ -0,0 +1,125 @@
+
+use std::future::Future;
+use std::io::{self, Error, ErrorKind};
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, ToSocketAddrs};
+
+use super::client::RpcClient;
+use super::record::RecordCodec;
+use super::transport::{reply_xid, is_stale_xid};
+
+// Total time to wait for a reply by default, the same as the blocking UDP client
+pub const DEFAULT_TIMEOUT:Duration = Duration::from_secs(25);
+
+const READ_CHUNK_SIZE:usize = 64 * 1024;
+
+// Record-marked TCP for async clients.  Partly sent calls and partly received replies are kept in the transport
+// rather than in the futures, so a call can be dropped at any await point (by a timeout or a select!) without
+// leaving the connection out of step.  The next call finishes sending the abandoned one first and skips its reply.
+pub struct AsyncTcpTransport {
+	pub stream: TcpStream,
+	pub codec: RecordCodec,
+	pub timeout: Option<Duration>,     // Applies to each call separately, None to wait forever
+	send_buff: Vec<u8>,
+	send_pos: usize,
+	recv_buff: Vec<u8>,
+	unanswered: Option<u32>,           // xid of the last call sent, until its reply is read
+}
+
+// Gives up on a call after the transport's timeout
+async fn within<T, F: Future<Output=io::Result<T>>>(timeout:Option<Duration>, f:F) -> io::Result<T> {
+	match timeout {
+		Some(t) => tokio::time::timeout(t, f).await
+			.map_err(|_| Error::new(ErrorKind::TimedOut, "No reply before the call timed out"))?,
+		None => f.await,
+	}
+}
+
+impl AsyncTcpTransport {
+
+	pub async fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+		let stream = TcpStream::connect(addr).await?;
+		Ok(Self{ stream, codec: RecordCodec::default(), timeout: Some(DEFAULT_TIMEOUT), send_buff: vec![], send_pos: 0, recv_buff: vec![], unanswered: None })
+	}
+
+	async fn flush(&mut self) -> io::Result<()> {
+		while self.send_pos < self.send_buff.len() {
+			let n:usize = self.stream.write(&self.send_buff[self.send_pos..]).await?;
+			if n == 0 { return Err(Error::new(ErrorKind::WriteZero, "Connection closed while sending call")); }
+			self.send_pos += n;
+		}
+		Ok(())
+	}
+
+	async fn read_record(&mut self) -> io::Result<Vec<u8>> {
+		loop {
+			if let Some((record, used)) = self.codec.decode(&self.recv_buff)? {
+				self.recv_buff.drain(..used);
+				return Ok(record);
+			}
+
+			// read_buf only appends what it actually read, so nothing is lost or half-written if this is dropped
+			self.recv_buff.reserve(READ_CHUNK_SIZE);
+			if self.stream.read_buf(&mut self.recv_buff).await? == 0 {
+				return Err(Error::new(ErrorKind::UnexpectedEof, "Connection closed while waiting for reply"));
+			}
+		}
+	}
+
+	async fn exchange(&mut self, xid:u32, msg:&[u8]) -> io::Result<Vec<u8>> {
+		// Finish whatever a cancelled call left half sent before starting on this one
+		self.flush().await?;
+		self.send_buff = self.codec.encode(msg);
+		self.send_pos = 0;
+		self.unanswered = Some(xid);
+		self.receive(xid).await
+	}
+
+	async fn receive(&mut self, xid:u32) -> io::Result<Vec<u8>> {
+		self.flush().await?;
+		loop {
+			let reply:Vec<u8> = self.read_record().await?;
+			match reply_xid(&reply) {
+				Some(reply_xid) if reply_xid == xid => {
+					self.unanswered = None;
+					return Ok(reply);
+				},
+				Some(reply_xid) if is_stale_xid(reply_xid, xid) => continue,     // Reply to a call that timed out or was cancelled
+				Some(_) => return Err(Error::other("Somehow got a packet from the future")),
+				None    => return Err(Error::new(ErrorKind::InvalidData, "Reply too short to hold an xid")),
+			}
+		}
+	}
+
+	// Sends a complete call message and returns the reply with the same xid
+	pub async fn call(&mut self, xid:u32, msg:&[u8]) -> io::Result<Vec<u8>> {
+		within(self.timeout, self.exchange(xid, msg)).await
+	}
+
+	// The xid of a call that went out but was dropped or timed out before its reply came back
+	pub fn unanswered(&self) -> Option<u32> { self.unanswered }
+
+	// Waits for the reply to that call, for callers that need to know what it did
+	pub async fn reply_to(&mut self, xid:u32) -> io::Result<Vec<u8>> {
+		within(self.timeout, self.receive(xid)).await
+	}
+}
+
+pub type AsyncTcpClient = RpcClient<AsyncTcpTransport>;
+
+impl RpcClient<AsyncTcpTransport> {
+
+	pub async fn connect<A: ToSocketAddrs>(addr: A, prog: u32, vers: u32) -> io::Result<Self> {
+		Ok(Self::new(AsyncTcpTransport::connect(addr).await?, prog, vers))
+	}
+
+	// Sends the call and leaves the results of a successful reply in the unpacker
+	pub async fn do_call(&mut self) -> io::Result<()> {
+		loop {
+			let reply:Vec<u8> = self.transport.call(self.lastxid, &self.packer.buff).await?;
+			if self.handle_reply(reply)? { return Ok(()); }
+		}
+	}
+}
@@ -0,0 +1,231 @@
+
+use std::io::{self, Error, ErrorKind};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Serialize, Deserialize};
+
+use crate::xdr;
+use super::reply::{OpaqueAuth, ReplyBody, RejectedReply};
+
+// Authentication flavors from RFC 5531 section 8.2
+pub const AUTH_NONE:i32  = 0;
+pub const AUTH_SYS:i32   = 1;
+pub const AUTH_SHORT:i32 = 2;
+
+pub const AUTH_SYS_MAX_MACHINENAME:u32 = 255;
+pub const AUTH_SYS_MAX_GIDS:u32        = 16;
+
+// Supplies the credential and verifier for each call a client makes and checks the verifier in each reply
+pub trait Auth: Send {
+	fn credential(&self) -> OpaqueAuth;
+
+	fn verifier(&self) -> OpaqueAuth { OpaqueAuth::none() }
+
+	// Called with the verifier of every accepted reply.  Returning an error fails the call.
+	fn validate(&mut self, _verf:&OpaqueAuth) -> io::Result<()> { Ok(()) }
+
+	// Called when the server rejects the credential.  Returning true means the credential changed and the call
+	// should be sent again.
+	fn refresh(&mut self) -> bool { false }
+}
+
+pub struct AuthNone;
+
+impl Auth for AuthNone {
+	fn credential(&self) -> OpaqueAuth { OpaqueAuth::none() }
+}
+
+// The body of an AUTH_SYS credential
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuthSysParms {
+	pub stamp: u32,
+	pub machinename: String,
+	pub uid: u32,
+	pub gid: u32,
+	pub gids: Vec<u32>,
+}
+
+impl AuthSysParms {
+
+	pub fn to_opaque(&self) -> xdr::Result<OpaqueAuth> {
+		let mut packer = xdr::Packer::new();
+		packer.pack_u32(self.stamp)?;
+		packer.pack_string(&self.machinename, AUTH_SYS_MAX_MACHINENAME)?;
+		packer.pack_u32(self.uid)?;
+		packer.pack_u32(self.gid)?;
+		packer.pack_variable_array(&self.gids, AUTH_SYS_MAX_GIDS, |p, gid| p.pack_u32(*gid))?;
+		Ok(OpaqueAuth{ flavor: AUTH_SYS, body: packer.buff })
+	}
+
+	pub fn from_opaque(auth:&OpaqueAuth) -> io::Result<Self> {
+		if auth.flavor != AUTH_SYS { return Err(Error::new(ErrorKind::InvalidData, "Not an AUTH_SYS credential")); }
+
+		let mut unpacker = xdr::Unpacker::new();
+		unpacker.reset(&auth.body);
+		let stamp:u32           = unpacker.unpack_u32()?;
+		let machinename:String  = unpacker.unpack_string(AUTH_SYS_MAX_MACHINENAME)?;
+		let uid:u32             = unpacker.unpack_u32()?;
+		let gid:u32             = unpacker.unpack_u32()?;
+		let gids:Vec<u32>       = unpacker.unpack_variable_array(AUTH_SYS_MAX_GIDS, |u| u.unpack_u32())?;
+		Ok(Self{ stamp, machinename, uid, gid, gids })
+	}
+}
+
+// AUTH_SYS (also known as AUTH_UNIX).  If the server hands back an AUTH_SHORT verifier, its body is used as the
+// credential for later calls until the server rejects it, at which point the full credential is sent again.
+pub struct AuthSys {
+	cred: OpaqueAuth,
+	short: Option<OpaqueAuth>,
+}
+
+impl AuthSys {
+
+	pub fn new(machinename:&str, uid:u32, gid:u32, gids:&[u32]) -> io::Result<Self> {
+		let stamp:u32 = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as u32).unwrap_or(0);
+		Self::from_parms(&AuthSysParms{ stamp, machinename: machinename.to_owned(), uid, gid, gids: gids.to_vec() })
+	}
+
+	pub fn from_parms(parms:&AuthSysParms) -> io::Result<Self> {
+		Ok(Self{ cred: parms.to_opaque()?, short: None })
+	}
+
+	pub fn parms(&self) -> io::Result<AuthSysParms> { AuthSysParms::from_opaque(&self.cred) }
+}
+
+impl Auth for AuthSys {
+
+	fn credential(&self) -> OpaqueAuth {
+		self.short.clone().unwrap_or_else(|| self.cred.clone())
+	}
+
+	fn validate(&mut self, verf:&OpaqueAuth) -> io::Result<()> {
+		if verf.flavor == AUTH_SHORT {
+			self.short = Some(OpaqueAuth{ flavor: AUTH_SHORT, body: verf.body.clone() });
+		}
+		Ok(())
+	}
+
+	fn refresh(&mut self) -> bool { self.short.take().is_some() }
+}
+
+// Shared by the clients once a reply with the right xid arrives.  Returns the verifier of a successful reply, or
+// None if the credential was refreshed and the call should be sent again.
+pub(crate) fn check_reply(auth:&mut dyn Auth, body:ReplyBody) -> io::Result<Option<OpaqueAuth>> {
+	if let ReplyBody::Denied(RejectedReply::AuthError(_)) = body {
+		if auth.refresh() { return Ok(None); }
+	}
+
+	let verf:OpaqueAuth = body.into_result()?;
+	auth.validate(&verf)?;
+	Ok(Some(verf))
+}
+
+#[cfg(test)]
+mod tests {
+	use std::thread;
+
+	use crate::xdr::{Packer, Unpacker};
+	use crate::rpc::{xdr_pack, xdr_unpack, RpcError};
+	use crate::rpc::client::RpcClient;
+	use crate::rpc::reply::{AcceptedReply, AuthStat};
+	use crate::rpc::transport::{ChannelTransport, ChannelPeer};
+	use super::*;
+
+	fn words(ws:&[u32]) -> Vec<u8> { ws.iter().flat_map(|w| w.to_be_bytes()).collect() }
+
+	// The xid and credential of a call message
+	fn call_cred(call:&[u8]) -> (u32, OpaqueAuth) {
+		let mut unpacker = Unpacker::new();
+		unpacker.reset(call);
+		let xid:u32 = unpacker.unpack_u32().unwrap();
+		for _ in 0..5 { unpacker.unpack_u32().unwrap(); }
+		(xid, xdr_unpack::unpack_auth(&mut unpacker).unwrap())
+	}
+
+	fn reply(xid:u32, body:ReplyBody) -> Vec<u8> {
+		let mut packer = Packer::new();
+		xdr_pack::pack_reply(&mut packer, xid, &body).unwrap();
+		packer.buff
+	}
+
+	fn parms() -> AuthSysParms {
+		AuthSysParms{ stamp: 0x1234, machinename: "bench".to_owned(), uid: 1000, gid: 100, gids: vec![100, 20] }
+	}
+
+	#[test]
+	fn auth_none_sends_empty_credentials() {
+		let (transport, _peer) = ChannelTransport::pair();
+		let mut client = RpcClient::new(transport, 1, 1);
+		client.start_call(0).unwrap();
+		assert_eq!(&client.packer.buff[24..], &words(&[AUTH_NONE as u32, 0, AUTH_NONE as u32, 0])[..]);
+		assert_eq!(AuthNone.credential(), OpaqueAuth::none());
+	}
+
+	#[test]
+	fn auth_sys_credential_encoding() {
+		let cred:OpaqueAuth = parms().to_opaque().unwrap();
+		assert_eq!(cred.flavor, AUTH_SYS);
+		let expected:Vec<u8> = [words(&[0x1234, 5]), b"bench\0\0\0".to_vec(), words(&[1000, 100, 2, 100, 20])].concat();
+		assert_eq!(cred.body, expected);
+
+		assert_eq!(AuthSysParms::from_opaque(&cred).unwrap(), parms());
+		assert_eq!(AuthSys::from_parms(&parms()).unwrap().parms().unwrap(), parms());
+		assert!(AuthSysParms::from_opaque(&OpaqueAuth::none()).is_err());
+	}
+
+	#[test]
+	fn auth_sys_bounds() {
+		let long_name = AuthSysParms{ machinename: "x".repeat(AUTH_SYS_MAX_MACHINENAME as usize + 1), ..parms() };
+		assert!(long_name.to_opaque().is_err());
+
+		let many_gids = AuthSysParms{ gids: vec![0; AUTH_SYS_MAX_GIDS as usize + 1], ..parms() };
+		assert!(many_gids.to_opaque().is_err());
+		assert!(AuthSysParms{ gids: vec![0; AUTH_SYS_MAX_GIDS as usize], ..parms() }.to_opaque().is_ok());
+	}
+
+	#[test]
+	fn auth_sys_switches_to_auth_short_and_back_when_rejected() {
+		let short = OpaqueAuth{ flavor: AUTH_SHORT, body: vec![7; 8] };
+		let (transport, peer) = ChannelTransport::pair();
+
+		let expected_short:OpaqueAuth = short.clone();
+		let server = thread::spawn(move || {
+			let ChannelPeer{ calls, replies } = peer;
+			let full:OpaqueAuth = parms().to_opaque().unwrap();
+
+			// The first call gets a short hand credential back in the verifier
+			let (xid, cred) = call_cred(&calls.recv().unwrap());
+			assert_eq!(cred, full);
+			replies.send(reply(xid, ReplyBody::Accepted{ verf: expected_short.clone(), reply: AcceptedReply::Success })).unwrap();
+
+			// Which the second call uses until the server forgets it
+			let (xid, cred) = call_cred(&calls.recv().unwrap());
+			assert_eq!(cred, expected_short);
+			replies.send(reply(xid, ReplyBody::Denied(RejectedReply::AuthError(AuthStat::RejectedCred)))).unwrap();
+
+			// So the same call goes again with the full credential
+			let (retry_xid, cred) = call_cred(&calls.recv().unwrap());
+			assert_ne!(retry_xid, xid);
+			assert_eq!(cred, full);
+			replies.send(reply(retry_xid, ReplyBody::success(OpaqueAuth::none()))).unwrap();
+
+			// The full credential being rejected is an error
+			let (xid, cred) = call_cred(&calls.recv().unwrap());
+			assert_eq!(cred, full);
+			replies.send(reply(xid, ReplyBody::Denied(RejectedReply::AuthError(AuthStat::BadCred)))).unwrap();
+		});
+
+		let mut client = RpcClient::new(transport, 1, 1);
+		client.set_auth(AuthSys::from_parms(&parms()).unwrap());
+		for _ in 0..2 {
+			client.start_call(1).unwrap();
+			client.do_call().unwrap();
+		}
+		assert_eq!(client.verf, Some(OpaqueAuth::none()));
+
+		client.start_call(1).unwrap();
+		let e = client.do_call().unwrap_err();
+		assert_eq!(e.get_ref().unwrap().downcast_ref::<RpcError>(), Some(&RpcError::AuthError(AuthStat::BadCred)));
+		server.join().unwrap();
+	}
+}
@@ -0,0 +1,85 @@
+
+use std::io::{self, Error, ErrorKind};
+
+use crate::xdr;
+use super::{xdr_pack, xdr_unpack};
+use super::auth::{self, Auth, AuthNone};
+use super::reply::OpaqueAuth;
+use super::transport::RpcTransport;
+
+// Client for one program version, over any transport.  Transports that don't implement RpcTransport (like the async
+// ones) provide their own do_call on top of start_call and handle_reply.
+pub struct RpcClient<T> {
+	pub transport: T,
+    pub prog: u32,
+    pub vers: u32,
+    pub lastxid: u32,
+	pub packer: xdr::Packer,
+	pub unpacker: xdr::Unpacker,
+	pub auth: Box<dyn Auth>,
+	pub verf: Option<OpaqueAuth>,      // Verifier from the last successful reply
+	prc: u32,
+	args_start: usize,
+}
+
+impl<T> RpcClient<T> {
+
+	pub fn new(transport:T, prog:u32, vers:u32) -> Self {
+		let packer = xdr::Packer::new();
+		let unpacker = xdr::Unpacker::new();
+		Self{ transport, prog, vers, lastxid: 0, packer, unpacker, auth: Box::new(AuthNone), verf: None, prc: 0, args_start: 0 }
+	}
+
+	pub fn set_auth<A: Auth + 'static>(&mut self, auth:A) {
+		self.auth = Box::new(auth);
+	}
+
+	// Packs the call header for procedure prc, after which the arguments get packed and do_call is called
+	pub fn start_call(&mut self, prc:u32) -> io::Result<()> {
+		self.lastxid = self.lastxid.wrapping_add(1);
+		self.packer.reset();
+		xdr_pack::pack_callheader(&mut self.packer, self.lastxid, self.prog, self.vers, prc, &self.auth.credential(), &self.auth.verifier())?;
+		self.prc = prc;
+		self.args_start = self.packer.buff.len();
+		Ok(())
+	}
+
+	// Packs the current call again with a new xid and credential, keeping the arguments
+	fn restart_call(&mut self) -> io::Result<()> {
+		let args:Vec<u8> = self.packer.buff.split_off(self.args_start);
+		self.start_call(self.prc)?;
+		self.packer.buff.extend_from_slice(&args);
+		Ok(())
+	}
+
+	// Checks the reply to the current call.  Returns true with the results left in the unpacker, or false if the call
+	// has been packed again with fresh credentials and needs to be sent again.
+	pub fn handle_reply(&mut self, reply:Vec<u8>) -> io::Result<bool> {
+		self.unpacker.reset_owned(reply);
+
+		let (xid, body) = xdr_unpack::unpack_reply(&mut self.unpacker)?;
+		if xid != self.lastxid { return Err(Error::new(ErrorKind::InvalidData, "Transport returned the reply to a different call")); }
+
+		match auth::check_reply(self.auth.as_mut(), body)? {
+			Some(verf) => {
+				self.verf = Some(verf);
+				Ok(true)
+			},
+			None => {
+				self.restart_call()?;
+				Ok(false)
+			},
+		}
+	}
+}
+
+impl<T: RpcTransport> RpcClient<T> {
+
+	// Sends the call and leaves the results of a successful reply in the unpacker
+	pub fn do_call(&mut self) -> io::Result<()> {
+		loop {
+			let reply:Vec<u8> = self.transport.call(self.lastxid, &self.packer.buff)?;
+			if self.handle_reply(reply)? { return Ok(()); }
+		}
+	}
+}
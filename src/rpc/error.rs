@@ -0,0 +1,53 @@
+
+use std::fmt;
+use std::io;
+
+use super::reply::AuthStat;
+
+// Everything a server can send back instead of a successful result, along with whatever detail it included
+#[derive(Debug, Clone, PartialEq)]
+pub enum RpcError {
+	NotAReply{ mtype:i32 },
+	RpcMismatch{ low:u32, high:u32 },
+	AuthError(AuthStat),
+	ProgUnavail,
+	ProgMismatch{ low:u32, high:u32 },
+	ProcUnavail,
+	GarbageArgs,
+	SystemErr,
+}
+
+impl fmt::Display for RpcError {
+	fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+		match self {
+			RpcError::NotAReply{ mtype } =>
+				write!(f, "Expected a REPLY message but got message type {}", mtype),
+			RpcError::RpcMismatch{ low, high } =>
+				write!(f, "Message denied, server supports RPC versions {} to {}", low, high),
+			RpcError::AuthError(stat) =>
+				write!(f, "Message denied due to authentication error {:?}", stat),
+			RpcError::ProgUnavail =>
+				write!(f, "Program unavailable"),
+			RpcError::ProgMismatch{ low, high } =>
+				write!(f, "Program mismatch, server supports versions {} to {}", low, high),
+			RpcError::ProcUnavail =>
+				write!(f, "Procedure unavailable"),
+			RpcError::GarbageArgs =>
+				write!(f, "Server couldn't decode the arguments"),
+			RpcError::SystemErr =>
+				write!(f, "Server-side system error"),
+		}
+	}
+}
+
+impl std::error::Error for RpcError {}
+
+// The RpcError can be recovered from the io::Error with get_ref and downcast_ref
+impl From<RpcError> for io::Error {
+	fn from(e:RpcError) -> Self {
+		match e {
+			RpcError::NotAReply{ .. } => io::Error::new(io::ErrorKind::InvalidData, e),
+			_                         => io::Error::other(e),
+		}
+	}
+}
@@ -15,16 +15,38 @@ pub const PROG_UNAVAIL:i32  = 1;      // remote hasn't exported program
 pub const PROG_MISMATCH:i32 = 2;      // remote can't support version #
 pub const PROC_UNAVAIL:i32  = 3;      // program can't support procedure
 pub const GARBAGE_ARGS:i32  = 4;      // procedure can't decode params
+pub const SYSTEM_ERR:i32    = 5;      // errors like memory allocation failure
 
 pub const IPPROTO_TCP:u32 = 6;
 pub const IPPROTO_UDP:u32 = 17;
 
+use std::sync::{Mutex, MutexGuard};
+
+pub mod error;
+pub use self::error::RpcError;
+
+pub mod reply;
+pub mod auth;
+
 pub mod xdr_unpack;
 pub mod xdr_pack;
 
 pub mod port_mapping;
+pub mod rpcbind;
 
-// TODO: implement server functionality
-
+pub mod transport;
+pub mod client;
+pub mod record;
 pub mod tcp_clients;
 pub mod udp_clients;
+pub mod pipelined_clients;
+#[cfg(feature = "async")]
+pub mod async_clients;
+
+pub mod server;
+
+// For locks that never leave what they guard half updated, so a panic on one thread while holding the lock shouldn't
+// stop every other thread using it
+pub(crate) fn lock_ignoring_poison<T: ?Sized>(mutex:&Mutex<T>) -> MutexGuard<'_, T> {
+	mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
@@ -0,0 +1,338 @@
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Error, ErrorKind};
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::mpsc::{self, Sender, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+use crate::xdr::{Packer, Unpacker};
+use super::{xdr_pack, xdr_unpack, lock_ignoring_poison};
+use super::auth::{self, Auth, AuthNone};
+use super::record::RecordCodec;
+use super::reply::OpaqueAuth;
+use super::tcp_clients::TcpTransport;
+use super::transport::reply_xid;
+
+// Orphaned replies kept around for take_orphans, oldest dropped first
+pub const MAX_ORPHANS:usize = 64;
+
+// None once the reader thread has stopped, so no more calls can be registered
+type Pending = Arc<Mutex<Option<HashMap<u32, Sender<Vec<u8>>>>>>;
+type Orphans = Arc<Mutex<VecDeque<(u32, Vec<u8>)>>>;
+
+struct Shared {
+	stream: Mutex<TcpStream>,
+	codec: RecordCodec,
+	prog: u32,
+	vers: u32,
+	lastxid: AtomicU32,
+	auth: Mutex<Box<dyn Auth>>,
+	pending: Pending,
+	orphans: Orphans,
+}
+
+// TCP client that keeps any number of calls in flight on one connection.  A reader thread hands each reply to
+// whichever PendingCall has its xid, in whatever order the server answers.  Replies nobody is waiting for (because
+// the call was dropped or timed out, or the server made up an xid) are kept as orphans.
+pub struct PipelinedClient {
+	shared: Arc<Shared>,
+}
+
+// Handle for one call in flight
+pub struct PendingCall {
+	shared: Arc<Shared>,
+	xid: u32,
+	prc: u32,
+	args: Vec<u8>,
+	rx: Receiver<Vec<u8>>,
+}
+
+impl Shared {
+
+	// Packs and sends a call, registering the xid before sending so the reply can't beat us to the map
+	fn send_call(&self, prc:u32, args:&[u8]) -> io::Result<(u32, Receiver<Vec<u8>>)> {
+		let xid:u32 = self.lastxid.fetch_add(1, Ordering::Relaxed).wrapping_add(1);
+
+		let mut packer = Packer::new();
+		{
+			let auth = lock_ignoring_poison(&self.auth);
+			xdr_pack::pack_callheader(&mut packer, xid, self.prog, self.vers, prc, &auth.credential(), &auth.verifier())?;
+		}
+		packer.buff.extend_from_slice(args);
+
+		let (tx, rx) = mpsc::channel();
+		match lock_ignoring_poison(&self.pending).as_mut() {
+			Some(pending) => { pending.insert(xid, tx); },
+			None          => return Err(Error::new(ErrorKind::ConnectionAborted, "Connection closed")),
+		}
+
+		let mut stream = lock_ignoring_poison(&self.stream);
+		if let Err(e) = self.codec.write_record(&mut *stream, &packer.buff) {
+			// Part of the record may have gone out, so nothing sent after it would be understood.  Shutting the
+			// connection down stops the reader, which fails every other call too.
+			remove_pending(&self.pending, xid);
+			let _ = stream.shutdown(Shutdown::Both);
+			return Err(e);
+		}
+		Ok((xid, rx))
+	}
+}
+
+fn remove_pending(pending:&Pending, xid:u32) -> Option<Sender<Vec<u8>>> {
+	lock_ignoring_poison(pending).as_mut().and_then(|pending| pending.remove(&xid))
+}
+
+fn read_replies(mut stream:TcpStream, codec:RecordCodec, pending:Pending, orphans:Orphans) {
+	// Any error ends the connection, and dropping the senders wakes every waiting call
+	while let Ok(reply) = codec.read_record(&mut stream) {
+		let xid:u32 = match reply_xid(&reply) {
+			Some(xid) => xid,
+			None      => break,
+		};
+
+		let waiting:Option<Sender<Vec<u8>>> = remove_pending(&pending, xid);
+		let unclaimed:Option<Vec<u8>> = match waiting {
+			Some(tx) => tx.send(reply).err().map(|e| e.0),
+			None     => Some(reply),
+		};
+
+		if let Some(reply) = unclaimed {
+			let mut orphans = lock_ignoring_poison(&orphans);
+			if orphans.len() == MAX_ORPHANS { orphans.pop_front(); }
+			orphans.push_back((xid, reply));
+		}
+	}
+	*lock_ignoring_poison(&pending) = None;
+}
+
+impl PipelinedClient {
+
+	pub fn connect<A: ToSocketAddrs>(addr: A, prog: u32, vers: u32) -> io::Result<Self> {
+		Self::with_stream(TcpStream::connect(addr)?, prog, vers)
+	}
+
+	// Gives up on connecting after connect_timeout and on sending a call after write_timeout, for example the ones in
+	// vxi11::LinkOptions.  How long to wait for each reply is up to the PendingCall.
+	pub fn connect_timeout<A: ToSocketAddrs>(addr: A, prog: u32, vers: u32, connect_timeout: Duration, write_timeout: Option<Duration>) -> io::Result<Self> {
+		let stream:TcpStream = TcpTransport::connect_timeout(addr, connect_timeout)?.stream;
+		stream.set_write_timeout(write_timeout)?;
+		Self::with_stream(stream, prog, vers)
+	}
+
+	// The stream mustn't have a read timeout, since the reader thread takes any error as the end of the connection
+	pub fn with_stream(stream: TcpStream, prog: u32, vers: u32) -> io::Result<Self> {
+		let reader = stream.try_clone()?;
+		let codec = RecordCodec::default();
+
+		let pending:Pending = Arc::new(Mutex::new(Some(HashMap::new())));
+		let orphans:Orphans = Arc::new(Mutex::new(VecDeque::new()));
+		let (p, o) = (pending.clone(), orphans.clone());
+		thread::spawn(move || read_replies(reader, codec, p, o));
+
+		let auth:Box<dyn Auth> = Box::new(AuthNone);
+		let shared = Shared{ stream: Mutex::new(stream), codec, prog, vers, lastxid: AtomicU32::new(0), auth: Mutex::new(auth), pending, orphans };
+		Ok(Self{ shared: Arc::new(shared) })
+	}
+
+	pub fn set_auth<A: Auth + 'static>(&self, auth:A) {
+		*lock_ignoring_poison(&self.shared.auth) = Box::new(auth);
+	}
+
+	// Sends a call with arguments that have already been packed and returns without waiting for the reply
+	pub fn call(&self, prc:u32, args:&[u8]) -> io::Result<PendingCall> {
+		let (xid, rx) = self.shared.send_call(prc, args)?;
+		Ok(PendingCall{ shared: self.shared.clone(), xid, prc, args: args.to_vec(), rx })
+	}
+
+	// Same as call, packing the arguments with XDR first
+	pub fn call_with<S: serde::Serialize>(&self, prc:u32, args:&S) -> io::Result<PendingCall> {
+		let mut packer = Packer::new();
+		packer.pack(args)?;
+		self.call(prc, &packer.buff)
+	}
+
+	// Number of calls still waiting for a reply
+	pub fn in_flight(&self) -> usize {
+		lock_ignoring_poison(&self.shared.pending).as_ref().map_or(0, |pending| pending.len())
+	}
+
+	// Returns the replies that arrived with no call waiting for them, along with their xids
+	pub fn take_orphans(&self) -> Vec<(u32, Vec<u8>)> {
+		lock_ignoring_poison(&self.shared.orphans).drain(..).collect()
+	}
+}
+
+impl Drop for PipelinedClient {
+	fn drop(&mut self) {
+		// Stops the reader thread, which fails any calls still pending
+		let _ = lock_ignoring_poison(&self.shared.stream).shutdown(Shutdown::Both);
+	}
+}
+
+impl PendingCall {
+
+	pub fn xid(&self) -> u32 { self.xid }
+
+	// Blocks until the reply arrives and returns an unpacker positioned at the results
+	pub fn wait(self) -> io::Result<Unpacker> {
+		self.wait_inner(None)
+	}
+
+	// Gives up after the timeout, in which case a reply that turns up later becomes an orphan
+	pub fn wait_timeout(self, timeout:Duration) -> io::Result<Unpacker> {
+		self.wait_inner(Some(timeout))
+	}
+
+	fn wait_inner(mut self, timeout:Option<Duration>) -> io::Result<Unpacker> {
+		loop {
+			let received = match timeout {
+				Some(t) => self.rx.recv_timeout(t),
+				None    => self.rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+			};
+			let reply:Vec<u8> = match received {
+				Ok(reply) => reply,
+				Err(RecvTimeoutError::Timeout) => return Err(Error::new(ErrorKind::TimedOut, "No reply before the call timed out")),
+				Err(RecvTimeoutError::Disconnected) => return Err(Error::new(ErrorKind::ConnectionAborted, "Connection closed before the reply arrived")),
+			};
+
+			let mut unpacker = Unpacker::new();
+			unpacker.reset_owned(reply);
+			let (_, body) = xdr_unpack::unpack_reply(&mut unpacker)?;
+
+			let checked:Option<OpaqueAuth> = auth::check_reply(lock_ignoring_poison(&self.shared.auth).as_mut(), body)?;
+			if checked.is_some() { return Ok(unpacker); }
+
+			// The credential was refreshed, so send the call again under a new xid
+			let (xid, rx) = self.shared.send_call(self.prc, &self.args)?;
+			self.xid = xid;
+			self.rx = rx;
+		}
+	}
+}
+
+impl Drop for PendingCall {
+	fn drop(&mut self) {
+		remove_pending(&self.shared.pending, self.xid);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::net::TcpListener;
+	use std::panic::{self, AssertUnwindSafe};
+
+	use super::*;
+
+	const PROG:u32 = 0x20000001;
+
+	fn reply(xid:u32, result:u32) -> Vec<u8> {
+		let mut packer = Packer::new();
+		xdr_pack::pack_replyheader(&mut packer, xid, &OpaqueAuth::none()).unwrap();
+		packer.pack_u32(result).unwrap();
+		packer.buff
+	}
+
+	// Runs the server side of one connection on another thread
+	fn serve<F: FnOnce(TcpStream, RecordCodec) + Send + 'static>(f:F) -> (PipelinedClient, thread::JoinHandle<()>) {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let server = thread::spawn(move || {
+			let (stream, _) = listener.accept().unwrap();
+			f(stream, RecordCodec::default());
+		});
+		(PipelinedClient::connect_timeout(addr, PROG, 1, Duration::from_secs(5), Some(Duration::from_secs(5))).unwrap(), server)
+	}
+
+	#[test]
+	fn replies_out_of_order_reach_the_right_call() {
+		let (client, server) = serve(|mut stream, codec| {
+			let xids:Vec<u32> = (0..3).map(|_| reply_xid(&codec.read_record(&mut stream).unwrap()).unwrap()).collect();
+
+			// A reply nobody asked for, then the real ones last call first, each with its call's position
+			codec.write_record(&mut stream, &reply(xids[2].wrapping_add(100), 99)).unwrap();
+			for (idx, xid) in xids.iter().enumerate().rev() {
+				codec.write_record(&mut stream, &reply(*xid, idx as u32)).unwrap();
+			}
+		});
+
+		let calls:Vec<PendingCall> = (0..3).map(|_| client.call(1, &[]).unwrap()).collect();
+		assert_eq!(client.in_flight(), 3);
+
+		let xids:Vec<u32> = calls.iter().map(|c| c.xid()).collect();
+		for (idx, call) in calls.into_iter().enumerate() {
+			assert_eq!(call.wait().unwrap().unpack_u32().unwrap(), idx as u32);
+		}
+		assert_eq!(client.in_flight(), 0);
+
+		let orphans = client.take_orphans();
+		assert_eq!(orphans.len(), 1);
+		assert_eq!(orphans[0].0, xids[2].wrapping_add(100));
+		assert!(client.take_orphans().is_empty());
+		server.join().unwrap();
+	}
+
+	#[test]
+	fn late_and_abandoned_replies_become_orphans() {
+		let (client, server) = serve(|mut stream, codec| {
+			let late:u32 = reply_xid(&codec.read_record(&mut stream).unwrap()).unwrap();
+			let dropped:u32 = reply_xid(&codec.read_record(&mut stream).unwrap()).unwrap();
+			let last:u32 = reply_xid(&codec.read_record(&mut stream).unwrap()).unwrap();
+			codec.write_record(&mut stream, &reply(late, 1)).unwrap();
+			codec.write_record(&mut stream, &reply(dropped, 2)).unwrap();
+			codec.write_record(&mut stream, &reply(last, 3)).unwrap();
+		});
+
+		let late = client.call(1, &[]).unwrap();
+		let late_xid:u32 = late.xid();
+		assert_eq!(late.wait_timeout(Duration::from_millis(10)).err().map(|e| e.kind()), Some(ErrorKind::TimedOut));
+		let dropped = client.call(1, &[]).unwrap();
+		let dropped_xid:u32 = dropped.xid();
+		drop(dropped);
+		assert_eq!(client.in_flight(), 0);
+
+		assert_eq!(client.call(1, &[]).unwrap().wait().unwrap().unpack_u32().unwrap(), 3);
+		let orphans:Vec<u32> = client.take_orphans().into_iter().map(|(xid, _)| xid).collect();
+		assert_eq!(orphans, vec![late_xid, dropped_xid]);
+		server.join().unwrap();
+	}
+
+	#[test]
+	fn calls_fail_once_the_connection_closes() {
+		let (client, server) = serve(|mut stream, codec| {
+			codec.read_record(&mut stream).unwrap();
+		});
+
+		let e = client.call(1, &[]).unwrap().wait().err().unwrap();
+		assert_eq!(e.kind(), ErrorKind::ConnectionAborted);
+		assert_eq!(client.in_flight(), 0);
+
+		let e = client.call(1, &[]).err().unwrap();
+		assert_eq!(e.kind(), ErrorKind::ConnectionAborted);
+		server.join().unwrap();
+	}
+
+	struct PanickingAuth;
+
+	impl Auth for PanickingAuth {
+		fn credential(&self) -> OpaqueAuth { panic!("No credential") }
+	}
+
+	#[test]
+	fn a_panicking_caller_doesnt_poison_the_client() {
+		let (client, server) = serve(|mut stream, codec| {
+			let xid:u32 = reply_xid(&codec.read_record(&mut stream).unwrap()).unwrap();
+			codec.write_record(&mut stream, &reply(xid, 7)).unwrap();
+		});
+
+		client.set_auth(PanickingAuth);
+		assert!(panic::catch_unwind(AssertUnwindSafe(|| client.call(1, &[]))).is_err());
+		assert!(client.shared.auth.is_poisoned());
+
+		client.set_auth(AuthNone);
+		assert_eq!(client.call(1, &[]).unwrap().wait().unwrap().unpack_u32().unwrap(), 7);
+		server.join().unwrap();
+	}
+}
@@ -10,19 +10,30 @@ pub const PMAPPROC_GETPORT:u32 = 3;     // (mapping) -> unsigned int
 pub const PMAPPROC_DUMP:u32    = 4;     // (void) -> pmaplist
 pub const PMAPPROC_CALLIT:u32  = 5;     // (call_args) -> call_result
 
-use std::io::{self, Error, ErrorKind};
+use std::io::{self, Error};
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use serde::{Serialize, Deserialize};
 
 use crate::xdr;
 
-use super::{IPPROTO_TCP, IPPROTO_UDP};
-use super::xdr_pack;
-use super::tcp_clients::TcpClient;
-use super::udp_clients::UdpClient;
+use super::{IPPROTO_TCP, IPPROTO_UDP, xdr_pack, xdr_unpack};
+use super::client::RpcClient;
+use super::transport::RpcTransport;
+use super::tcp_clients::{TcpClient, TcpTransport};
+#[cfg(feature = "async")]
+use super::async_clients::AsyncTcpClient;
+use super::udp_clients::{UdpClient, UdpTransport, BroadcastUdpClient, MAX_UDP_PAYLOAD};
+
+pub mod server;
+pub use self::server::PortMapperServer;
 
-#[derive(Debug)]
+// Serialized as the IPPROTO_* value, so the variant names have to be kept in sync with those constants
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Protocol {
-	TCP,
-	UDP,
+	#[serde(rename = "6")]  TCP,
+	#[serde(rename = "17")] UDP,
 }
 
 impl Protocol {
@@ -32,7 +43,7 @@ impl Protocol {
 	}}
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Mapping {
 	pub program: u32,
 	pub version: u32,
@@ -40,83 +51,255 @@ pub struct Mapping {
 	pub port: u32,
 }
 
-pub struct TcpPortMapperClient {
+// Client for every PMAP v2 procedure over any transport, normally TCP or UDP
+pub struct PortMapperClient<T: RpcTransport = TcpTransport> {
 	pub host: String,
-	pub tcp_client: TcpClient,
+	client: RpcClient<T>,
 }
 
-impl TcpPortMapperClient {
+impl PortMapperClient<TcpTransport> {
 
+	// Connects over TCP to the portmapper on the standard port
 	pub fn new(host:&str) -> io::Result<Self> {
-		let tcp_client = TcpClient::connect((host, PMAP_PORT), PMAP_PROG, PMAP_VERS)?;
-		Ok(Self{ host: host.to_owned(), tcp_client })
+		Self::tcp(host, PMAP_PORT)
+	}
+
+	pub fn tcp(host:&str, port:u16) -> io::Result<Self> {
+		let client = TcpClient::connect((host, port), PMAP_PROG, PMAP_VERS)?;
+		Ok(Self{ host: host.to_owned(), client })
+	}
+}
+
+impl PortMapperClient<UdpTransport> {
+
+	pub fn udp(host:&str, port:u16) -> io::Result<Self> {
+		let mut client = UdpClient::connect((host, port), PMAP_PROG, PMAP_VERS)?;
+
+		// Make room for DUMP replies from hosts with lots of registered programs
+		client.transport.recv_size = MAX_UDP_PAYLOAD;
+		Ok(Self{ host: host.to_owned(), client })
+	}
+}
+
+impl<T: RpcTransport> PortMapperClient<T> {
+
+	pub fn with_transport(host:&str, transport:T) -> Self {
+		Self{ host: host.to_owned(), client: RpcClient::new(transport, PMAP_PROG, PMAP_VERS) }
+	}
+
+	fn packer(&mut self) -> &mut xdr::Packer {
+		&mut self.client.packer
 	}
 
-	pub fn make_call(&mut self) -> io::Result<()> {
-	    self.tcp_client.do_call()
+	fn unpacker(&mut self) -> &mut xdr::Unpacker {
+		&mut self.client.unpacker
 	}
 
-	pub fn start_call(&mut self, prc:u32) -> io::Result<()> {
-	    self.tcp_client.lastxid += 1;
-	    self.tcp_client.packer.reset();
-	    xdr_pack::pack_callheader_no_auth(&mut self.tcp_client.packer, self.tcp_client.lastxid, self.tcp_client.prog, self.tcp_client.vers, prc)
+	fn start_call(&mut self, prc:u32) -> io::Result<()> {
+		self.client.start_call(prc)
 	}
 
+	fn make_call(&mut self) -> io::Result<()> {
+		self.client.do_call()
+	}
 
+	pub fn ping(&mut self) -> io::Result<()> {
+		self.start_call(PMAPPROC_NULL)?;
+		self.make_call()
+	}
+
+	// Returns false if the program, version and protocol already have a mapping
+	pub fn set(&mut self, m:&Mapping) -> io::Result<bool> {
+		self.start_call(PMAPPROC_SET)?;
+		self.packer().pack(m)?;
+		self.make_call()?;
+		Ok(self.unpacker().unpack_bool()?)
+	}
+
+	// Removes the program and version for every protocol; the protocol and port are ignored
+	pub fn unset(&mut self, m:&Mapping) -> io::Result<bool> {
+		self.start_call(PMAPPROC_UNSET)?;
+		self.packer().pack(m)?;
+		self.make_call()?;
+		Ok(self.unpacker().unpack_bool()?)
+	}
+
+	// Returns zero if the program isn't registered; the port is ignored
 	pub fn get_port(&mut self, m:&Mapping) -> io::Result<u32> {
         self.start_call(PMAPPROC_GETPORT)?;
-        xdr_pack::pack_mapping(&mut self.tcp_client.packer, m.program, m.version, m.protocol.to_u32(), m.port as u32)?;
+        self.packer().pack(m)?;
         self.make_call()?;
 
-       	let ans:u32 = self.tcp_client.unpacker.unpack_u32()?;
+       	let ans:u32 = self.unpacker().unpack_u32()?;
 
-       	if self.tcp_client.unpacker.all_data_consumed() { Ok(ans) }
-       	else { Err(Error::new(ErrorKind::Other, "Data unexpectedly left over in unpacker after unpacking port")) }
+       	if self.unpacker().all_data_consumed() { Ok(ans) }
+       	else { Err(Error::other("Data unexpectedly left over in unpacker after unpacking port")) }
 	}
 
+	pub fn dump(&mut self) -> io::Result<Vec<Mapping>> {
+		self.start_call(PMAPPROC_DUMP)?;
+		self.make_call()?;
+		Ok(xdr_unpack::unpack_list(self.unpacker())?)
+	}
+
+	// Asks the portmapper to call a procedure on our behalf.  Returns the port of the program along with its
+	// packed results.  The portmapper doesn't answer at all if the call fails, so this only returns on success or
+	// when the transport gives up.
+	pub fn callit(&mut self, prog:u32, vers:u32, prc:u32, args:&[u8]) -> io::Result<(u32, Vec<u8>)> {
+		self.start_call(PMAPPROC_CALLIT)?;
+		xdr_pack::pack_call_args(self.packer(), prog, vers, prc, args)?;
+		self.make_call()?;
+
+		let port:u32 = self.unpacker().unpack_u32()?;
+		let res:Vec<u8> = self.unpacker().unpack_variable_len_opaque()?;
+		Ok((port, res))
+	}
 }
 
-pub struct UdpPortMapperClient {
+// Async version of PortMapperClient over TCP
+#[cfg(feature = "async")]
+pub struct AsyncPortMapperClient {
 	pub host: String,
-	pub packer: xdr::Packer,
-	pub unpacker: xdr::Unpacker,
-	pub udp_client: UdpClient,
+	pub client: AsyncTcpClient,
 }
 
-impl UdpPortMapperClient {
-	
-	pub fn new(host:&str) -> io::Result<Self> {
-		let packer = xdr::Packer::new();
-		let unpacker = xdr::Unpacker::new();
-		let udp_client = UdpClient::connect((host, PMAP_PORT), PMAP_PROG, PMAP_VERS)?;
-		Ok(UdpPortMapperClient{ host: host.to_owned(), packer, unpacker, udp_client })
+#[cfg(feature = "async")]
+impl AsyncPortMapperClient {
+
+	pub async fn new(host:&str) -> io::Result<Self> {
+		Self::tcp(host, PMAP_PORT).await
 	}
 
-	pub fn start_call(&mut self, prc:u32) -> io::Result<()> {
-        self.udp_client.lastxid += 1;
-        self.packer.reset();
-        xdr_pack::pack_callheader_no_auth(&mut self.packer, self.udp_client.lastxid, self.udp_client.prog, self.udp_client.vers, prc)
+	pub async fn tcp(host:&str, port:u16) -> io::Result<Self> {
+		let client = AsyncTcpClient::connect((host, port), PMAP_PROG, PMAP_VERS).await?;
+		Ok(Self{ host: host.to_owned(), client })
 	}
 
-	pub fn dump(&mut self) -> io::Result<Vec<Mapping>> {
-		self.start_call(PMAPPROC_DUMP)?;
-		self.udp_client.do_call(&self.packer.get_buf()?, &mut self.unpacker, self.udp_client.lastxid)?;
-
-		let mut ans:Vec<Mapping> = vec![];
-		while self.unpacker.unpack_u32()? == 1 {
-			// TODO: consider moving this unpacking logic to xdr_unpack
-	        let program:u32 = self.unpacker.unpack_u32()?;
-	        let version:u32 = self.unpacker.unpack_u32()?;
-	        let protocol = match self.unpacker.unpack_u32()? {
-	        	IPPROTO_TCP => Protocol::TCP,
-	        	IPPROTO_UDP => Protocol::UDP,
-	        	_  => return Err(Error::new(ErrorKind::Other, "Unrecognized protocol type")),
-	        };
-	        let port:u32 = self.unpacker.unpack_u32()?;
-	        ans.push(Mapping{ program, version, protocol, port });
-		}
-
-		Ok(ans)
+	pub async fn ping(&mut self) -> io::Result<()> {
+		self.client.start_call(PMAPPROC_NULL)?;
+		self.client.do_call().await
+	}
+
+	pub async fn set(&mut self, m:&Mapping) -> io::Result<bool> {
+		self.client.start_call(PMAPPROC_SET)?;
+		self.client.packer.pack(m)?;
+		self.client.do_call().await?;
+		Ok(self.client.unpacker.unpack_bool()?)
+	}
+
+	pub async fn unset(&mut self, m:&Mapping) -> io::Result<bool> {
+		self.client.start_call(PMAPPROC_UNSET)?;
+		self.client.packer.pack(m)?;
+		self.client.do_call().await?;
+		Ok(self.client.unpacker.unpack_bool()?)
+	}
+
+	pub async fn get_port(&mut self, m:&Mapping) -> io::Result<u32> {
+		self.client.start_call(PMAPPROC_GETPORT)?;
+		self.client.packer.pack(m)?;
+		self.client.do_call().await?;
+
+		let ans:u32 = self.client.unpacker.unpack_u32()?;
+
+		if self.client.unpacker.all_data_consumed() { Ok(ans) }
+		else { Err(Error::other("Data unexpectedly left over in unpacker after unpacking port")) }
+	}
+
+	pub async fn dump(&mut self) -> io::Result<Vec<Mapping>> {
+		self.client.start_call(PMAPPROC_DUMP)?;
+		self.client.do_call().await?;
+		Ok(xdr_unpack::unpack_list(&mut self.client.unpacker)?)
+	}
+
+	pub async fn callit(&mut self, prog:u32, vers:u32, prc:u32, args:&[u8]) -> io::Result<(u32, Vec<u8>)> {
+		self.client.start_call(PMAPPROC_CALLIT)?;
+		xdr_pack::pack_call_args(&mut self.client.packer, prog, vers, prc, args)?;
+		self.client.do_call().await?;
+
+		let port:u32 = self.client.unpacker.unpack_u32()?;
+		let res:Vec<u8> = self.client.unpacker.unpack_variable_len_opaque()?;
+		Ok((port, res))
+	}
+}
+
+// Broadcasts a GETPORT to every portmapper reachable at the broadcast address and collects the answers for the
+// length of the window.  Hosts that don't have the program registered answer with port zero.  With a local address
+// the call goes out on that interface, which matters for the limited broadcast address on a host with several.
+pub fn broadcast_get_port(broadcast_addr:IpAddr, local_addr:Option<IpAddr>, window:Duration, m:&Mapping) -> io::Result<Vec<(SocketAddr, u32)>> {
+	let mut client = match local_addr {
+		Some(local) => BroadcastUdpClient::bind_on(local, PMAP_PORT, PMAP_PROG, PMAP_VERS)?,
+		None        => BroadcastUdpClient::bind(PMAP_PORT, PMAP_PROG, PMAP_VERS)?,
+	};
+	client.transport.broadcast_addr = broadcast_addr;
+	client.transport.window = window;
+
+	client.start_call(PMAPPROC_GETPORT)?;
+	client.packer.pack(m)?;
+
+	let mut ans:Vec<(SocketAddr, u32)> = vec![];
+	for (addr, reply) in client.make_call()? {
+		// Skip anything that doesn't decode rather than failing the whole discovery because of one odd host
+		if let Ok(port) = xdr::from_bytes::<u32>(&reply) { ans.push((addr, port)); }
 	}
+	Ok(ans)
 }
 
+#[cfg(test)]
+mod tests {
+	use std::net::Ipv4Addr;
+	use std::thread;
+
+	use crate::xdr::{Packer, Unpacker};
+	use crate::rpc::{CALL, RPCVERSION};
+	use crate::rpc::reply::OpaqueAuth;
+	use crate::rpc::transport::ChannelTransport;
+	use super::*;
+
+	#[test]
+	fn get_port_over_a_channel() {
+		let (transport, peer) = ChannelTransport::pair();
+		let mut client = PortMapperClient::with_transport("instrument", transport);
+
+		let instrument = thread::spawn(move || {
+			let call:Vec<u8> = peer.calls.recv().unwrap();
+			let mut unpacker = Unpacker::new();
+			unpacker.reset_owned(call);
+
+			let xid:u32 = unpacker.unpack_u32().unwrap();
+			assert_eq!(unpacker.unpack_i32().unwrap(), CALL);
+			assert_eq!(unpacker.unpack_u32().unwrap(), RPCVERSION);
+			assert_eq!(unpacker.unpack_u32().unwrap(), PMAP_PROG);
+			assert_eq!(unpacker.unpack_u32().unwrap(), PMAP_VERS);
+			assert_eq!(unpacker.unpack_u32().unwrap(), PMAPPROC_GETPORT);
+			xdr_unpack::unpack_auth(&mut unpacker).unwrap();
+			xdr_unpack::unpack_auth(&mut unpacker).unwrap();
+			let m:Mapping = unpacker.unpack().unwrap();
+			assert_eq!((m.program, m.version, m.protocol, m.port), (0x0607af, 1, Protocol::TCP, 0));
+			assert!(unpacker.remaining().is_empty());
+
+			// A reply to some other call first, which the transport should pass over
+			for (xid, port) in [(xid.wrapping_add(100), 1), (xid, 1024)] {
+				let mut packer = Packer::new();
+				xdr_pack::pack_replyheader(&mut packer, xid, &OpaqueAuth::none()).unwrap();
+				packer.pack_u32(port).unwrap();
+				peer.replies.send(packer.buff).unwrap();
+			}
+		});
+
+		let m = Mapping{ program: 0x0607af, version: 1, protocol: Protocol::TCP, port: 0 };
+		assert_eq!(client.get_port(&m).unwrap(), 1024);
+		instrument.join().unwrap();
+	}
+
+	#[test]
+	fn broadcast_from_a_local_address() {
+		let m = Mapping{ program: 0x0607af, version: 1, protocol: Protocol::TCP, port: 0 };
+		let loopback:IpAddr = Ipv4Addr::LOCALHOST.into();
+		let found = broadcast_get_port(loopback, Some(loopback), Duration::from_millis(50), &m).unwrap();
+		assert!(found.iter().all(|(addr, _)| addr.ip() == loopback));
+
+		// An address this host doesn't have can't be bound to
+		let elsewhere:IpAddr = Ipv4Addr::new(192, 0, 2, 1).into();
+		assert!(broadcast_get_port(loopback, Some(elsewhere), Duration::from_millis(50), &m).is_err());
+	}
+}
@@ -0,0 +1,252 @@
+
+use std::io;
+use std::net::{SocketAddr, TcpListener, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::xdr::{Packer, Unpacker, UNBOUNDED};
+use crate::rpc::xdr_pack;
+use crate::rpc::server::{Server, CallInfo, Outcome};
+use crate::rpc::udp_clients::{UdpClient, MAX_UDP_PAYLOAD};
+use super::{Mapping, Protocol, PMAP_PROG, PMAP_VERS, PMAPPROC_NULL, PMAPPROC_SET, PMAPPROC_UNSET, PMAPPROC_GETPORT, PMAPPROC_DUMP, PMAPPROC_CALLIT};
+
+// How long CALLIT waits on the program it forwards to before giving up.  The program is on this host so it should
+// answer in well under this, and the wait holds up every other UDP call to the portmapper.
+const CALLIT_TIMEOUT:Duration = Duration::from_millis(250);
+
+// A portmapper (RFC 1833, version 2) that can stand in for the system one, either on port 111 or on any other port
+// for testing.  Mappings can be added locally with set or with PMAPPROC_SET, which like the system portmapper only
+// accepts calls from this host.
+#[derive(Clone, Default)]
+pub struct PortMapperServer {
+	mappings: Arc<Mutex<Vec<Mapping>>>,
+}
+
+impl PortMapperServer {
+
+	pub fn new() -> Self { Self::default() }
+
+	// Fails if there's already a mapping for the same program, version and protocol
+	pub fn set(&self, m:Mapping) -> bool {
+		let mut mappings = self.mappings.lock().unwrap();
+		if mappings.iter().any(|x| x.program == m.program && x.version == m.version && x.protocol == m.protocol) {
+			return false;
+		}
+		mappings.push(m);
+		true
+	}
+
+	// Removes the mappings for a program and version over every protocol
+	pub fn unset(&self, program:u32, version:u32) -> bool {
+		let mut mappings = self.mappings.lock().unwrap();
+		let before:usize = mappings.len();
+		mappings.retain(|x| !(x.program == program && x.version == version));
+		mappings.len() < before
+	}
+
+	// Zero means the program isn't registered
+	pub fn get_port(&self, program:u32, version:u32, protocol:Protocol) -> u32 {
+		self.mappings.lock().unwrap().iter()
+			.find(|x| x.program == program && x.version == version && x.protocol == protocol)
+			.map(|x| x.port)
+			.unwrap_or(0)
+	}
+
+	pub fn dump(&self) -> Vec<Mapping> { self.mappings.lock().unwrap().clone() }
+
+	// Adds the portmapper procedures to a server, so it can share a server with other programs
+	pub fn register(&self, server:&mut Server) {
+		server.register(PMAP_PROG, PMAP_VERS, PMAPPROC_NULL, |_:&CallInfo, _:&mut Unpacker, _:&mut Packer| Ok(Outcome::Reply));
+
+		let pmap = self.clone();
+		server.register(PMAP_PROG, PMAP_VERS, PMAPPROC_SET, move |info:&CallInfo, args:&mut Unpacker, results:&mut Packer| {
+			let m:Mapping = args.unpack()?;
+			results.pack_bool(info.peer.ip().is_loopback() && pmap.set(m))?;
+			Ok(Outcome::Reply)
+		});
+
+		let pmap = self.clone();
+		server.register(PMAP_PROG, PMAP_VERS, PMAPPROC_UNSET, move |info:&CallInfo, args:&mut Unpacker, results:&mut Packer| {
+			let m:Mapping = args.unpack()?;
+			results.pack_bool(info.peer.ip().is_loopback() && pmap.unset(m.program, m.version))?;
+			Ok(Outcome::Reply)
+		});
+
+		let pmap = self.clone();
+		server.register(PMAP_PROG, PMAP_VERS, PMAPPROC_GETPORT, move |_:&CallInfo, args:&mut Unpacker, results:&mut Packer| {
+			let m:Mapping = args.unpack()?;
+			results.pack_u32(pmap.get_port(m.program, m.version, m.protocol))?;
+			Ok(Outcome::Reply)
+		});
+
+		let pmap = self.clone();
+		server.register(PMAP_PROG, PMAP_VERS, PMAPPROC_DUMP, move |_:&CallInfo, _:&mut Unpacker, results:&mut Packer| {
+			xdr_pack::pack_list(results, &pmap.dump())?;
+			Ok(Outcome::Reply)
+		});
+
+		let pmap = self.clone();
+		server.register(PMAP_PROG, PMAP_VERS, PMAPPROC_CALLIT, move |_:&CallInfo, args:&mut Unpacker, results:&mut Packer| {
+			let prog:u32 = args.unpack_u32()?;
+			let vers:u32 = args.unpack_u32()?;
+			let prc:u32  = args.unpack_u32()?;
+			let call_args:Vec<u8> = args.unpack_variable_len_opaque_max(UNBOUNDED)?;
+
+			// Errors and unregistered programs get no reply at all so broadcasts don't get flooded with failures
+			match pmap.forward(prog, vers, prc, &call_args) {
+				Some((port, res)) => {
+					results.pack_u32(port)?;
+					results.pack_variable_len_opaque(&res)?;
+					Ok(Outcome::Reply)
+				},
+				None => Ok(Outcome::NoReply),
+			}
+		});
+	}
+
+	// Makes a call on behalf of a CALLIT caller, over UDP to the local program.  Calls to the portmapper itself are
+	// refused, since they'd wait on the thread that's making them and would let anyone SET through the loopback.
+	fn forward(&self, prog:u32, vers:u32, prc:u32, args:&[u8]) -> Option<(u32, Vec<u8>)> {
+		if prog == PMAP_PROG { return None; }
+
+		let port:u32 = self.get_port(prog, vers, Protocol::UDP);
+		if port == 0 { return None; }
+
+		let mut client = UdpClient::connect(("127.0.0.1", port as u16), prog, vers).ok()?;
+		client.transport.timeout = CALLIT_TIMEOUT;
+		client.transport.recv_size = MAX_UDP_PAYLOAD;
+		client.start_call(prc).ok()?;
+		client.packer.buff.extend_from_slice(args);
+		client.do_call().ok()?;
+		Some((port, client.unpacker.remaining().to_vec()))
+	}
+
+	// Serves over TCP and UDP on the same port in background threads and registers the portmapper with itself.  Use
+	// port 0 to pick any free port.  Returns the address actually bound.
+	pub fn spawn(&self, addr:SocketAddr) -> io::Result<SocketAddr> {
+		let listener = TcpListener::bind(addr)?;
+		let local:SocketAddr = listener.local_addr()?;
+		let socket = UdpSocket::bind(local)?;
+
+		let port:u32 = local.port() as u32;
+		self.set(Mapping{ program: PMAP_PROG, version: PMAP_VERS, protocol: Protocol::TCP, port });
+		self.set(Mapping{ program: PMAP_PROG, version: PMAP_VERS, protocol: Protocol::UDP, port });
+
+		let mut server = Server::new();
+		self.register(&mut server);
+		let server = Arc::new(server);
+
+		let tcp_server = server.clone();
+		thread::spawn(move || tcp_server.serve_tcp(listener));
+		thread::spawn(move || server.serve_udp(&socket));
+
+		Ok(local)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::net::Ipv4Addr;
+
+	use crate::rpc::transport::LoopbackTransport;
+	use super::*;
+	use super::super::PortMapperClient;
+
+	const PROG:u32 = 0x20000003;
+
+	fn spawn() -> (PortMapperServer, u16) {
+		let pmap = PortMapperServer::new();
+		let port:u16 = pmap.spawn(SocketAddr::from((Ipv4Addr::LOCALHOST, 0))).unwrap().port();
+		(pmap, port)
+	}
+
+	#[test]
+	fn set_get_port_and_dump_over_tcp() {
+		let (pmap, port) = spawn();
+		let mut client = PortMapperClient::tcp("127.0.0.1", port).unwrap();
+
+		let m = Mapping{ program: PROG, version: 1, protocol: Protocol::TCP, port: 4000 };
+		assert!(client.set(&m).unwrap());
+		assert!(!client.set(&m).unwrap());
+		assert_eq!(client.get_port(&Mapping{ port: 0, ..m.clone() }).unwrap(), 4000);
+		assert_eq!(client.get_port(&Mapping{ protocol: Protocol::UDP, ..m.clone() }).unwrap(), 0);
+
+		let pmap_tcp = Mapping{ program: PMAP_PROG, version: PMAP_VERS, protocol: Protocol::TCP, port: port as u32 };
+		let pmap_udp = Mapping{ protocol: Protocol::UDP, ..pmap_tcp.clone() };
+		assert_eq!(client.dump().unwrap(), vec![pmap_tcp, pmap_udp, m]);
+		assert_eq!(pmap.get_port(PROG, 1, Protocol::TCP), 4000);
+	}
+
+	#[test]
+	fn callit_refuses_the_portmapper_itself() {
+		let (_pmap, port) = spawn();
+		let mut client = PortMapperClient::udp("127.0.0.1", port).unwrap();
+		client.client.transport.timeout = Duration::from_millis(300);
+
+		let m = Mapping{ program: PROG, version: 1, protocol: Protocol::UDP, port: 4000 };
+		let mut args = Packer::new();
+		args.pack(&m).unwrap();
+		assert!(client.callit(PMAP_PROG, PMAP_VERS, PMAPPROC_SET, &args.buff).is_err());
+		assert_eq!(client.get_port(&m).unwrap(), 0);
+	}
+
+	#[test]
+	fn unset_removes_every_protocol() {
+		let (pmap, port) = spawn();
+		let mut client = PortMapperClient::udp("127.0.0.1", port).unwrap();
+
+		let tcp = Mapping{ program: PROG, version: 1, protocol: Protocol::TCP, port: 4000 };
+		let udp = Mapping{ protocol: Protocol::UDP, port: 4001, ..tcp.clone() };
+		let other_version = Mapping{ version: 2, ..tcp.clone() };
+		for m in [&tcp, &udp, &other_version] { assert!(client.set(m).unwrap()); }
+
+		assert!(client.unset(&tcp).unwrap());
+		assert!(!client.unset(&tcp).unwrap());
+		assert_eq!(client.get_port(&udp).unwrap(), 0);
+		assert_eq!(client.get_port(&other_version).unwrap(), 4000);
+		assert_eq!(pmap.dump().into_iter().filter(|m| m.program == PROG).collect::<Vec<_>>(), vec![other_version]);
+	}
+
+	#[test]
+	fn callit_forwards_to_a_registered_program() {
+		let (pmap, port) = spawn();
+
+		// A program that adds one to its argument, over UDP only
+		let mut server = Server::new();
+		server.register(PROG, 1, 1, |_:&CallInfo, args:&mut Unpacker, results:&mut Packer| {
+			results.pack_u32(args.unpack_u32()? + 1)?;
+			Ok(Outcome::Reply)
+		});
+		let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+		let prog_port:u32 = socket.local_addr().unwrap().port() as u32;
+		thread::spawn(move || server.serve_udp(&socket));
+		assert!(pmap.set(Mapping{ program: PROG, version: 1, protocol: Protocol::UDP, port: prog_port }));
+
+		let mut client = PortMapperClient::udp("127.0.0.1", port).unwrap();
+		assert_eq!(client.callit(PROG, 1, 1, &41u32.to_be_bytes()).unwrap(), (prog_port, 42u32.to_be_bytes().to_vec()));
+
+		// Unregistered versions get no answer at all
+		client.client.transport.timeout = Duration::from_millis(300);
+		assert!(client.callit(PROG, 2, 1, &41u32.to_be_bytes()).is_err());
+	}
+
+	#[test]
+	fn set_and_unset_only_from_this_host() {
+		let pmap = PortMapperServer::new();
+		let mut server = Server::new();
+		pmap.register(&mut server);
+
+		let remote = LoopbackTransport{ server: Arc::new(server), peer: SocketAddr::from(([192, 0, 2, 1], 600)) };
+		let mut client = PortMapperClient::with_transport("remote", remote);
+
+		let m = Mapping{ program: PROG, version: 1, protocol: Protocol::TCP, port: 4000 };
+		assert!(!client.set(&m).unwrap());
+		assert!(pmap.dump().is_empty());
+
+		assert!(pmap.set(m.clone()));
+		assert!(!client.unset(&m).unwrap());
+		assert_eq!(client.get_port(&m).unwrap(), 4000);
+		assert_eq!(client.dump().unwrap(), vec![m]);
+	}
+}
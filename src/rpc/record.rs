@@ -0,0 +1,240 @@
+
+use std::io::{self, Read, Write, ErrorKind};
+
+use byteorder::{BigEndian, ReadBytesExt};
+
+// Record marking for RPC over TCP (RFC 5531 section 11).  Each record is sent as one or more fragments, each
+// preceded by a four-byte mark holding the fragment length with the high bit set on the last fragment.
+
+pub const LAST_FRAGMENT:u32 = 0x80000000;
+pub const MAX_FRAGMENT_SIZE:usize = 0x7fffffff;
+
+// Large enough for a full memory dump from the scopes we've used, small enough that a corrupted mark can't exhaust memory
+pub const DEFAULT_MAX_RECORD_SIZE:usize = 1 << 28;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RecordCodec {
+	pub max_fragment: usize,        // Outgoing records longer than this are split into several fragments
+	pub max_record: usize,          // Incoming records longer than this are an error
+}
+
+impl Default for RecordCodec {
+	fn default() -> Self { Self{ max_fragment: MAX_FRAGMENT_SIZE, max_record: DEFAULT_MAX_RECORD_SIZE } }
+}
+
+impl RecordCodec {
+
+	pub fn read_record<R: Read>(&self, stream:&mut R) -> io::Result<Vec<u8>> {
+		let mut record:Vec<u8> = vec![];
+		let mut last:bool = false;
+		while !last {
+			let mark:u32 = stream.read_u32::<BigEndian>()?;
+			last = (mark & LAST_FRAGMENT) != 0;
+			let n:usize = (mark & !LAST_FRAGMENT) as usize;
+
+			if record.len() + n > self.max_record {
+				return Err(io::Error::new(ErrorKind::InvalidData, format!("Record exceeds the maximum size of {} bytes", self.max_record)));
+			}
+
+			// The buffer grows as the data turns up rather than all at once from a mark that may be bogus
+			let start:usize = record.len();
+			stream.take(n as u64).read_to_end(&mut record)?;
+			if record.len() - start < n {
+				return Err(io::Error::new(ErrorKind::UnexpectedEof, "Stream ended part way through a record"));
+			}
+		}
+		Ok(record)
+	}
+
+	// The whole record goes out in one write so the marks don't end up in packets of their own
+	pub fn write_record<W: Write>(&self, stream:&mut W, record:&[u8]) -> io::Result<()> {
+		stream.write_all(&self.encode(record))
+	}
+
+	// Splits a record into marked fragments, ready to be sent as is
+	pub fn encode(&self, record:&[u8]) -> Vec<u8> {
+		let frag_size:usize = self.max_fragment.clamp(1, MAX_FRAGMENT_SIZE);
+		let n_frags:usize = record.len().div_ceil(frag_size).max(1);
+
+		let mut send_bytes:Vec<u8> = Vec::with_capacity(record.len() + 4*n_frags);
+		let mut chunks = record.chunks(frag_size).peekable();
+		if chunks.peek().is_none() {
+			send_bytes.extend_from_slice(&LAST_FRAGMENT.to_be_bytes());
+		}
+		while let Some(chunk) = chunks.next() {
+			let last:u32 = if chunks.peek().is_none() { LAST_FRAGMENT } else { 0 };
+			send_bytes.extend_from_slice(&(chunk.len() as u32 | last).to_be_bytes());
+			send_bytes.extend_from_slice(chunk);
+		}
+		send_bytes
+	}
+
+	// Takes the first complete record off the front of a buffer of received bytes, returning the record and the
+	// number of bytes it took up.  Returns None if the buffer doesn't hold a whole record yet.
+	pub fn decode(&self, buff:&[u8]) -> io::Result<Option<(Vec<u8>, usize)>> {
+		let mut record:Vec<u8> = vec![];
+		let mut pos:usize = 0;
+		loop {
+			let mark:u32 = match buff.get(pos..pos+4) {
+				Some(b) => u32::from_be_bytes([b[0], b[1], b[2], b[3]]),
+				None    => return Ok(None),
+			};
+			let last:bool = (mark & LAST_FRAGMENT) != 0;
+			let n:usize = (mark & !LAST_FRAGMENT) as usize;
+
+			if record.len() + n > self.max_record {
+				return Err(io::Error::new(ErrorKind::InvalidData, format!("Record exceeds the maximum size of {} bytes", self.max_record)));
+			}
+
+			let frag:&[u8] = match buff.get(pos+4..pos+4+n) {
+				Some(frag) => frag,
+				None       => return Ok(None),
+			};
+			record.extend_from_slice(frag);
+			pos += 4 + n;
+
+			if last { return Ok(Some((record, pos))); }
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::Cursor;
+	use super::*;
+
+	fn mark(len:u32, last:bool) -> [u8; 4] {
+		(len | if last { LAST_FRAGMENT } else { 0 }).to_be_bytes()
+	}
+
+	#[test]
+	fn round_trip_in_several_fragments() {
+		let codec = RecordCodec{ max_fragment: 4, ..Default::default() };
+		let record:Vec<u8> = (0..10).collect();
+
+		let mut stream = Cursor::new(vec![]);
+		codec.write_record(&mut stream, &record).unwrap();
+
+		let mut expected:Vec<u8> = vec![];
+		expected.extend_from_slice(&mark(4, false));
+		expected.extend_from_slice(&record[0..4]);
+		expected.extend_from_slice(&mark(4, false));
+		expected.extend_from_slice(&record[4..8]);
+		expected.extend_from_slice(&mark(2, true));
+		expected.extend_from_slice(&record[8..10]);
+		assert_eq!(stream.get_ref(), &expected);
+
+		stream.set_position(0);
+		assert_eq!(codec.read_record(&mut stream).unwrap(), record);
+	}
+
+	#[test]
+	fn lengths_that_are_not_multiples_of_four() {
+		let codec = RecordCodec{ max_fragment: 3, ..Default::default() };
+		for len in [1usize, 2, 3, 5, 7, 13] {
+			let record:Vec<u8> = (0..len as u8).collect();
+			let mut stream = Cursor::new(vec![]);
+			codec.write_record(&mut stream, &record).unwrap();
+			stream.set_position(0);
+			assert_eq!(codec.read_record(&mut stream).unwrap(), record);
+		}
+	}
+
+	#[test]
+	fn empty_last_fragment() {
+		let mut bytes:Vec<u8> = vec![];
+		bytes.extend_from_slice(&mark(3, false));
+		bytes.extend_from_slice(b"abc");
+		bytes.extend_from_slice(&mark(0, true));
+
+		let codec = RecordCodec::default();
+		assert_eq!(codec.read_record(&mut Cursor::new(bytes.clone())).unwrap(), b"abc");
+		assert_eq!(codec.decode(&bytes).unwrap(), Some((b"abc".to_vec(), bytes.len())));
+	}
+
+	#[test]
+	fn empty_record() {
+		let codec = RecordCodec::default();
+		let mut stream = Cursor::new(vec![]);
+		codec.write_record(&mut stream, &[]).unwrap();
+		assert_eq!(stream.get_ref(), &mark(0, true));
+
+		stream.set_position(0);
+		assert!(codec.read_record(&mut stream).unwrap().is_empty());
+	}
+
+	#[test]
+	fn fragments_no_longer_than_max_fragment() {
+		let codec = RecordCodec{ max_fragment: 5, ..Default::default() };
+		let bytes:Vec<u8> = codec.encode(&[0; 12]);
+
+		let mut pos:usize = 0;
+		let mut lens:Vec<usize> = vec![];
+		while pos < bytes.len() {
+			let n:usize = (u32::from_be_bytes([bytes[pos], bytes[pos+1], bytes[pos+2], bytes[pos+3]]) & !LAST_FRAGMENT) as usize;
+			lens.push(n);
+			pos += 4 + n;
+		}
+		assert_eq!(lens, vec![5, 5, 2]);
+	}
+
+	#[test]
+	fn records_over_max_record_are_rejected() {
+		let codec = RecordCodec{ max_fragment: 4, max_record: 6 };
+		let bytes:Vec<u8> = codec.encode(&[0; 8]);
+
+		let e = codec.read_record(&mut Cursor::new(bytes.clone())).unwrap_err();
+		assert_eq!(e.kind(), ErrorKind::InvalidData);
+		assert_eq!(codec.decode(&bytes).unwrap_err().kind(), ErrorKind::InvalidData);
+
+		// Exactly at the limit is fine
+		let bytes:Vec<u8> = codec.encode(&[0; 6]);
+		assert_eq!(codec.read_record(&mut Cursor::new(bytes)).unwrap().len(), 6);
+	}
+
+	#[test]
+	fn truncated_stream_is_an_error() {
+		let codec = RecordCodec::default();
+		let bytes:Vec<u8> = codec.encode(b"hello");
+		let e = codec.read_record(&mut Cursor::new(&bytes[..bytes.len()-1])).unwrap_err();
+		assert_eq!(e.kind(), ErrorKind::UnexpectedEof);
+	}
+
+	#[test]
+	fn decode_waits_for_the_whole_record() {
+		let codec = RecordCodec{ max_fragment: 4, ..Default::default() };
+		let mut bytes:Vec<u8> = codec.encode(b"partial record");
+		bytes.extend_from_slice(&codec.encode(b"next"));
+		let first_len:usize = bytes.len() - 8;
+
+		for end in 0..first_len {
+			assert_eq!(codec.decode(&bytes[..end]).unwrap(), None, "decoded from only {} bytes", end);
+		}
+		assert_eq!(codec.decode(&bytes[..first_len]).unwrap(), Some((b"partial record".to_vec(), first_len)));
+
+		// Whatever follows the record is left alone
+		let (_, used) = codec.decode(&bytes).unwrap().unwrap();
+		assert_eq!(codec.decode(&bytes[used..]).unwrap(), Some((b"next".to_vec(), 8)));
+	}
+
+	// Remembers the largest buffer it was asked to fill
+	struct Largest<R> { inner: R, largest: usize }
+
+	impl<R: Read> Read for Largest<R> {
+		fn read(&mut self, buf:&mut [u8]) -> io::Result<usize> {
+			self.largest = self.largest.max(buf.len());
+			self.inner.read(buf)
+		}
+	}
+
+	#[test]
+	fn a_huge_mark_on_a_short_stream_doesnt_allocate_the_whole_record() {
+		let codec = RecordCodec::default();
+		let mut bytes:Vec<u8> = mark((DEFAULT_MAX_RECORD_SIZE - 1) as u32, true).to_vec();
+		bytes.extend_from_slice(b"abcd");
+
+		let mut stream = Largest{ inner: Cursor::new(bytes), largest: 0 };
+		assert_eq!(codec.read_record(&mut stream).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+		assert!(stream.largest < 1 << 20, "asked to fill {} bytes", stream.largest);
+	}
+}
@@ -0,0 +1,216 @@
+
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+
+use super::RpcError;
+
+// Reply message bodies from RFC 5531 section 9.  The discriminants are given explicitly so these pack and unpack
+// through xdr::Packer::pack and xdr::Unpacker::unpack exactly as the RFC describes them.
+
+// Longest body an opaque_auth may have
+pub const MAX_AUTH_BYTES:u32 = 400;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OpaqueAuth {
+	pub flavor: i32,
+	#[serde(serialize_with = "crate::xdr::bounded::opaque::serialize::<_, MAX_AUTH_BYTES>")]
+	#[serde(deserialize_with = "crate::xdr::bounded::opaque::deserialize::<_, MAX_AUTH_BYTES>")]
+	pub body: Vec<u8>,
+}
+
+impl OpaqueAuth {
+	pub fn none() -> Self { Self{ flavor: 0, body: vec![] } }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ReplyBody {
+	#[serde(rename = "0")]
+	Accepted{ verf: OpaqueAuth, reply: AcceptedReply },
+	#[serde(rename = "1")]
+	Denied(RejectedReply),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum AcceptedReply {
+	#[serde(rename = "0")]
+	Success,
+	#[serde(rename = "1")]
+	ProgUnavail,
+	#[serde(rename = "2")]
+	ProgMismatch{ low: u32, high: u32 },
+	#[serde(rename = "3")]
+	ProcUnavail,
+	#[serde(rename = "4")]
+	GarbageArgs,
+	#[serde(rename = "5")]
+	SystemErr,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum RejectedReply {
+	#[serde(rename = "0")]
+	RpcMismatch{ low: u32, high: u32 },
+	#[serde(rename = "1")]
+	AuthError(AuthStat),
+}
+
+// Packed as a plain enum, by hand rather than derived so that a status from a flavor this doesn't know about (the
+// Kerberos ones, say) still comes back as an authentication error rather than failing to unpack
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthStat {
+	Ok,
+	BadCred,                // bad credential (seal broken)
+	RejectedCred,           // client must begin new session
+	BadVerf,                // bad verifier (seal broken)
+	RejectedVerf,           // verifier expired or replayed
+	TooWeak,                // rejected for security reasons
+	InvalidResp,            // bogus response verifier
+	Failed,                 // reason unknown
+	RpcsecGssCredProblem,   // no credentials for user
+	RpcsecGssCtxProblem,    // problem with context
+	Other(i32),
+}
+
+impl AuthStat {
+
+	pub fn code(&self) -> i32 {
+		match self {
+			AuthStat::Ok                   => 0,
+			AuthStat::BadCred              => 1,
+			AuthStat::RejectedCred         => 2,
+			AuthStat::BadVerf              => 3,
+			AuthStat::RejectedVerf         => 4,
+			AuthStat::TooWeak              => 5,
+			AuthStat::InvalidResp          => 6,
+			AuthStat::Failed               => 7,
+			AuthStat::RpcsecGssCredProblem => 13,
+			AuthStat::RpcsecGssCtxProblem  => 14,
+			AuthStat::Other(code)          => *code,
+		}
+	}
+
+	pub fn from_code(code:i32) -> Self {
+		match code {
+			0  => AuthStat::Ok,
+			1  => AuthStat::BadCred,
+			2  => AuthStat::RejectedCred,
+			3  => AuthStat::BadVerf,
+			4  => AuthStat::RejectedVerf,
+			5  => AuthStat::TooWeak,
+			6  => AuthStat::InvalidResp,
+			7  => AuthStat::Failed,
+			13 => AuthStat::RpcsecGssCredProblem,
+			14 => AuthStat::RpcsecGssCtxProblem,
+			_  => AuthStat::Other(code),
+		}
+	}
+}
+
+impl Serialize for AuthStat {
+	fn serialize<S: Serializer>(&self, s:S) -> Result<S::Ok, S::Error> { s.serialize_i32(self.code()) }
+}
+
+impl<'de> Deserialize<'de> for AuthStat {
+	fn deserialize<D: Deserializer<'de>>(d:D) -> Result<Self, D::Error> { Ok(AuthStat::from_code(i32::deserialize(d)?)) }
+}
+
+impl ReplyBody {
+
+	pub fn success(verf:OpaqueAuth) -> Self { ReplyBody::Accepted{ verf, reply: AcceptedReply::Success } }
+
+	// Anything other than an accepted, successful reply becomes an error.  On success, the verifier is returned
+	// and the procedure's results follow in the message.
+	pub fn into_result(self) -> Result<OpaqueAuth, RpcError> {
+		match self {
+			ReplyBody::Accepted{ verf, reply } => match reply {
+				AcceptedReply::Success                => Ok(verf),
+				AcceptedReply::ProgUnavail            => Err(RpcError::ProgUnavail),
+				AcceptedReply::ProgMismatch{ low, high } => Err(RpcError::ProgMismatch{ low, high }),
+				AcceptedReply::ProcUnavail            => Err(RpcError::ProcUnavail),
+				AcceptedReply::GarbageArgs            => Err(RpcError::GarbageArgs),
+				AcceptedReply::SystemErr              => Err(RpcError::SystemErr),
+			},
+			ReplyBody::Denied(RejectedReply::RpcMismatch{ low, high }) => Err(RpcError::RpcMismatch{ low, high }),
+			ReplyBody::Denied(RejectedReply::AuthError(stat))          => Err(RpcError::AuthError(stat)),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::xdr::{to_bytes, from_bytes, XdrError};
+	use super::*;
+
+	fn words(ws:&[u32]) -> Vec<u8> { ws.iter().flat_map(|w| w.to_be_bytes()).collect() }
+
+	fn round_trip(body:ReplyBody, expected:&[u8]) {
+		let bytes:Vec<u8> = to_bytes(&body).unwrap();
+		assert_eq!(bytes, expected, "{:?}", body);
+		assert_eq!(from_bytes::<ReplyBody>(&bytes).unwrap(), body);
+	}
+
+	#[test]
+	fn every_accepted_reply() {
+		let verf = OpaqueAuth{ flavor: 2, body: vec![1, 2, 3] };
+		let verf_bytes:Vec<u8> = [words(&[2, 3]), vec![1, 2, 3, 0]].concat();
+		let accepted = |reply:AcceptedReply| ReplyBody::Accepted{ verf: verf.clone(), reply };
+
+		for (stat, reply) in [(0, AcceptedReply::Success), (1, AcceptedReply::ProgUnavail), (3, AcceptedReply::ProcUnavail),
+				(4, AcceptedReply::GarbageArgs), (5, AcceptedReply::SystemErr)] {
+			round_trip(accepted(reply), &[words(&[0]), verf_bytes.clone(), words(&[stat])].concat());
+		}
+		round_trip(accepted(AcceptedReply::ProgMismatch{ low: 1, high: 3 }), &[words(&[0]), verf_bytes, words(&[2, 1, 3])].concat());
+	}
+
+	#[test]
+	fn every_rejected_reply() {
+		round_trip(ReplyBody::Denied(RejectedReply::RpcMismatch{ low: 2, high: 2 }), &words(&[1, 0, 2, 2]));
+		for code in [0, 1, 2, 3, 4, 5, 6, 7, 13, 14] {
+			let stat = AuthStat::from_code(code);
+			assert_ne!(stat, AuthStat::Other(code));
+			round_trip(ReplyBody::Denied(RejectedReply::AuthError(stat)), &words(&[1, 1, code as u32]));
+		}
+	}
+
+	#[test]
+	fn unknown_auth_stat_is_an_auth_error() {
+		let body:ReplyBody = from_bytes(&words(&[1, 1, 9])).unwrap();
+		assert_eq!(body.clone().into_result(), Err(RpcError::AuthError(AuthStat::Other(9))));
+		assert_eq!(to_bytes(&body).unwrap(), words(&[1, 1, 9]));
+	}
+
+	#[test]
+	fn unknown_reply_stats_are_still_invalid() {
+		assert!(matches!(from_bytes::<ReplyBody>(&words(&[2])), Err(XdrError::InvalidDiscriminant{ value: 2, .. })));
+		assert!(matches!(from_bytes::<ReplyBody>(&words(&[1, 2])), Err(XdrError::InvalidDiscriminant{ value: 2, .. })));
+		assert!(matches!(from_bytes::<ReplyBody>(&words(&[0, 0, 0, 6])), Err(XdrError::InvalidDiscriminant{ value: 6, .. })));
+	}
+
+	#[test]
+	fn into_result_for_every_arm() {
+		let verf = OpaqueAuth{ flavor: 1, body: vec![9; 8] };
+		let accepted = |reply:AcceptedReply| ReplyBody::Accepted{ verf: verf.clone(), reply };
+
+		assert_eq!(ReplyBody::success(verf.clone()).into_result(), Ok(verf.clone()));
+		assert_eq!(accepted(AcceptedReply::ProgUnavail).into_result(), Err(RpcError::ProgUnavail));
+		assert_eq!(accepted(AcceptedReply::ProgMismatch{ low: 1, high: 2 }).into_result(), Err(RpcError::ProgMismatch{ low: 1, high: 2 }));
+		assert_eq!(accepted(AcceptedReply::ProcUnavail).into_result(), Err(RpcError::ProcUnavail));
+		assert_eq!(accepted(AcceptedReply::GarbageArgs).into_result(), Err(RpcError::GarbageArgs));
+		assert_eq!(accepted(AcceptedReply::SystemErr).into_result(), Err(RpcError::SystemErr));
+		assert_eq!(ReplyBody::Denied(RejectedReply::RpcMismatch{ low: 2, high: 2 }).into_result(), Err(RpcError::RpcMismatch{ low: 2, high: 2 }));
+		assert_eq!(ReplyBody::Denied(RejectedReply::AuthError(AuthStat::TooWeak)).into_result(), Err(RpcError::AuthError(AuthStat::TooWeak)));
+	}
+
+	#[test]
+	fn auth_bodies_are_bounded() {
+		let at_limit = OpaqueAuth{ flavor: 1, body: vec![0; MAX_AUTH_BYTES as usize] };
+		let bytes:Vec<u8> = to_bytes(&at_limit).unwrap();
+		assert_eq!(from_bytes::<OpaqueAuth>(&bytes).unwrap(), at_limit);
+
+		let over = OpaqueAuth{ flavor: 1, body: vec![0; MAX_AUTH_BYTES as usize + 1] };
+		assert!(to_bytes(&over).is_err());
+
+		let mut bytes:Vec<u8> = words(&[1, MAX_AUTH_BYTES + 4]);
+		bytes.resize(bytes.len() + MAX_AUTH_BYTES as usize + 4, 0);
+		assert!(from_bytes::<OpaqueAuth>(&bytes).is_err());
+	}
+}
@@ -0,0 +1,230 @@
+
+// rpcbind, the successor to the portmapper (RFC 1833).  It listens on the same port and program number, but
+// versions 3 and 4 describe services with a netid and a universal address instead of a protocol and port.
+
+pub const RPCBPROG:u32  = 100000;
+pub const RPCBVERS:u32  = 3;
+pub const RPCBVERS4:u32 = 4;
+
+pub const RPCBPROC_SET:u32         = 1;     // (rpcb) -> bool
+pub const RPCBPROC_UNSET:u32       = 2;     // (rpcb) -> bool
+pub const RPCBPROC_GETADDR:u32     = 3;     // (rpcb) -> string
+pub const RPCBPROC_DUMP:u32        = 4;     // (void) -> rpcblist_ptr
+pub const RPCBPROC_GETTIME:u32     = 6;     // (void) -> unsigned int
+pub const RPCBPROC_GETADDRLIST:u32 = 11;    // (rpcb) -> rpcb_entry_list_ptr, version 4 only
+
+use std::io::{self, Error, ErrorKind};
+use std::net::{IpAddr, SocketAddr};
+
+use serde::{Serialize, Deserialize};
+
+use crate::xdr::{self, Unpacker};
+use super::xdr_unpack;
+use super::port_mapping::PMAP_PORT;
+use super::client::RpcClient;
+use super::transport::RpcTransport;
+use super::tcp_clients::{TcpClient, TcpTransport};
+#[cfg(feature = "async")]
+use super::async_clients::AsyncTcpClient;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rpcb {
+	pub program: u32,
+	pub version: u32,
+	pub netid: String,      // For example "tcp", "udp", "tcp6" or "udp6"
+	pub addr: String,       // Universal address
+	pub owner: String,
+}
+
+impl Rpcb {
+	// The argument for GETADDR and GETADDRLIST, where only the program, version and netid matter
+	pub fn query(program:u32, version:u32, netid:&str) -> Self {
+		Self{ program, version, netid: netid.to_owned(), addr: String::new(), owner: String::new() }
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RpcbEntry {
+	pub maddr: String,
+	pub netid: String,
+	pub semantics: u32,     // 1 for connectionless, 2 for connection-oriented, 3 for connection-oriented with orderly release
+	pub protofmly: String,
+	pub proto: String,
+}
+
+// Universal addresses are the IP address in its usual text form followed by the port as two more decimal
+// numbers for the high and low bytes, so port 111 on 10.0.0.5 is "10.0.0.5.0.111"
+pub fn parse_uaddr(uaddr:&str) -> io::Result<SocketAddr> {
+	let invalid = || Error::new(ErrorKind::InvalidData, format!("Invalid universal address {:?}", uaddr));
+
+	let mut parts = uaddr.rsplitn(3, '.');
+	let lo:u8 = parts.next().and_then(|s| s.parse().ok()).ok_or_else(invalid)?;
+	let hi:u8 = parts.next().and_then(|s| s.parse().ok()).ok_or_else(invalid)?;
+	let ip:IpAddr = parts.next().and_then(|s| s.parse().ok()).ok_or_else(invalid)?;
+
+	Ok(SocketAddr::new(ip, u16::from_be_bytes([hi, lo])))
+}
+
+pub fn to_uaddr(addr:&SocketAddr) -> String {
+	let [hi, lo] = addr.port().to_be_bytes();
+	format!("{}.{}.{}", addr.ip(), hi, lo)
+}
+
+// GETADDR answers with an empty address for programs that aren't registered
+fn registered(uaddr:String) -> Option<String> {
+	if uaddr.is_empty() { None } else { Some(uaddr) }
+}
+
+// Client for rpcbind versions 3 and 4 over any transport, normally TCP
+pub struct RpcbindClient<T: RpcTransport = TcpTransport> {
+	pub host: String,
+	pub client: RpcClient<T>,
+}
+
+impl RpcbindClient<TcpTransport> {
+
+	// Connects over TCP to version 4 on the standard port
+	pub fn new(host:&str) -> io::Result<Self> {
+		Self::tcp(host, PMAP_PORT, RPCBVERS4)
+	}
+
+	pub fn tcp(host:&str, port:u16, vers:u32) -> io::Result<Self> {
+		let client = TcpClient::connect((host, port), RPCBPROG, vers)?;
+		Ok(Self{ host: host.to_owned(), client })
+	}
+}
+
+impl<T: RpcTransport> RpcbindClient<T> {
+
+	pub fn with_transport(host:&str, transport:T, vers:u32) -> Self {
+		Self{ host: host.to_owned(), client: RpcClient::new(transport, RPCBPROG, vers) }
+	}
+
+	fn call(&mut self, prc:u32, arg:Option<&Rpcb>) -> io::Result<&mut Unpacker> {
+		self.client.start_call(prc)?;
+		if let Some(arg) = arg { self.client.packer.pack(arg)?; }
+		self.client.do_call()?;
+		Ok(&mut self.client.unpacker)
+	}
+
+	pub fn set(&mut self, rpcb:&Rpcb) -> io::Result<bool> {
+		Ok(self.call(RPCBPROC_SET, Some(rpcb))?.unpack_bool()?)
+	}
+
+	pub fn unset(&mut self, rpcb:&Rpcb) -> io::Result<bool> {
+		Ok(self.call(RPCBPROC_UNSET, Some(rpcb))?.unpack_bool()?)
+	}
+
+	// Returns the universal address of a program, or None if it isn't registered
+	pub fn get_addr(&mut self, program:u32, version:u32, netid:&str) -> io::Result<Option<String>> {
+		let uaddr:String = self.call(RPCBPROC_GETADDR, Some(&Rpcb::query(program, version, netid)))?.unpack_string(xdr::UNBOUNDED)?;
+		Ok(registered(uaddr))
+	}
+
+	pub fn dump(&mut self) -> io::Result<Vec<Rpcb>> {
+		Ok(xdr_unpack::unpack_list(self.call(RPCBPROC_DUMP, None)?)?)
+	}
+
+	// Seconds since the Unix epoch on the server
+	pub fn get_time(&mut self) -> io::Result<u32> {
+		Ok(self.call(RPCBPROC_GETTIME, None)?.unpack_u32()?)
+	}
+
+	// Every address a program is reachable at for a given netid, version 4 only
+	pub fn get_addr_list(&mut self, program:u32, version:u32, netid:&str) -> io::Result<Vec<RpcbEntry>> {
+		Ok(xdr_unpack::unpack_list(self.call(RPCBPROC_GETADDRLIST, Some(&Rpcb::query(program, version, netid)))?)?)
+	}
+}
+
+// Async version of RpcbindClient over TCP, so far only for looking up addresses
+#[cfg(feature = "async")]
+pub struct AsyncRpcbindClient {
+	pub host: String,
+	pub client: AsyncTcpClient,
+}
+
+#[cfg(feature = "async")]
+impl AsyncRpcbindClient {
+
+	pub async fn new(host:&str) -> io::Result<Self> {
+		Self::tcp(host, PMAP_PORT, RPCBVERS4).await
+	}
+
+	pub async fn tcp(host:&str, port:u16, vers:u32) -> io::Result<Self> {
+		let client = AsyncTcpClient::connect((host, port), RPCBPROG, vers).await?;
+		Ok(Self{ host: host.to_owned(), client })
+	}
+
+	pub async fn get_addr(&mut self, program:u32, version:u32, netid:&str) -> io::Result<Option<String>> {
+		self.client.start_call(RPCBPROC_GETADDR)?;
+		self.client.packer.pack(&Rpcb::query(program, version, netid))?;
+		self.client.do_call().await?;
+		Ok(registered(self.client.unpacker.unpack_string(xdr::UNBOUNDED)?))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::net::{Ipv4Addr, Ipv6Addr};
+	use std::sync::Arc;
+
+	use crate::xdr::Packer;
+	use crate::rpc::xdr_pack;
+	use crate::rpc::server::{Server, CallInfo, Outcome};
+	use crate::rpc::transport::LoopbackTransport;
+	use super::*;
+
+	#[test]
+	fn ipv4_uaddrs() {
+		let addr = SocketAddr::from((Ipv4Addr::new(10, 0, 0, 5), 111));
+		assert_eq!(to_uaddr(&addr), "10.0.0.5.0.111");
+		assert_eq!(parse_uaddr("10.0.0.5.0.111").unwrap(), addr);
+		assert_eq!(parse_uaddr("192.168.1.20.4.1").unwrap(), SocketAddr::from((Ipv4Addr::new(192, 168, 1, 20), 1025)));
+	}
+
+	#[test]
+	fn ipv6_uaddrs() {
+		let addr = SocketAddr::from((Ipv6Addr::LOCALHOST, 1025));
+		assert_eq!(to_uaddr(&addr), "::1.4.1");
+		assert_eq!(parse_uaddr("::1.4.1").unwrap(), addr);
+
+		let mapped:Ipv6Addr = Ipv4Addr::new(10, 0, 0, 5).to_ipv6_mapped();
+		assert_eq!(parse_uaddr("::ffff:10.0.0.5.0.111").unwrap(), SocketAddr::from((mapped, 111)));
+		for port in [0, 1, 255, 256, 65535] {
+			let addr = SocketAddr::from((mapped, port));
+			assert_eq!(parse_uaddr(&to_uaddr(&addr)).unwrap(), addr);
+		}
+	}
+
+	#[test]
+	fn malformed_uaddrs() {
+		for uaddr in ["", "10.0.0.5", "10.0.0.5.111", "10.0.0.5.256.1", "10.0.0.5.0.-1", "10.0.0.5.a.1", "instrument.0.111", "10.0.0.5.0.111.", "::1"] {
+			let e = parse_uaddr(uaddr).unwrap_err();
+			assert_eq!(e.kind(), ErrorKind::InvalidData, "{:?}", uaddr);
+		}
+	}
+
+	#[test]
+	fn get_addr_and_dump_over_any_transport() {
+		let registered = Rpcb{ program: 0x0607af, version: 1, netid: "tcp".to_owned(), addr: "127.0.0.1.4.1".to_owned(), owner: "superuser".to_owned() };
+
+		let mut server = Server::new();
+		let known:Rpcb = registered.clone();
+		server.register(RPCBPROG, RPCBVERS, RPCBPROC_GETADDR, move |_:&CallInfo, args:&mut Unpacker, results:&mut Packer| {
+			let query:Rpcb = args.unpack()?;
+			let found:bool = (query.program, query.version, &query.netid) == (known.program, known.version, &known.netid);
+			results.pack_string(if found { &known.addr } else { "" }, xdr::UNBOUNDED)?;
+			Ok(Outcome::Reply)
+		});
+		let known:Rpcb = registered.clone();
+		server.register(RPCBPROG, RPCBVERS, RPCBPROC_DUMP, move |_:&CallInfo, _:&mut Unpacker, results:&mut Packer| {
+			xdr_pack::pack_list(results, std::slice::from_ref(&known))?;
+			Ok(Outcome::Reply)
+		});
+
+		let mut client = RpcbindClient::with_transport("instrument", LoopbackTransport::new(Arc::new(server)), RPCBVERS);
+		assert_eq!(client.get_addr(0x0607af, 1, "tcp").unwrap(), Some(registered.addr.clone()));
+		assert_eq!(client.get_addr(0x0607af, 1, "udp").unwrap(), None);
+		assert_eq!(client.dump().unwrap(), vec![registered]);
+	}
+}
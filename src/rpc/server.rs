@@ -0,0 +1,350 @@
+
+use std::collections::HashMap;
+use std::io::{self, ErrorKind};
+use std::net::{TcpListener, TcpStream, UdpSocket, SocketAddr, ToSocketAddrs};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use crate::xdr::{Packer, Unpacker};
+use super::{CALL, RPCVERSION, xdr_pack, xdr_unpack};
+use super::reply::{OpaqueAuth, ReplyBody, AcceptedReply, RejectedReply};
+use super::record::RecordCodec;
+
+// Largest call record accepted over TCP, so a bogus record mark can't make the server allocate gigabytes
+pub const MAX_CALL_SIZE:usize = 1 << 24;
+
+// Largest possible UDP datagram
+const UDP_BUFF_SIZE:usize = 65535;
+
+// Connections with no call for this long are closed so idle clients don't hold on to a thread each
+pub const IDLE_TIMEOUT:Duration = Duration::from_secs(120);
+
+// Most connections served at once, each on its own thread.  Connections past this are closed as soon as they're
+// accepted.
+pub const MAX_CONNECTIONS:usize = 64;
+
+// Pause after a failed accept, which is often a shortage of file descriptors that takes a moment to clear
+const ACCEPT_RETRY_DELAY:Duration = Duration::from_millis(100);
+
+// Everything in a call header, plus where the call came from
+#[derive(Debug, Clone)]
+pub struct CallInfo {
+	pub xid: u32,
+	pub prog: u32,
+	pub vers: u32,
+	pub prc: u32,
+	pub cred: OpaqueAuth,
+	pub verf: OpaqueAuth,
+	pub peer: SocketAddr,
+}
+
+pub enum Outcome {
+	Reply,                     // The results have been packed and should be sent back
+	NoReply,                   // Send nothing back, for example for batched calls or ignored broadcasts
+	Denied(RejectedReply),     // Reject the call, typically with an AUTH_ERROR
+}
+
+// A handler unpacks the arguments from the unpacker and packs the results into the packer.  Returning an error with
+// kind InvalidData or UnexpectedEof (which is what XDR errors convert to) sends GARBAGE_ARGS, anything else sends
+// SYSTEM_ERR, as does leaving arguments unread.
+pub trait Handler: Send + Sync {
+	fn call(&self, call:&CallInfo, args:&mut Unpacker, results:&mut Packer) -> io::Result<Outcome>;
+}
+
+impl<F> Handler for F where F: Fn(&CallInfo, &mut Unpacker, &mut Packer) -> io::Result<Outcome> + Send + Sync {
+	fn call(&self, call:&CallInfo, args:&mut Unpacker, results:&mut Packer) -> io::Result<Outcome> { self(call, args, results) }
+}
+
+// Dispatches calls to handlers registered by (prog, vers, proc).  Procedure 0 (NULL) is answered automatically for
+// every registered program version unless a handler is registered for it.
+#[derive(Default)]
+pub struct Server {
+	handlers: HashMap<(u32, u32, u32), Arc<dyn Handler>>,
+}
+
+impl Server {
+
+	pub fn new() -> Self { Self::default() }
+
+	pub fn register<H: Handler + 'static>(&mut self, prog:u32, vers:u32, prc:u32, handler:H) {
+		self.handlers.insert((prog, vers, prc), Arc::new(handler));
+	}
+
+	fn versions(&self, prog:u32) -> Option<(u32, u32)> {
+		let vers = self.handlers.keys().filter(|(p, _, _)| *p == prog).map(|(_, v, _)| *v);
+		Some((vers.clone().min()?, vers.max()?))
+	}
+
+	// Takes a call message and returns the reply message, or None if nothing should be sent back.  Messages that
+	// aren't well-formed calls are dropped since there's no reliable way to answer them.  The message is unpacked
+	// where it is rather than copied.
+	pub fn dispatch(&self, msg:Vec<u8>, peer:SocketAddr) -> Option<Vec<u8>> {
+		let mut unpacker = Unpacker::new();
+		unpacker.reset_owned(msg);
+		self.dispatch_unpacker(&mut unpacker, peer)
+	}
+
+	// Same as dispatch for a message already in an unpacker, which serve_udp reuses for every datagram
+	fn dispatch_unpacker(&self, unpacker:&mut Unpacker, peer:SocketAddr) -> Option<Vec<u8>> {
+		let xid:u32 = unpacker.unpack_u32().ok()?;
+		if unpacker.unpack_enum().ok()? != CALL { return None; }
+
+		let rpcvers:u32 = unpacker.unpack_u32().ok()?;
+		if rpcvers != RPCVERSION {
+			return reply(xid, &ReplyBody::Denied(RejectedReply::RpcMismatch{ low: RPCVERSION, high: RPCVERSION }), &[]);
+		}
+
+		let prog:u32 = unpacker.unpack_u32().ok()?;
+		let vers:u32 = unpacker.unpack_u32().ok()?;
+		let prc:u32  = unpacker.unpack_u32().ok()?;
+		let cred:OpaqueAuth = xdr_unpack::unpack_auth(unpacker).ok()?;
+		let verf:OpaqueAuth = xdr_unpack::unpack_auth(unpacker).ok()?;
+		let call = CallInfo{ xid, prog, vers, prc, cred, verf, peer };
+
+		let accepted = |reply:AcceptedReply| ReplyBody::Accepted{ verf: OpaqueAuth::none(), reply };
+
+		let (low, high) = match self.versions(prog) {
+			Some(range) => range,
+			None        => return reply(xid, &accepted(AcceptedReply::ProgUnavail), &[]),
+		};
+		if !self.handlers.keys().any(|(p, v, _)| *p == prog && *v == vers) {
+			return reply(xid, &accepted(AcceptedReply::ProgMismatch{ low, high }), &[]);
+		}
+
+		let handler:Arc<dyn Handler> = match self.handlers.get(&(prog, vers, prc)) {
+			Some(handler) => handler.clone(),
+			None if prc == 0 => return reply(xid, &accepted(AcceptedReply::Success), &[]),
+			None => return reply(xid, &accepted(AcceptedReply::ProcUnavail), &[]),
+		};
+
+		let mut results = Packer::new();
+		match handler.call(&call, unpacker, &mut results) {
+			Ok(Outcome::Reply) if !unpacker.all_data_consumed() => reply(xid, &accepted(AcceptedReply::GarbageArgs), &[]),
+			Ok(Outcome::Reply)          => reply(xid, &accepted(AcceptedReply::Success), &results.buff),
+			Ok(Outcome::NoReply)        => None,
+			Ok(Outcome::Denied(reason)) => reply(xid, &ReplyBody::Denied(reason), &[]),
+			Err(e) => match e.kind() {
+				ErrorKind::InvalidData | ErrorKind::UnexpectedEof => reply(xid, &accepted(AcceptedReply::GarbageArgs), &[]),
+				_                                                 => reply(xid, &accepted(AcceptedReply::SystemErr), &[]),
+			},
+		}
+	}
+
+	// Accepts connections forever, handling each one on its own thread, up to MAX_CONNECTIONS at once.  A failed
+	// accept (an aborted connection, or running out of file descriptors) only costs that connection.
+	pub fn serve_tcp(self:&Arc<Self>, listener:TcpListener) {
+		let active:Arc<AtomicUsize> = Arc::new(AtomicUsize::new(0));
+		loop {
+			let (stream, peer) = match listener.accept() {
+				Ok(accepted) => accepted,
+				Err(_)       => { thread::sleep(ACCEPT_RETRY_DELAY); continue; },
+			};
+
+			// Dropping the stream closes it
+			if active.load(Ordering::SeqCst) >= MAX_CONNECTIONS { continue; }
+
+			let slot = ConnectionSlot::take(&active);
+			let server:Arc<Self> = self.clone();
+			thread::spawn(move || {
+				let _slot = slot;
+				server.serve_connection(stream, peer)
+			});
+		}
+	}
+
+	// Answers calls on a single connection until the client hangs up, goes quiet for IDLE_TIMEOUT or sends
+	// something malformed
+	fn serve_connection(&self, mut stream:TcpStream, peer:SocketAddr) -> io::Result<()> {
+		stream.set_read_timeout(Some(IDLE_TIMEOUT))?;
+		let codec = RecordCodec{ max_record: MAX_CALL_SIZE, ..RecordCodec::default() };
+		loop {
+			let call:Vec<u8> = match codec.read_record(&mut stream) {
+				Ok(call) => call,
+				Err(ref e) if matches!(e.kind(), ErrorKind::UnexpectedEof | ErrorKind::WouldBlock | ErrorKind::TimedOut) => return Ok(()),
+				Err(e) => return Err(e),
+			};
+
+			if let Some(reply) = self.dispatch(call, peer) {
+				codec.write_record(&mut stream, &reply)?;
+			}
+		}
+	}
+
+	// Answers datagrams forever.  Errors only affect one datagram, for example when an ICMP port unreachable from
+	// an earlier reply turns up as an error on the next receive, so they're skipped.
+	pub fn serve_udp(&self, socket:&UdpSocket) {
+		let mut unpacker = Unpacker::new();
+		let mut buff:Vec<u8> = vec![0; UDP_BUFF_SIZE];
+		loop {
+			let (n, peer) = match socket.recv_from(&mut buff) {
+				Ok(received) => received,
+				Err(_)       => continue,
+			};
+
+			// The datagram is unpacked in the receive buffer, which comes back for the next one
+			buff.truncate(n);
+			unpacker.reset_owned(buff);
+			let reply:Option<Vec<u8>> = self.dispatch_unpacker(&mut unpacker, peer);
+			buff = unpacker.take_buffer();
+			buff.resize(UDP_BUFF_SIZE, 0);
+
+			if let Some(reply) = reply {
+				let _ = socket.send_to(&reply, peer);
+			}
+		}
+	}
+
+	// Binds a TCP listener and serves it on a background thread.  Returns the address actually bound, which is
+	// handy when binding to port 0.
+	pub fn spawn_tcp<A: ToSocketAddrs>(self:&Arc<Self>, addr:A) -> io::Result<SocketAddr> {
+		let listener = TcpListener::bind(addr)?;
+		let local:SocketAddr = listener.local_addr()?;
+		let server:Arc<Self> = self.clone();
+		thread::spawn(move || server.serve_tcp(listener));
+		Ok(local)
+	}
+
+	pub fn spawn_udp<A: ToSocketAddrs>(self:&Arc<Self>, addr:A) -> io::Result<SocketAddr> {
+		let socket = UdpSocket::bind(addr)?;
+		let local:SocketAddr = socket.local_addr()?;
+		let server:Arc<Self> = self.clone();
+		thread::spawn(move || server.serve_udp(&socket));
+		Ok(local)
+	}
+}
+
+// Counts a connection as active for as long as it's kept
+struct ConnectionSlot(Arc<AtomicUsize>);
+
+impl ConnectionSlot {
+	fn take(active:&Arc<AtomicUsize>) -> Self {
+		active.fetch_add(1, Ordering::SeqCst);
+		Self(active.clone())
+	}
+}
+
+impl Drop for ConnectionSlot {
+	fn drop(&mut self) { self.0.fetch_sub(1, Ordering::SeqCst); }
+}
+
+fn reply(xid:u32, body:&ReplyBody, results:&[u8]) -> Option<Vec<u8>> {
+	let mut packer = Packer::new();
+	xdr_pack::pack_reply(&mut packer, xid, body).ok()?;
+	packer.buff.extend_from_slice(results);
+	Some(packer.buff)
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::Read;
+
+	use super::*;
+
+	const PROG:u32 = 0x20000002;
+
+	fn server() -> Server {
+		let mut server = Server::new();
+		for vers in [2, 4] {
+			server.register(PROG, vers, 1, |_:&CallInfo, args:&mut Unpacker, results:&mut Packer| {
+				let n:u32 = args.unpack_u32()?;
+				results.pack_u32(n + 1)?;
+				Ok(Outcome::Reply)
+			});
+		}
+		server
+	}
+
+	fn call(vers:u32, prc:u32, args:&[u8]) -> Vec<u8> {
+		let mut packer = Packer::new();
+		xdr_pack::pack_callheader(&mut packer, 9, PROG, vers, prc, &OpaqueAuth::none(), &OpaqueAuth::none()).unwrap();
+		packer.buff.extend_from_slice(args);
+		packer.buff
+	}
+
+	// The reply body and the results after it
+	fn answer(server:&Server, msg:Vec<u8>) -> (ReplyBody, Vec<u8>) {
+		let mut unpacker = Unpacker::new();
+		unpacker.reset_owned(server.dispatch(msg, SocketAddr::from(([127, 0, 0, 1], 0))).unwrap());
+		let (xid, body) = xdr_unpack::unpack_reply(&mut unpacker).unwrap();
+		assert_eq!(xid, 9);
+		(body, unpacker.take_remaining())
+	}
+
+	fn accepted(reply:AcceptedReply) -> ReplyBody { ReplyBody::Accepted{ verf: OpaqueAuth::none(), reply } }
+
+	#[test]
+	fn success() {
+		assert_eq!(answer(&server(), call(4, 1, &[0, 0, 0, 41])), (accepted(AcceptedReply::Success), vec![0, 0, 0, 42]));
+	}
+
+	#[test]
+	fn null_procedure_is_answered_for_every_version() {
+		assert_eq!(answer(&server(), call(2, 0, &[])), (accepted(AcceptedReply::Success), vec![]));
+	}
+
+	#[test]
+	fn unknown_program() {
+		let mut msg:Vec<u8> = call(2, 1, &[]);
+		msg[12..16].copy_from_slice(&(PROG + 1).to_be_bytes());
+		assert_eq!(answer(&server(), msg).0, accepted(AcceptedReply::ProgUnavail));
+	}
+
+	#[test]
+	fn unknown_version_gets_the_range() {
+		assert_eq!(answer(&server(), call(3, 1, &[])).0, accepted(AcceptedReply::ProgMismatch{ low: 2, high: 4 }));
+	}
+
+	#[test]
+	fn unknown_procedure() {
+		assert_eq!(answer(&server(), call(2, 2, &[])).0, accepted(AcceptedReply::ProcUnavail));
+	}
+
+	#[test]
+	fn arguments_too_short_or_too_long() {
+		assert_eq!(answer(&server(), call(2, 1, &[0, 0])).0, accepted(AcceptedReply::GarbageArgs));
+		assert_eq!(answer(&server(), call(2, 1, &[0, 0, 0, 1, 0, 0, 0, 2])).0, accepted(AcceptedReply::GarbageArgs));
+	}
+
+	#[test]
+	fn wrong_rpc_version() {
+		let mut msg:Vec<u8> = call(2, 1, &[0, 0, 0, 1]);
+		msg[8..12].copy_from_slice(&3u32.to_be_bytes());
+		assert_eq!(answer(&server(), msg).0, ReplyBody::Denied(RejectedReply::RpcMismatch{ low: RPCVERSION, high: RPCVERSION }));
+	}
+
+	#[test]
+	fn replies_aren_t_answered() {
+		let mut msg:Vec<u8> = call(2, 1, &[0, 0, 0, 1]);
+		msg[4..8].copy_from_slice(&1u32.to_be_bytes());
+		assert!(server().dispatch(msg, SocketAddr::from(([127, 0, 0, 1], 0))).is_none());
+	}
+
+	#[test]
+	fn udp_replies_from_the_reused_buffer() {
+		let addr:SocketAddr = Arc::new(server()).spawn_udp("127.0.0.1:0").unwrap();
+		let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+		socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+		// A long datagram followed by a short one, which mustn't see what was left of the first
+		let mut buff:Vec<u8> = vec![0; UDP_BUFF_SIZE];
+		for (args, result) in [(vec![0; 1000], AcceptedReply::GarbageArgs), (vec![0, 0, 0, 1], AcceptedReply::Success)] {
+			socket.send_to(&call(2, 1, &args), addr).unwrap();
+			let n:usize = socket.recv(&mut buff).unwrap();
+			let mut unpacker = Unpacker::new();
+			unpacker.reset(&buff[..n]);
+			assert_eq!(xdr_unpack::unpack_reply(&mut unpacker).unwrap().1, accepted(result));
+		}
+	}
+
+	#[test]
+	fn connections_past_the_limit_are_closed() {
+		let addr:SocketAddr = Arc::new(server()).spawn_tcp("127.0.0.1:0").unwrap();
+		let _open:Vec<TcpStream> = (0..MAX_CONNECTIONS).map(|_| TcpStream::connect(addr).unwrap()).collect();
+
+		let mut extra = TcpStream::connect(addr).unwrap();
+		extra.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+		assert_eq!(extra.read(&mut [0; 4]).unwrap(), 0);
+	}
+}
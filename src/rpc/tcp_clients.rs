@@ -1,78 +1,212 @@
-
-extern crate byteorder;
-
-use std::io::{self, Read, Write, Error, ErrorKind};
+use std::io::{self, Error, Read};
 use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
 
-use byteorder::{BigEndian, WriteBytesExt, ReadBytesExt};
+use super::client::RpcClient;
+use super::record::RecordCodec;
+use super::transport::{RpcTransport, reply_xid, is_stale_xid};
 
-use crate::xdr;
-use super::xdr_unpack;
+const READ_CHUNK_SIZE:usize = 16 * 1024;
 
-pub struct TcpClient {
+// Record-marked TCP.  Replies are read into a buffer that outlives each call, so a read timeout part way through a
+// reply leaves the rest of it to be read (and skipped) by the next call rather than taken for the start of another.
+// Anything else that could leave the stream out of step, like a call only partly sent, breaks the transport for good.
+pub struct TcpTransport {
     pub stream: TcpStream,
-    pub prog: u32,
-    pub vers: u32,
-    pub lastxid: u32,
-	pub packer: xdr::Packer,
-	pub unpacker: xdr::Unpacker,
+	pub codec: RecordCodec,
+	recv_buff: Vec<u8>,
+	broken: bool,
 }
 
-impl TcpClient {
-	
-	pub fn connect<A: ToSocketAddrs>(addr: A, prog: u32, vers: u32) -> io::Result<Self> {
-		let packer = xdr::Packer::new();
-		let unpacker = xdr::Unpacker::new();
-		Ok(Self{ stream: TcpStream::connect(addr)?, prog, vers, lastxid: 0, packer, unpacker })
+impl TcpTransport {
+	pub fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+		Ok(Self::with_stream(TcpStream::connect(addr)?))
 	}
 
-	pub fn do_call(&mut self) -> io::Result<()> {
-		let call:Vec<u8> = self.packer.get_buf()?;
-		if call.len() > 0 {
-			let header:u32 = call.len() as u32 | 0x80000000;
+	pub fn with_stream(stream:TcpStream) -> Self {
+		Self{ stream, codec: RecordCodec::default(), recv_buff: vec![], broken: false }
+	}
 
-			let mut send_bytes:Vec<u8> = vec![];
-			send_bytes.write_u32::<BigEndian>(header)?;
-			send_bytes.extend_from_slice(&call);
-			self.stream.write_all(&send_bytes)?;
+	// Tries each address in turn like connect, giving up on each one after the timeout
+	pub fn connect_timeout<A: ToSocketAddrs>(addr: A, timeout: Duration) -> io::Result<Self> {
+		let mut last_err:Option<Error> = None;
+		for addr in addr.to_socket_addrs()? {
+			match TcpStream::connect_timeout(&addr, timeout) {
+				Ok(stream) => return Ok(Self::with_stream(stream)),
+				Err(e)     => last_err = Some(e),
+			}
 		}
+		Err(last_err.unwrap_or_else(|| Error::new(io::ErrorKind::InvalidInput, "Address didn't resolve to anything")))
+	}
 
-		'outer: loop {
-			let mut reply:Vec<u8> = vec![];
-
-			let mut last:bool = false;
-			while !last {
-				let x:u32 = self.stream.read_u32::<BigEndian>()?;
-
-		        last = (x & 0x80000000) != 0;
-		        let n:u32 = x & 0x7fffffff;
-
-		        let mut frag:Vec<u8> = Vec::with_capacity(n as usize);
-		        let mut buff:[u8; 4] = [0; 4];
-		        while frag.len() < n as usize {
-			        self.stream.read_exact(&mut buff)?;
-			        frag.extend_from_slice(&buff);		        	
-		        }
+	// Whether an earlier error left the stream out of step, after which every call fails and the only way on is a
+	// new connection
+	pub fn is_broken(&self) -> bool { self.broken }
 
-		        reply.append(&mut frag);
+	fn read_record(&mut self) -> io::Result<Vec<u8>> {
+		let mut chunk = [0u8; READ_CHUNK_SIZE];
+		loop {
+			if let Some((record, used)) = self.codec.decode(&self.recv_buff)? {
+				self.recv_buff.drain(..used);
+				return Ok(record);
 			}
 
-	        // Load the response into the unpacker and make sure the xid matches
-	        self.unpacker.reset(&reply);
+			let n:usize = self.stream.read(&mut chunk)?;
+			if n == 0 { return Err(Error::new(io::ErrorKind::UnexpectedEof, "Connection closed while waiting for reply")); }
+			self.recv_buff.extend_from_slice(&chunk[..n]);
+		}
+	}
+
+	fn exchange(&mut self, xid:u32, msg:&[u8]) -> io::Result<Vec<u8>> {
+		if let Err(e) = self.codec.write_record(&mut self.stream, msg) {
+			self.broken = true;
+			return Err(e);
+		}
 
-	        let (xid, _) = xdr_unpack::unpack_replyheader(&mut self.unpacker)?;
-	        if xid == self.lastxid {
+		loop {
+			// Running out the read timeout shows up as WouldBlock on some platforms.  Either way what's been read so
+			// far stays in the buffer, but any other error could have lost part of a record.
+			let reply:Vec<u8> = self.read_record().map_err(|e| match e.kind() {
+				io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => Error::new(io::ErrorKind::TimedOut, "No reply before the call timed out"),
+				io::ErrorKind::Interrupted => e,
+				_ => {
+					self.broken = true;
+					e
+				},
+			})?;
+			let reply_xid:u32 = reply_xid(&reply).ok_or_else(|| Error::new(io::ErrorKind::InvalidData, "Reply too short to hold an xid"))?;
+
+	        if reply_xid == xid {
 				// Packet from the present
-				return Ok(());
-	        } else if xid < self.lastxid {
+				return Ok(reply);
+	        } else if is_stale_xid(reply_xid, xid) {
 		        // Packet from the past
-		        continue 'outer;
+		        continue;
 	        } else {
 		        // Packet from the future?
-	        	return Err(Error::new(ErrorKind::Other, "Somehow got a packet from the future"));
+	        	return Err(Error::other("Somehow got a packet from the future"));
 	        }
 		}
+	}
+}
+
+impl RpcTransport for TcpTransport {
+
+	fn call(&mut self, xid:u32, msg:&[u8]) -> io::Result<Vec<u8>> {
+		if self.broken {
+			return Err(Error::new(io::ErrorKind::NotConnected, "Connection out of step after an earlier error"));
+		}
+		self.exchange(xid, msg)
+	}
 
+	fn set_timeout(&mut self, timeout:Option<Duration>) -> io::Result<()> {
+		self.stream.set_read_timeout(timeout)
 	}
+}
 
+pub type TcpClient = RpcClient<TcpTransport>;
+
+impl RpcClient<TcpTransport> {
+	pub fn connect<A: ToSocketAddrs>(addr: A, prog: u32, vers: u32) -> io::Result<Self> {
+		Ok(Self::new(TcpTransport::connect(addr)?, prog, vers))
+	}
+
+	pub fn connect_timeout<A: ToSocketAddrs>(addr: A, prog: u32, vers: u32, timeout: Duration) -> io::Result<Self> {
+		Ok(Self::new(TcpTransport::connect_timeout(addr, timeout)?, prog, vers))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::Write;
+	use std::net::TcpListener;
+	use std::thread;
+
+	use crate::xdr::Packer;
+	use crate::rpc::xdr_pack;
+	use crate::rpc::reply::OpaqueAuth;
+	use super::*;
+
+	fn reply(xid:u32, result:u32) -> Vec<u8> {
+		let mut packer = Packer::new();
+		xdr_pack::pack_replyheader(&mut packer, xid, &OpaqueAuth::none()).unwrap();
+		packer.pack_u32(result).unwrap();
+		packer.buff
+	}
+
+	#[test]
+	fn stale_replies_are_skipped_across_the_xid_wrapping() {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let server = thread::spawn(move || {
+			let (mut stream, _) = listener.accept().unwrap();
+			let codec = RecordCodec::default();
+			let call:Vec<u8> = codec.read_record(&mut stream).unwrap();
+			assert_eq!(reply_xid(&call), Some(0));
+
+			// A late reply to the call before the wrap, then the real one
+			codec.write_record(&mut stream, &reply(u32::MAX, 1)).unwrap();
+			codec.write_record(&mut stream, &reply(0, 2)).unwrap();
+		});
+
+		let mut client = TcpClient::connect(addr, 1, 1).unwrap();
+		client.lastxid = u32::MAX;
+		client.start_call(1).unwrap();
+		client.do_call().unwrap();
+		assert_eq!(client.unpacker.unpack_u32().unwrap(), 2);
+		server.join().unwrap();
+	}
+
+	#[test]
+	fn a_reply_cut_off_by_the_timeout_is_finished_and_skipped_by_the_next_call() {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let server = thread::spawn(move || {
+			let (mut stream, _) = listener.accept().unwrap();
+			let codec = RecordCodec::default();
+			let call:Vec<u8> = codec.read_record(&mut stream).unwrap();
+
+			// Half the first reply, then the rest once the client has given up on it
+			let first:Vec<u8> = codec.encode(&reply(reply_xid(&call).unwrap(), 1));
+			stream.write_all(&first[..10]).unwrap();
+			thread::sleep(Duration::from_millis(300));
+			stream.write_all(&first[10..]).unwrap();
+
+			let call:Vec<u8> = codec.read_record(&mut stream).unwrap();
+			codec.write_record(&mut stream, &reply(reply_xid(&call).unwrap(), 2)).unwrap();
+		});
+
+		let mut client = TcpClient::connect(addr, 1, 1).unwrap();
+		client.transport.set_timeout(Some(Duration::from_millis(100))).unwrap();
+		client.start_call(1).unwrap();
+		assert_eq!(client.do_call().unwrap_err().kind(), io::ErrorKind::TimedOut);
+		assert!(!client.transport.is_broken());
+
+		client.transport.set_timeout(Some(Duration::from_secs(5))).unwrap();
+		client.start_call(1).unwrap();
+		client.do_call().unwrap();
+		assert_eq!(client.unpacker.unpack_u32().unwrap(), 2);
+		server.join().unwrap();
+	}
+
+	#[test]
+	fn a_connection_closed_mid_reply_breaks_the_transport() {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let server = thread::spawn(move || {
+			let (mut stream, _) = listener.accept().unwrap();
+			let codec = RecordCodec::default();
+			let call:Vec<u8> = codec.read_record(&mut stream).unwrap();
+			stream.write_all(&codec.encode(&reply(reply_xid(&call).unwrap(), 1))[..10]).unwrap();
+		});
+
+		let mut client = TcpClient::connect(addr, 1, 1).unwrap();
+		client.start_call(1).unwrap();
+		assert_eq!(client.do_call().unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+		assert!(client.transport.is_broken());
+
+		client.start_call(1).unwrap();
+		assert_eq!(client.do_call().unwrap_err().kind(), io::ErrorKind::NotConnected);
+		server.join().unwrap();
+	}
 }
@@ -0,0 +1,110 @@
+
+use std::io::{self, Error, ErrorKind};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::mpsc::{self, Sender, Receiver};
+use std::time::Duration;
+
+use super::server::Server;
+
+// Moves call messages to a server and brings back the replies.  Implementations take care of framing, retransmission
+// and discarding replies meant for other calls, while rpc::client::RpcClient takes care of everything in the messages.
+pub trait RpcTransport {
+	// Sends a complete call message with the given xid and returns the reply message with the same xid
+	fn call(&mut self, xid:u32, msg:&[u8]) -> io::Result<Vec<u8>>;
+
+	// Longest to wait for each reply, where None waits forever.  Transports that can't time out, or that have timeout
+	// settings of their own like UdpTransport, ignore it.
+	fn set_timeout(&mut self, _timeout:Option<Duration>) -> io::Result<()> { Ok(()) }
+}
+
+impl<T: RpcTransport + ?Sized> RpcTransport for Box<T> {
+	fn call(&mut self, xid:u32, msg:&[u8]) -> io::Result<Vec<u8>> { (**self).call(xid, msg) }
+	fn set_timeout(&mut self, timeout:Option<Duration>) -> io::Result<()> { (**self).set_timeout(timeout) }
+}
+
+pub(crate) fn reply_xid(reply:&[u8]) -> Option<u32> {
+	reply.get(0..4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+// Whether a reply's xid comes before the xid of the call being waited on, allowing for xids wrapping around
+pub(crate) fn is_stale_xid(reply_xid:u32, xid:u32) -> bool {
+	(reply_xid.wrapping_sub(xid) as i32) < 0
+}
+
+// Hands calls straight to a Server in the same process, for simulators and tests with no sockets involved
+pub struct LoopbackTransport {
+	pub server: Arc<Server>,
+	pub peer: SocketAddr,       // What the server sees as the caller's address
+}
+
+impl LoopbackTransport {
+	pub fn new(server:Arc<Server>) -> Self {
+		Self{ server, peer: SocketAddr::from(([127, 0, 0, 1], 0)) }
+	}
+}
+
+impl RpcTransport for LoopbackTransport {
+	fn call(&mut self, _xid:u32, msg:&[u8]) -> io::Result<Vec<u8>> {
+		self.server.dispatch(msg.to_vec(), self.peer).ok_or_else(|| Error::new(ErrorKind::TimedOut, "Server didn't reply to the call"))
+	}
+}
+
+// Sends calls over a channel to whatever holds the other end, which can check the exact bytes of each call and
+// send back scripted replies
+pub struct ChannelTransport {
+	calls: Sender<Vec<u8>>,
+	replies: Receiver<Vec<u8>>,
+	pub timeout: Option<Duration>,
+}
+
+pub struct ChannelPeer {
+	pub calls: Receiver<Vec<u8>>,
+	pub replies: Sender<Vec<u8>>,
+}
+
+impl ChannelTransport {
+	pub fn pair() -> (Self, ChannelPeer) {
+		let (call_tx, call_rx) = mpsc::channel();
+		let (reply_tx, reply_rx) = mpsc::channel();
+		(Self{ calls: call_tx, replies: reply_rx, timeout: None }, ChannelPeer{ calls: call_rx, replies: reply_tx })
+	}
+}
+
+impl RpcTransport for ChannelTransport {
+	fn call(&mut self, xid:u32, msg:&[u8]) -> io::Result<Vec<u8>> {
+		let hung_up = || Error::new(ErrorKind::BrokenPipe, "Other end of the channel hung up");
+
+		self.calls.send(msg.to_vec()).map_err(|_| hung_up())?;
+		loop {
+			let reply:Vec<u8> = match self.timeout {
+				Some(t) => self.replies.recv_timeout(t).map_err(|e| match e {
+					mpsc::RecvTimeoutError::Timeout      => Error::new(ErrorKind::TimedOut, "No reply before the call timed out"),
+					mpsc::RecvTimeoutError::Disconnected => hung_up(),
+				})?,
+				None => self.replies.recv().map_err(|_| hung_up())?,
+			};
+			if reply_xid(&reply) == Some(xid) { return Ok(reply); }
+		}
+	}
+
+	fn set_timeout(&mut self, timeout:Option<Duration>) -> io::Result<()> {
+		self.timeout = timeout;
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn stale_xids_wrap_around() {
+		assert!(is_stale_xid(1, 2));
+		assert!(!is_stale_xid(2, 2));
+		assert!(!is_stale_xid(3, 2));
+		assert!(is_stale_xid(u32::MAX, 0));
+		assert!(is_stale_xid(u32::MAX - 5, 3));
+		assert!(!is_stale_xid(0, u32::MAX));
+	}
+}
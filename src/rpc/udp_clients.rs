@@ -1,115 +1,332 @@
 
 use std::io::{self, Error, ErrorKind};
-use std::net::{UdpSocket, ToSocketAddrs};
-use std::time::Duration;
+use std::mem;
+use std::net::{UdpSocket, ToSocketAddrs, SocketAddr, IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::{Duration, Instant};
 
-use crate::xdr;
-use super::{xdr_pack, xdr_unpack};
+use super::xdr_unpack;
+use super::client::RpcClient;
+use super::transport::{RpcTransport, reply_xid};
 
-pub struct UdpClient {
+// Defaults in the spirit of the original Sun implementation: give up after 25 seconds, resending with the wait
+// doubling from half a second up to eight seconds
+pub const DEFAULT_TIMEOUT:Duration      = Duration::from_secs(25);
+pub const DEFAULT_INITIAL_WAIT:Duration = Duration::from_millis(500);
+pub const DEFAULT_MAX_WAIT:Duration     = Duration::from_secs(8);
+
+// Conventional receive buffer size for RPC over UDP, and the largest payload a UDP datagram can carry
+pub const UDPMSGSIZE:usize = 8800;
+pub const MAX_UDP_PAYLOAD:usize = 65507;
+
+pub struct UdpTransport {
 	pub socket: UdpSocket,
-    pub prog: u32,
-    pub vers: u32,
-    pub lastxid: u32,
-    recv_buff: [u8; 8092],
+    pub timeout: Duration,             // Total time to wait for a reply, including retransmissions
+    pub initial_wait: Duration,        // Wait before the first retransmission, doubled after each one
+    pub max_wait: Duration,            // Longest wait between retransmissions
+    pub recv_size: usize,              // Largest reply expected, longer replies are an error
+    recv_buff: Vec<u8>,
 }
 
-impl UdpClient {
+impl UdpTransport {
 
-	pub fn connect<A: ToSocketAddrs>(addr: A, prog: u32, vers: u32) -> io::Result<Self> {
-		let socket:UdpSocket = (3600..3900).map(|port| { UdpSocket::bind(("0.0.0.0", port as u16)) })
-			.find(|sock_result| sock_result.is_ok() )
-			.unwrap_or(Err(Error::new(ErrorKind::Other, "No available ports found in the range scanned")))?;
+	// Binds to an ephemeral port
+	pub fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+		let remote:SocketAddr = addr.to_socket_addrs()?.next().ok_or_else(|| Error::new(ErrorKind::InvalidInput, "No address to connect to"))?;
+		let local:SocketAddr = match remote {
+			SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+			SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+		};
+		Self::connect_from(local, remote)
+	}
+
+	// Binds to a specific local address and port, for example to get through a firewall
+	pub fn connect_from<L: ToSocketAddrs, A: ToSocketAddrs>(local: L, addr: A) -> io::Result<Self> {
+		let socket:UdpSocket = UdpSocket::bind(local)?;
 		socket.connect(addr)?;
-		Ok(Self{ socket, prog, vers, lastxid: 0, recv_buff: [0; 8092] })
+		Ok(Self{ socket, timeout: DEFAULT_TIMEOUT, initial_wait: DEFAULT_INITIAL_WAIT, max_wait: DEFAULT_MAX_WAIT, recv_size: UDPMSGSIZE, recv_buff: vec![] })
 	}
+}
+
+impl RpcTransport for UdpTransport {
+
+	fn call(&mut self, xid:u32, msg:&[u8]) -> io::Result<Vec<u8>> {
+		let deadline:Instant = Instant::now() + self.timeout;
+		let mut wait:Duration = self.initial_wait;
+
+		// One byte more than the largest reply, so a reply that filled it must have been truncated
+		self.recv_buff.resize(self.recv_size + 1, 0);
 
-	pub fn do_call(&mut self, call:&[u8], unpacker:&mut xdr::Unpacker, lastxid:u32) -> io::Result<()> {
-        
-        match self.socket.send(call) {
-        	Ok(n) => {
-        		if n < call.len() { return Err(Error::new(ErrorKind::Other, "Unable to send all bytes")) }
-        		else if n > call.len() { return Err(Error::new(ErrorKind::Other, "Somehow sent more bytes than expected")) }
-        		// If n == call.len(), then do nothing and continue because that's what we expected
-        	},
-        	Err(e) => return Err(e),
-        }
+		loop {
+	        let n:usize = self.socket.send(msg)?;
+	        if n != msg.len() { return Err(Error::other("Unable to send all bytes")); }
 
-    	// TODO: consider resending if the first recv fails
-    	let n = self.socket.recv(&mut self.recv_buff)?;
+	        let resend_at:Instant = deadline.min(Instant::now() + wait);
+	        while let Some(remaining) = resend_at.checked_duration_since(Instant::now()).filter(|d| !d.is_zero()) {
+	        	self.socket.set_read_timeout(Some(remaining))?;
+	        	let n:usize = match self.socket.recv(&mut self.recv_buff) {
+	        		Ok(n) => n,
+	        		Err(ref e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => break,
+	        		Err(e) => return Err(e),
+	        	};
 
-    	unpacker.reset(&self.recv_buff[0..n as usize]);
-    	
-    	let (xid, _) = xdr_unpack::unpack_replyheader(unpacker)?;
-    	if xid == lastxid { Ok(())                                                             }
-    	else              { Err(Error::new(ErrorKind::Other, "Wrong xid in received message")) }
+	        	// Replies to earlier calls and duplicates from retransmissions are dropped while we keep waiting
+	        	if reply_xid(&self.recv_buff[0..n]) == Some(xid) {
+	        		if n > self.recv_size {
+	        			return Err(Error::new(ErrorKind::InvalidData, format!("Reply is longer than the receive size of {} bytes", self.recv_size)));
+	        		}
+	        		return Ok(self.recv_buff[0..n].to_vec());
+	        	}
+	        }
+
+	        if Instant::now() >= deadline {
+	        	return Err(Error::new(ErrorKind::TimedOut, "No reply before the call timed out"));
+	        }
+	        wait = (wait * 2).min(self.max_wait);
+		}
 	}
 }
 
-pub struct BroadcastUdpClient {
+pub type UdpClient = RpcClient<UdpTransport>;
+
+impl RpcClient<UdpTransport> {
+
+	pub fn connect<A: ToSocketAddrs>(addr: A, prog: u32, vers: u32) -> io::Result<Self> {
+		Ok(Self::new(UdpTransport::connect(addr)?, prog, vers))
+	}
+
+	pub fn connect_from<L: ToSocketAddrs, A: ToSocketAddrs>(local: L, addr: A, prog: u32, vers: u32) -> io::Result<Self> {
+		Ok(Self::new(UdpTransport::connect_from(local, addr)?, prog, vers))
+	}
+}
+
+// How long a broadcast call collects replies by default
+pub const DEFAULT_BROADCAST_WINDOW:Duration = Duration::from_secs(2);
+
+// Sends each call to every host at a broadcast (or multicast) address and collects the replies.  It can't implement
+// RpcTransport since one call gets any number of replies, so BroadcastUdpClient has a make_call of its own instead.
+pub struct BroadcastUdpTransport {
 	pub socket: UdpSocket,
-    pub prog: u32,
-    pub vers: u32,
     pub port: u16,
-    pub lastxid: u32,
-    pub packer: xdr::Packer,
-    pub unpacker: xdr::Unpacker,
-    recv_buff: [u8; 8092],
+    pub broadcast_addr: IpAddr,        // Limited, subnet-directed (like 192.168.1.255) or multicast address
+    pub window: Duration,              // How long to collect replies after each call
+    recv_buff: Vec<u8>,
 }
 
-impl BroadcastUdpClient {
+impl BroadcastUdpTransport {
 
 	// https://stackoverflow.com/questions/61045602/how-do-you-broadcast-a-udp-datagram-and-receive-the-responses-in-rust?noredirect=1#comment107997707_61045602
 
-	pub fn bind(port:u16, prog: u32, vers: u32) -> io::Result<Self> {
-		let socket:UdpSocket = UdpSocket::bind("0.0.0.0:0")?;
-		socket.set_read_timeout(Some(Duration::new(5, 0)))?;
+	pub fn bind(port:u16) -> io::Result<Self> {
+		Self::bind_on(Ipv4Addr::UNSPECIFIED.into(), port)
+	}
+
+	// Binds to the address of a particular interface so the calls go out on it
+	pub fn bind_on(local:IpAddr, port:u16) -> io::Result<Self> {
+		let socket:UdpSocket = UdpSocket::bind((local, 0))?;
 		socket.set_broadcast(true)?;
+		Ok(Self{ socket, port, broadcast_addr: Ipv4Addr::BROADCAST.into(), window: DEFAULT_BROADCAST_WINDOW, recv_buff: vec![0; MAX_UDP_PAYLOAD] })
+	}
+}
 
-		let packer = xdr::Packer::new();
-		let unpacker = xdr::Unpacker::new();
+pub type BroadcastUdpClient = RpcClient<BroadcastUdpTransport>;
 
-		Ok(Self{ socket, prog, vers, port: port as u16, lastxid: 0, packer, unpacker, recv_buff: [0; 8092] })
-	}
+impl RpcClient<BroadcastUdpTransport> {
 
+	pub fn bind(port:u16, prog: u32, vers: u32) -> io::Result<Self> {
+		Ok(Self::new(BroadcastUdpTransport::bind(port)?, prog, vers))
+	}
 
-    pub fn start_call(&mut self, prc:u32) -> io::Result<()> {
-        self.lastxid += 1;
-        self.packer.reset();
-        xdr_pack::pack_callheader_no_auth(&mut self.packer, self.lastxid, self.prog, self.vers, prc)
-    }
+	pub fn bind_on(local:IpAddr, port:u16, prog: u32, vers: u32) -> io::Result<Self> {
+		Ok(Self::new(BroadcastUdpTransport::bind_on(local, port)?, prog, vers))
+	}
 
-    pub fn make_call(&mut self) -> io::Result<()> {
-	    // Function arguments should have just been packed when this message is called
-	    let call:Vec<u8> = self.packer.get_buf()?;
-	    match self.socket.send_to(&call, ("255.255.255.255", self.port)) {
-	    	Ok(n) => {
-	    		if n != call.len() {
-	    			return Err(Error::new(ErrorKind::Other, "Sent the wrong number of bytes"))
-	    		}
-	    		else {
-	    			// Do nothing because we sent the number of bytes we expected to send
-	    		}
-	    	},
-	    	Err(e) => return Err(e),
-	    }
+    // Sends the call and collects successful replies until the window closes.  Returns the packed results from each
+    // host that answered, once per host even if it answered more than once (for example on several interfaces).
+    pub fn make_call(&mut self) -> io::Result<Vec<(SocketAddr, Vec<u8>)>> {
+	    let transport:&mut BroadcastUdpTransport = &mut self.transport;
+	    let call:&[u8] = &self.packer.buff;
+	    let n:usize = transport.socket.send_to(call, (transport.broadcast_addr, transport.port))?;
+	    if n != call.len() { return Err(Error::other("Sent the wrong number of bytes")); }
 
-	    while let Ok((n, addr)) = self.socket.recv_from(&mut self.recv_buff) {
-		    self.unpacker.reset(&self.recv_buff[0..(n as usize)]);
-		    let (xid, _) = xdr_unpack::unpack_replyheader(&mut self.unpacker)?;
+	    let mut ans:Vec<(SocketAddr, Vec<u8>)> = vec![];
+	    let deadline:Instant = Instant::now() + transport.window;
+	    while let Some(remaining) = deadline.checked_duration_since(Instant::now()).filter(|d| !d.is_zero()) {
+	    	transport.socket.set_read_timeout(Some(remaining))?;
+	    	let (n, addr) = match transport.socket.recv_from(&mut transport.recv_buff) {
+	    		Ok(x) => x,
+	    		Err(ref e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => break,
+	    		Err(e) => return Err(e),
+	    	};
 
-		    // Only keep messages with the correct xid
-		    if xid == self.lastxid {
-		        let reply:Vec<u8> = self.unpacker.get_remaining_bytes()?;
-		        println!("From {:?}: {:?}", addr, reply);
+		    // Only keep successful replies with the correct xid, anything else on the network is none of our business.
+		    // Other traffic and repeats are skipped on the xid alone, before anything is copied.
+		    if reply_xid(&transport.recv_buff[0..n]) != Some(self.lastxid) || ans.iter().any(|(a, _)| *a == addr) {
+		    	continue;
 		    }
 
+		    // The receive buffer becomes the reply, and the next datagram gets a fresh one
+		    let mut reply:Vec<u8> = mem::replace(&mut transport.recv_buff, vec![0; MAX_UDP_PAYLOAD]);
+		    reply.truncate(n);
+		    self.unpacker.reset_owned(reply);
+		    if xdr_unpack::unpack_replyheader(&mut self.unpacker).is_ok() {
+		    	ans.push((addr, self.unpacker.take_remaining()));
+		    }
 	    }
 
-    	// TODO: think about returning the replies instead of storing them as state
-    	Ok(())
+    	Ok(ans)
     }
+}
 
+#[cfg(test)]
+mod tests {
+	use std::thread;
 
-}
+	use crate::xdr;
+	use crate::rpc::xdr_pack;
+	use crate::rpc::reply::OpaqueAuth;
+	use super::*;
 
+	fn reply(xid:u32, result:u32) -> Vec<u8> {
+		let mut packer = xdr::Packer::new();
+		xdr_pack::pack_replyheader(&mut packer, xid, &OpaqueAuth::none()).unwrap();
+		packer.pack_u32(result).unwrap();
+		packer.buff
+	}
+
+	#[test]
+	fn broadcast_keeps_one_good_reply_per_host() {
+		let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+		let mut client = BroadcastUdpClient::bind_on(Ipv4Addr::LOCALHOST.into(), server.local_addr().unwrap().port(), 1, 1).unwrap();
+		client.transport.broadcast_addr = Ipv4Addr::LOCALHOST.into();
+		client.transport.window = Duration::from_millis(200);
+
+		let responder = thread::spawn(move || {
+			let mut buff = [0u8; 512];
+			let (n, peer) = server.recv_from(&mut buff).unwrap();
+			let xid:u32 = reply_xid(&buff[..n]).unwrap();
+
+			// Someone else's reply, a garbled one, then the real reply twice
+			server.send_to(&reply(xid.wrapping_add(1), 1), peer).unwrap();
+			server.send_to(&xid.to_be_bytes(), peer).unwrap();
+			server.send_to(&reply(xid, 2), peer).unwrap();
+			server.send_to(&reply(xid, 3), peer).unwrap();
+		});
+
+		client.start_call(0).unwrap();
+		let ans = client.make_call().unwrap();
+		responder.join().unwrap();
+
+		assert_eq!(ans.len(), 1);
+		assert_eq!(ans[0].1, 2u32.to_be_bytes());
+	}
+
+	fn transport(server:&UdpSocket, timeout_ms:u64) -> UdpTransport {
+		let mut transport = UdpTransport::connect(server.local_addr().unwrap()).unwrap();
+		transport.timeout = Duration::from_millis(timeout_ms);
+		transport.initial_wait = Duration::from_millis(50);
+		transport.max_wait = Duration::from_millis(200);
+		transport
+	}
+
+	// Collects every call that turns up, along with when it did, until none come for a while
+	fn record_calls(server:UdpSocket) -> thread::JoinHandle<Vec<(Instant, Vec<u8>)>> {
+		thread::spawn(move || {
+			server.set_read_timeout(Some(Duration::from_millis(500))).unwrap();
+			let mut calls = vec![];
+			let mut buff = [0u8; 512];
+			while let Ok(n) = server.recv(&mut buff) {
+				calls.push((Instant::now(), buff[..n].to_vec()));
+			}
+			calls
+		})
+	}
+
+	#[test]
+	fn retransmits_until_answered() {
+		let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+		let mut transport = transport(&server, 2000);
+
+		// The first three sends go unanswered, and every resend is the same message
+		let responder = thread::spawn(move || {
+			let mut buff = [0u8; 512];
+			for _ in 0..3 {
+				let (n, _) = server.recv_from(&mut buff).unwrap();
+				assert_eq!(&buff[..n], &reply(7, 0)[..]);
+			}
+			let (_, peer) = server.recv_from(&mut buff).unwrap();
+			server.send_to(&reply(7, 1), peer).unwrap();
+		});
+
+		assert_eq!(transport.call(7, &reply(7, 0)).unwrap(), reply(7, 1));
+		responder.join().unwrap();
+	}
+
+	#[test]
+	fn waits_double_up_to_the_max_until_the_total_timeout() {
+		let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+		let mut transport = transport(&server, 700);
+		let calls = record_calls(server);
+
+		let start = Instant::now();
+		let e = transport.call(1, &reply(1, 0)).unwrap_err();
+		let elapsed:Duration = start.elapsed();
+		assert_eq!(e.kind(), ErrorKind::TimedOut);
+		assert!(elapsed >= Duration::from_millis(700) && elapsed < Duration::from_millis(1500), "gave up after {:?}", elapsed);
+
+		let calls = calls.join().unwrap();
+		assert!(calls.len() >= 3 && calls.len() <= 5, "sent {} times", calls.len());
+		let mut wait = Duration::from_millis(50);
+		for pair in calls.windows(2) {
+			let gap:Duration = pair[1].0 - pair[0].0;
+			assert!(gap + Duration::from_millis(5) >= wait, "resent after {:?} rather than {:?}", gap, wait);
+			wait = (wait * 2).min(Duration::from_millis(200));
+		}
+	}
+
+	#[test]
+	fn stale_and_duplicate_replies_are_skipped() {
+		let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+		let mut transport = transport(&server, 2000);
+
+		let responder = thread::spawn(move || {
+			let mut buff = [0u8; 512];
+			let (_, peer) = server.recv_from(&mut buff).unwrap();
+			server.send_to(&reply(4, 1), peer).unwrap();
+			server.send_to(&reply(5, 2), peer).unwrap();
+			server.send_to(&reply(5, 3), peer).unwrap();
+
+			let (_, peer) = server.recv_from(&mut buff).unwrap();
+			server.send_to(&reply(6, 4), peer).unwrap();
+		});
+
+		assert_eq!(transport.call(5, &reply(5, 0)).unwrap(), reply(5, 2));
+
+		// The duplicate of the last reply is still waiting in the socket and gets passed over
+		assert_eq!(transport.call(6, &reply(6, 0)).unwrap(), reply(6, 4));
+		responder.join().unwrap();
+	}
+
+	#[test]
+	fn truncated_replies_are_an_error() {
+		let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+		let mut transport = transport(&server, 2000);
+		transport.recv_size = 12;
+
+		let responder = thread::spawn(move || {
+			let mut buff = [0u8; 512];
+			let (_, peer) = server.recv_from(&mut buff).unwrap();
+			let mut long_reply:Vec<u8> = reply(1, 0);
+			long_reply.extend_from_slice(&[0; 8]);
+			server.send_to(&long_reply, peer).unwrap();
+
+			let (_, peer) = server.recv_from(&mut buff).unwrap();
+			server.send_to(&reply(2, 0)[..12], peer).unwrap();
+		});
+
+		assert_eq!(transport.call(1, &reply(1, 0)).unwrap_err().kind(), ErrorKind::InvalidData);
+
+		// Exactly recv_size is fine
+		assert_eq!(transport.call(2, &reply(2, 0)).unwrap().len(), 12);
+		responder.join().unwrap();
+	}
+}
@@ -1,47 +1,58 @@
 
-use std::io;
+use serde::Serialize;
 
-use crate::xdr::Packer;
-use crate::rpc::{CALL, RPCVERSION, REPLY, MSG_ACCEPTED, SUCCESS};
+use crate::xdr::{Packer, Result};
+use crate::rpc::{CALL, RPCVERSION, REPLY};
+use crate::rpc::reply::{OpaqueAuth, ReplyBody};
 
-pub fn pack_auth(packer:&mut Packer, flavor:i32, stuff:&[u8]) -> io::Result<()> {
-	packer.pack_enum(flavor)?;
-	packer.pack_variable_len_opaque(stuff)
+pub fn pack_auth(packer:&mut Packer, auth:&OpaqueAuth) -> Result<()> {
+	packer.pack(auth)
 }
 
-pub fn pack_callheader(packer:&mut Packer, xid:u32, prog:u32, vers:u32, prc:u32, cred:(i32, &[u8]), verf:(i32, &[u8])) -> io::Result<()> {
+pub fn pack_callheader(packer:&mut Packer, xid:u32, prog:u32, vers:u32, prc:u32, cred:&OpaqueAuth, verf:&OpaqueAuth) -> Result<()> {
 	packer.pack_u32(xid)?;
 	packer.pack_enum(CALL)?;
 	packer.pack_u32(RPCVERSION)?;
 	packer.pack_u32(prog)?;
 	packer.pack_u32(vers)?;
 	packer.pack_u32(prc)?;
-	pack_auth(packer, cred.0, cred.1)?;
-	pack_auth(packer, verf.0, verf.1)
+	pack_auth(packer, cred)?;
+	pack_auth(packer, verf)
 }
 
-pub fn pack_callheader_no_auth(packer: &mut Packer, xid:u32, prog:u32, vers:u32, prc:u32) -> io::Result<()> {
-	pack_callheader(packer, xid, prog, vers, prc, (0, &[]), (0, &[]))
+pub fn pack_callheader_no_auth(packer: &mut Packer, xid:u32, prog:u32, vers:u32, prc:u32) -> Result<()> {
+	pack_callheader(packer, xid, prog, vers, prc, &OpaqueAuth::none(), &OpaqueAuth::none())
 }
 
-pub fn pack_replyheader(packer: &mut Packer, xid:u32, verf:(i32, &[u8])) -> io::Result<()> {
+pub fn pack_reply(packer: &mut Packer, xid:u32, body:&ReplyBody) -> Result<()> {
 	packer.pack_u32(xid)?;
 	packer.pack_enum(REPLY)?;
-	packer.pack_i32(MSG_ACCEPTED)?;	
-	pack_auth(packer, verf.0, verf.1)?;
-	packer.pack_enum(SUCCESS)
+	packer.pack(body)
 }
 
-pub fn pack_mapping(packer: &mut Packer, prog:u32, vers:u32, prot:u32, port:u32) -> io::Result<()> {
+pub fn pack_replyheader(packer: &mut Packer, xid:u32, verf:&OpaqueAuth) -> Result<()> {
+	pack_reply(packer, xid, &ReplyBody::success(verf.clone()))
+}
+
+pub fn pack_mapping(packer: &mut Packer, prog:u32, vers:u32, prot:u32, port:u32) -> Result<()> {
 	packer.pack_u32(prog)?;
 	packer.pack_u32(vers)?;
 	packer.pack_u32(prot)?;
 	packer.pack_u32(port)
 }
 
-pub fn pack_call_args(packer: &mut Packer, prog:u32, vers:u32, prc:u32, args:&[u8]) -> io::Result<()> {
+pub fn pack_call_args(packer: &mut Packer, prog:u32, vers:u32, prc:u32, args:&[u8]) -> Result<()> {
 	packer.pack_u32(prog)?;
 	packer.pack_u32(vers)?;
 	packer.pack_u32(prc)?;
 	packer.pack_variable_len_opaque(args)
 }
+
+// The reverse of xdr_unpack::unpack_list
+pub fn pack_list<T: Serialize>(packer: &mut Packer, items:&[T]) -> Result<()> {
+	for item in items {
+		packer.pack_bool(true)?;
+		packer.pack(item)?;
+	}
+	packer.pack_bool(false)
+}
@@ -1,55 +1,39 @@
 
-use std::io::{self, Error, ErrorKind};
+use std::io;
 
-use crate::xdr::Unpacker;
-use crate::rpc::{REPLY, MSG_DENIED, RPC_MISMATCH, AUTH_ERROR, MSG_ACCEPTED, PROG_UNAVAIL, PROG_MISMATCH, GARBAGE_ARGS, SUCCESS};
+use serde::de::DeserializeOwned;
 
-pub fn unpack_auth(unpacker:&mut Unpacker) -> io::Result<(i32, Vec<u8>)> {
-	let flavor:i32    = unpacker.unpack_enum()?;
-	let stuff:Vec<u8> = unpacker.unpack_variable_len_opaque()?;
-	Ok((flavor, stuff))
+use crate::xdr::{self, Unpacker};
+use crate::rpc::{REPLY, RpcError};
+use crate::rpc::reply::{OpaqueAuth, ReplyBody};
+
+pub fn unpack_auth(unpacker:&mut Unpacker) -> io::Result<OpaqueAuth> {
+	Ok(unpacker.unpack()?)
+}
+
+// Unpacks the xid and reply body without judging whether the call succeeded
+pub fn unpack_reply(unpacker:&mut Unpacker) -> io::Result<(u32, ReplyBody)> {
+	let xid:u32 = unpacker.unpack_u32()?;
+
+	let mtype:i32 = unpacker.unpack_enum()?;
+	if mtype != REPLY { return Err(RpcError::NotAReply{ mtype }.into()); }
+
+	Ok((xid, unpacker.unpack()?))
 }
 
-// TODO: consider making an auth struct instead of just using (i32, Vec<u8>)
-pub fn unpack_replyheader(unpacker:&mut Unpacker) -> io::Result<(u32, (i32, Vec<u8>))> {
-    let xid:u32 = unpacker.unpack_u32()?;
-
-    let mtype:i32 = unpacker.unpack_enum()?;
-    if mtype != REPLY { return Err(Error::new(ErrorKind::Other, "Expected REPLY message type in unpack_replyheader")); }
-
-    match unpacker.unpack_enum()? {
-		MSG_DENIED => {
-	    	match unpacker.unpack_enum()? {
-	    		RPC_MISMATCH => {
-	    			unpacker.unpack_u32()?;	// This u32 gives the low value
-					unpacker.unpack_u32()?;	// This u32 gives the high value
-					return Err(Error::new(ErrorKind::Other, "Message denied due to RPC_MISMATCH in unpack_replyheader"))
-	    		},
-	    		AUTH_ERROR => {
-					unpacker.unpack_u32()?;	// This u32 gives us another status code that might have more detail if needed
-					return Err(Error::new(ErrorKind::Other, "Message denied due to AUTH_ERROR in unpack_replyheader"))
-	    		}
-	    		_ => return Err(Error::new(ErrorKind::Other, "Message denied for an unknown reason in unpack_replyheader")),
-	    	}
-	    },
-	    MSG_ACCEPTED => { },
-	    _            => return Err(Error::new(ErrorKind::Other, "Neither MSG_DENIED nor MSG_ACCEPTED in unpack_replyheader")),
-    }
-
-    let verf = unpack_auth(unpacker)?;
-
-    match unpacker.unpack_enum()? {
-    	PROG_UNAVAIL  => return Err(Error::new(ErrorKind::Other, "Program unavailable in unpack_replyheader")),
-    	PROG_MISMATCH => {
-			unpacker.unpack_u32()?;	// This u32 gives the low value
-			unpacker.unpack_u32()?;	// This u32 gives the high value
-    		return Err(Error::new(ErrorKind::Other, "Program mismatch in unpack_replyheader"))
-    	},
-    	GARBAGE_ARGS  => return Err(Error::new(ErrorKind::Other, "Garbage args in unpack_replyheader")),
-    	SUCCESS => { },
-    	_ => return Err(Error::new(ErrorKind::Other, "Call failed for unknown reason in unpack_replyheader")),
-    }
-
-	Ok((xid, verf))
+// Unpacks the xid and verifier of a successful reply, leaving the procedure's results in the unpacker.  Any other
+// reply becomes an io::Error wrapping an RpcError.
+pub fn unpack_replyheader(unpacker:&mut Unpacker) -> io::Result<(u32, OpaqueAuth)> {
+	let (xid, body) = unpack_reply(unpacker)?;
+	Ok((xid, body.into_result()?))
 }
 
+// Lists like pmaplist and rpcblist are linked lists where each entry is preceded by a bool saying whether there's
+// another entry
+pub fn unpack_list<T: DeserializeOwned>(unpacker:&mut Unpacker) -> xdr::Result<Vec<T>> {
+	let mut ans:Vec<T> = vec![];
+	while unpacker.unpack_bool()? {
+		ans.push(unpacker.unpack()?);
+	}
+	Ok(ans)
+}
@@ -0,0 +1,405 @@
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io;
+
+use super::Options;
+use super::parser::{Definition, Decl, TypeSpec, Value, UnionArm, Version};
+
+// serde only implements Serialize and Deserialize for arrays up to this length
+const MAX_FIXED_ARRAY:i64 = 32;
+
+const KEYWORDS:&[&str] = &[
+	"as", "break", "const", "continue", "else", "enum", "extern", "false", "fn", "for", "if", "impl", "in", "let",
+	"loop", "match", "mod", "move", "mut", "pub", "ref", "return", "static", "struct", "trait", "true", "type",
+	"unsafe", "use", "where", "while", "async", "await", "dyn", "abstract", "become", "box", "do", "final", "macro",
+	"override", "priv", "typeof", "unsized", "virtual", "yield", "try",
+];
+
+fn err(msg:&str) -> io::Error { io::Error::new(io::ErrorKind::InvalidData, msg) }
+
+// Create_LinkParms -> CreateLinkParms, DEVICE_TCP -> DeviceTcp
+pub fn camel_case(name:&str) -> String {
+	let mut ans = String::new();
+	for part in name.split('_').filter(|p| !p.is_empty()) {
+		let shouting:bool = !part.chars().any(|c| c.is_ascii_lowercase());
+		let mut chars = part.chars();
+		if let Some(first) = chars.next() { ans.push(first.to_ascii_uppercase()); }
+		if shouting { ans.extend(chars.map(|c| c.to_ascii_lowercase())); }
+		else        { ans.extend(chars); }
+	}
+	ans
+}
+
+// clientId -> client_id, lock_timeout stays as it is
+pub fn snake_case(name:&str) -> String {
+	let mut ans = String::new();
+	let mut prev_lower:bool = false;
+	for c in name.chars() {
+		if c.is_ascii_uppercase() && prev_lower { ans.push('_'); }
+		prev_lower = c.is_ascii_lowercase() || c.is_ascii_digit();
+		ans.push(c.to_ascii_lowercase());
+	}
+
+	match ans.as_str() {
+		"self" | "super" | "crate" | "Self" => format!("{}_", ans),
+		s if KEYWORDS.contains(&s)          => format!("r#{}", ans),
+		_                                   => ans,
+	}
+}
+
+struct Generator<'a> {
+	opts: &'a Options,
+	symbols: HashMap<String, Value>,
+	out: String,
+}
+
+pub fn generate(defs:&[Definition], opts:&Options) -> io::Result<String> {
+	let mut symbols:HashMap<String, Value> = HashMap::new();
+	symbols.insert("TRUE".to_owned(), Value::Number(1));
+	symbols.insert("FALSE".to_owned(), Value::Number(0));
+	for def in defs {
+		match def {
+			Definition::Const{ name, value } => { symbols.insert(name.clone(), value.clone()); },
+			Definition::Enum{ variants, .. } => {
+				for (name, value) in variants { symbols.insert(name.clone(), value.clone()); }
+			},
+			_ => {},
+		}
+	}
+
+	let mut gen = Generator{ opts, symbols, out: String::new() };
+	gen.line("// Generated by rpcgen, do not edit by hand");
+	for def in defs {
+		gen.line("");
+		gen.definition(def)?;
+	}
+	Ok(gen.out)
+}
+
+impl<'a> Generator<'a> {
+
+	fn line(&mut self, s:&str) {
+		self.out.push_str(s);
+		self.out.push('\n');
+	}
+
+	fn resolve(&self, value:&Value) -> io::Result<i64> {
+		// Follow chains of constants defined in terms of other constants, but don't loop forever on a cycle
+		let mut v:&Value = value;
+		for _ in 0..64 {
+			match v {
+				Value::Number(n)   => return Ok(*n),
+				Value::Const(name) => v = self.symbols.get(name).ok_or_else(|| err(&format!("Undefined constant {}", name)))?,
+			}
+		}
+		Err(err("Constants are defined in terms of each other"))
+	}
+
+	fn xdr_path(&self) -> String { format!("{}::xdr", self.opts.crate_path) }
+
+	fn definition(&mut self, def:&Definition) -> io::Result<()> {
+		match def {
+			Definition::Const{ name, value }   => self.constant(name, value),
+			Definition::Typedef(decl)          => self.typedef(decl),
+			Definition::Enum{ name, variants } => self.enumeration(name, variants),
+			Definition::Struct{ name, fields } => self.structure(name, fields),
+			Definition::Union{ name, discriminant, arms, default } => self.union(name, discriminant, arms, default.as_ref()),
+			Definition::Program{ name, number, versions } => self.program(name, number, versions),
+		}
+	}
+
+	fn constant(&mut self, name:&str, value:&Value) -> io::Result<()> {
+		let n:i64 = self.resolve(value)?;
+		let ty:&str = if i32::try_from(n).is_ok() { "i32" } else if u32::try_from(n).is_ok() { "u32" } else { "i64" };
+		self.line(&format!("pub const {}:{} = {};", name, ty, n));
+		Ok(())
+	}
+
+	fn rust_type(&self, ty:&TypeSpec) -> String {
+		match ty {
+			TypeSpec::Int    => "i32".to_owned(),
+			TypeSpec::UInt   => "u32".to_owned(),
+			TypeSpec::Short  => "i16".to_owned(),
+			TypeSpec::UShort => "u16".to_owned(),
+			TypeSpec::Char   => "i8".to_owned(),
+			TypeSpec::UChar  => "u8".to_owned(),
+			TypeSpec::Hyper  => "i64".to_owned(),
+			TypeSpec::UHyper => "u64".to_owned(),
+			TypeSpec::Float  => "f32".to_owned(),
+			TypeSpec::Double => "f64".to_owned(),
+			TypeSpec::Bool   => "bool".to_owned(),
+			TypeSpec::Named(name) => camel_case(name),
+		}
+	}
+
+	fn fixed_len(&self, len:&Value) -> io::Result<i64> {
+		let n:i64 = self.resolve(len)?;
+		if n < 0 { return Err(err(&format!("Negative array length {}", n))); }
+		Ok(n)
+	}
+
+	fn bound(&self, max:&Value) -> io::Result<u32> {
+		let n:i64 = self.resolve(max)?;
+		u32::try_from(n).map_err(|_| err(&format!("Bound {} doesn't fit in an unsigned int", n)))
+	}
+
+	// Attribute that packs and unpacks through one of the xdr::bounded helpers, which check the length both ways
+	fn bounded_attr(&self, helper:&str, type_params:&str, max:&Value) -> io::Result<String> {
+		let n:u32 = self.bound(max)?;
+		let path:String = format!("{}::bounded::{}", self.xdr_path(), helper);
+		Ok(format!("#[serde(serialize_with = \"{}::serialize::<{}{}>\", deserialize_with = \"{}::deserialize::<{}{}>\")]",
+			path, type_params, n, path, type_params, n))
+	}
+
+	// Returns the field attribute (if any) and the Rust type for a declaration
+	fn decl_type(&self, decl:&Decl) -> io::Result<(Option<String>, String)> {
+		let ans = match decl {
+			Decl::Void => return Err(err("void is only allowed in union arms")),
+			Decl::Simple{ ty, .. } => (None, self.rust_type(ty)),
+			Decl::FixedArray{ ty, len, .. } => {
+				let n:i64 = self.fixed_len(len)?;
+				if n > MAX_FIXED_ARRAY {
+					return Err(err(&format!("Fixed-length arrays are limited to {} elements", MAX_FIXED_ARRAY)));
+				}
+				(None, format!("[{}; {}]", self.rust_type(ty), n))
+			},
+			Decl::VarArray{ ty, max, .. } => {
+				let attr:Option<String> = max.as_ref().map(|max| self.bounded_attr("array", "_, _, ", max)).transpose()?;
+				(attr, format!("Vec<{}>", self.rust_type(ty)))
+			},
+			Decl::FixedOpaque{ len, .. } => {
+				let n:i64 = self.fixed_len(len)?;
+				(Some(format!("#[serde(with = \"{}::opaque::fixed\")]", self.xdr_path())), format!("[u8; {}]", n))
+			},
+			Decl::VarOpaque{ max: Some(max), .. } => (Some(self.bounded_attr("opaque", "_, ", max)?), "Vec<u8>".to_owned()),
+			Decl::VarOpaque{ max: None, .. }      => (Some(format!("#[serde(with = \"{}::opaque::variable\")]", self.xdr_path())), "Vec<u8>".to_owned()),
+			Decl::Str{ max, .. } => {
+				let attr:Option<String> = max.as_ref().map(|max| self.bounded_attr("string", "_, ", max)).transpose()?;
+				(attr, "String".to_owned())
+			},
+			Decl::Optional{ ty, .. } => (None, format!("Option<Box<{}>>", self.rust_type(ty))),
+		};
+		Ok(ans)
+	}
+
+	fn typedef(&mut self, decl:&Decl) -> io::Result<()> {
+		let name:String = camel_case(decl.name().ok_or_else(|| err("typedef void isn't allowed"))?);
+		match self.decl_type(decl)? {
+			// The opaque and bounded helpers only work on fields, so typedefs that need them become newtypes
+			(Some(attr), ty) => {
+				self.line("#[derive(Debug, Clone, PartialEq, ::serde::Serialize, ::serde::Deserialize)]");
+				self.line(&format!("pub struct {}({} pub {});", name, attr, ty));
+			},
+			(None, ty) => self.line(&format!("pub type {} = {};", name, ty)),
+		}
+		Ok(())
+	}
+
+	// Enums and union discriminants go on the wire as an int, or an unsigned int for an unsigned discriminant, and the
+	// serializer reads the value back out of the variant's name as an i32
+	fn discriminant(&self, value:&Value, unsigned:bool) -> io::Result<i32> {
+		let n:i64 = self.resolve(value)?;
+		let ans = if unsigned { u32::try_from(n).map(|n| n as i32).ok() } else { i32::try_from(n).ok() };
+		ans.ok_or_else(|| err(&format!("{} doesn't fit in an {}", n, if unsigned { "unsigned int" } else { "int" })))
+	}
+
+	fn enumeration(&mut self, name:&str, variants:&[(String, Value)]) -> io::Result<()> {
+		self.line("#[derive(Debug, Clone, Copy, PartialEq, Eq, ::serde::Serialize, ::serde::Deserialize)]");
+		self.line(&format!("pub enum {} {{", camel_case(name)));
+		for (variant, value) in variants {
+			let n:i32 = self.discriminant(value, false)?;
+			self.line(&format!("\t#[serde(rename = \"{}\")]", n));
+			self.line(&format!("\t{},", camel_case(variant)));
+		}
+		self.line("}");
+		Ok(())
+	}
+
+	fn structure(&mut self, name:&str, fields:&[Decl]) -> io::Result<()> {
+		self.line("#[derive(Debug, Clone, PartialEq, ::serde::Serialize, ::serde::Deserialize)]");
+		self.line(&format!("pub struct {} {{", camel_case(name)));
+		for field in fields {
+			let field_name:String = snake_case(field.name().ok_or_else(|| err(&format!("void field in struct {}", name)))?);
+			let (attr, ty) = self.decl_type(field)?;
+			if let Some(attr) = attr { self.line(&format!("\t{}", attr)); }
+			self.line(&format!("\tpub {}: {},", field_name, ty));
+		}
+		self.line("}");
+		Ok(())
+	}
+
+	fn union(&mut self, name:&str, discriminant:&Decl, arms:&[UnionArm], default:Option<&Decl>) -> io::Result<()> {
+		let unsigned:bool = match discriminant {
+			Decl::Simple{ ty: TypeSpec::UInt, .. } => true,
+			Decl::Simple{ ty: TypeSpec::Int, .. } | Decl::Simple{ ty: TypeSpec::Bool, .. } | Decl::Simple{ ty: TypeSpec::Named(_), .. } => false,
+			_ => return Err(err(&format!("The discriminant of union {} must be an int, unsigned int, bool or enum", name))),
+		};
+
+		// A derived enum can't hold on to a discriminant it doesn't know, so there's nothing a default arm could become
+		if default.is_some() {
+			return Err(err(&format!("Union {} has a default arm, which isn't supported", name)));
+		}
+
+		self.line("#[derive(Debug, Clone, PartialEq, ::serde::Serialize, ::serde::Deserialize)]");
+		self.line(&format!("pub enum {} {{", camel_case(name)));
+		for arm in arms {
+			// Cases that share an arm each get their own variant since the discriminant is what tells them apart
+			for case in &arm.cases {
+				let n:i32 = self.discriminant(case, unsigned)?;
+				let variant:String = match case {
+					Value::Const(c)           => camel_case(c),
+					Value::Number(v) if *v < 0 => format!("CaseNeg{}", -v),
+					Value::Number(v)          => format!("Case{}", v),
+				};
+				self.line(&format!("\t#[serde(rename = \"{}\")]", n));
+				match &arm.decl {
+					Decl::Void => self.line(&format!("\t{},", variant)),
+					decl => {
+						let (attr, ty) = self.decl_type(decl)?;
+						let attr:String = attr.map(|a| format!("{} ", a)).unwrap_or_default();
+						self.line(&format!("\t{}({}{}),", variant, attr, ty));
+					},
+				}
+			}
+		}
+		self.line("}");
+		Ok(())
+	}
+
+	fn program(&mut self, name:&str, number:&Value, versions:&[Version]) -> io::Result<()> {
+		let prog:i64 = self.resolve(number)?;
+		self.line(&format!("pub const {}:u32 = {};", name, prog));
+
+		for version in versions {
+			let vers:i64 = self.resolve(&version.number)?;
+			self.line(&format!("pub const {}:u32 = {};", version.name, vers));
+			for procedure in &version.procedures {
+				let n:i64 = self.resolve(&procedure.number)?;
+				self.line(&format!("pub const {}:u32 = {};", procedure.name.to_uppercase(), n));
+			}
+
+			let rpc_path:String = format!("{}::rpc", self.opts.crate_path);
+			let client:String = format!("{}Client", camel_case(&version.name));
+			let tcp:String = format!("{}::tcp_clients::TcpTransport", rpc_path);
+			let transport:String = format!("{}::transport::RpcTransport", rpc_path);
+
+			// Generic over the transport like the hand-written clients, defaulting to TCP
+			self.line("");
+			self.line(&format!("pub struct {}<T: {} = {}> {{", client, transport, tcp));
+			self.line(&format!("\tpub client: {}::client::RpcClient<T>,", rpc_path));
+			self.line("}");
+			self.line("");
+			self.line(&format!("impl {}<{}> {{", client, tcp));
+			self.line("");
+			self.line("\tpub fn connect<A: ::std::net::ToSocketAddrs>(addr:A) -> ::std::io::Result<Self> {");
+			self.line(&format!("\t\tOk(Self{{ client: {}::tcp_clients::TcpClient::connect(addr, {}, {})? }})", rpc_path, name, version.name));
+			self.line("\t}");
+			self.line("}");
+			self.line("");
+			self.line(&format!("impl<T: {}> {}<T> {{", transport, client));
+			self.line("");
+			self.line("\tpub fn with_transport(transport:T) -> Self {");
+			self.line(&format!("\t\tSelf{{ client: {}::client::RpcClient::new(transport, {}, {}) }}", rpc_path, name, version.name));
+			self.line("\t}");
+			for procedure in &version.procedures {
+				let args:Vec<String> = procedure.args.iter().map(|ty| self.rust_type(ty)).collect();
+				let params:String = match args.len() {
+					1 => format!(", arg:&{}", args[0]),
+					_ => args.iter().enumerate().map(|(idx, ty)| format!(", arg{}:&{}", idx, ty)).collect(),
+				};
+				let result:String = procedure.result.as_ref().map(|ty| self.rust_type(ty)).unwrap_or_else(|| "()".to_owned());
+
+				self.line("");
+				self.line(&format!("\tpub fn {}(&mut self{}) -> ::std::io::Result<{}> {{", snake_case(&procedure.name), params, result));
+				self.line(&format!("\t\tself.client.start_call({})?;", procedure.name.to_uppercase()));
+				match args.len() {
+					0 => {},
+					1 => self.line("\t\tself.client.packer.pack(arg)?;"),
+					n => for idx in 0..n { self.line(&format!("\t\tself.client.packer.pack(arg{})?;", idx)); },
+				}
+				self.line("\t\tself.client.do_call()?;");
+				match procedure.result {
+					Some(_) => self.line("\t\tOk(self.client.unpacker.unpack()?)"),
+					None    => self.line("\t\tOk(())"),
+				}
+				self.line("\t}");
+			}
+			self.line("}");
+		}
+
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::super::compile;
+	use super::*;
+
+	fn gen(src:&str) -> io::Result<String> { compile(src, &Options::default()) }
+
+	#[test]
+	fn names() {
+		assert_eq!(camel_case("Create_LinkParms"), "CreateLinkParms");
+		assert_eq!(camel_case("DEVICE_TCP"), "DeviceTcp");
+		assert_eq!(snake_case("clientId"), "client_id");
+		assert_eq!(snake_case("lock_timeout"), "lock_timeout");
+		assert_eq!(snake_case("type"), "r#type");
+		assert_eq!(snake_case("self"), "self_");
+	}
+
+	#[test]
+	fn enum_values_have_to_fit_an_int() {
+		assert!(gen("enum e { A = 2147483647, B = -2147483648 };").is_ok());
+		assert!(gen("enum e { A = 2147483648 };").is_err());
+		assert!(gen("const BIG = 0x80000000; enum e { A = BIG };").is_err());
+	}
+
+	#[test]
+	fn union_cases() {
+		let code = gen("union u switch (unsigned k) { case 0xffffffff: int a; case 1: void; };").unwrap();
+		assert!(code.contains("\t#[serde(rename = \"-1\")]\n\tCase4294967295(i32),"), "{}", code);
+		assert!(code.contains("\t#[serde(rename = \"1\")]\n\tCase1,"), "{}", code);
+
+		let code = gen("enum k { ONE = 1 }; union u switch (k kind) { case ONE: int a; case -2: void; };").unwrap();
+		assert!(code.contains("\tOne(i32),"), "{}", code);
+		assert!(code.contains("\t#[serde(rename = \"-2\")]\n\tCaseNeg2,"), "{}", code);
+
+		assert!(gen("union u switch (int k) { case 0x80000000: void; };").is_err());
+		assert!(gen("union u switch (unsigned k) { case -1: void; };").is_err());
+	}
+
+	#[test]
+	fn default_arms_are_an_error() {
+		let e = gen("union u switch (int k) { case 1: int a; default: void; };").unwrap_err();
+		assert!(e.to_string().contains("default arm"), "{}", e);
+	}
+
+	#[test]
+	fn unsupported_declarations() {
+		assert!(gen("struct s { int a[33]; };").is_err());
+		assert!(gen("struct s { int a[-1]; };").is_err());
+		assert!(gen("struct s { int a[N]; };").is_err());
+		assert!(gen("const A = B; const B = A; struct s { int a[A]; };").is_err());
+		assert!(gen("union u switch (string k<>) { case 1: void; };").is_err());
+	}
+
+	#[test]
+	fn bounded_declarations() {
+		let code = gen("const MAX = 8; struct s { string a<MAX>; int b<2>; opaque c<4>; string d<>; opaque e<>; };").unwrap();
+		assert!(code.contains("#[serde(serialize_with = \"vxi11::xdr::bounded::string::serialize::<_, 8>\", deserialize_with = \"vxi11::xdr::bounded::string::deserialize::<_, 8>\")]\n\tpub a: String,"), "{}", code);
+		assert!(code.contains("#[serde(serialize_with = \"vxi11::xdr::bounded::array::serialize::<_, _, 2>\", deserialize_with = \"vxi11::xdr::bounded::array::deserialize::<_, _, 2>\")]\n\tpub b: Vec<i32>,"), "{}", code);
+		assert!(code.contains("bounded::opaque::serialize::<_, 4>"), "{}", code);
+		assert!(code.contains("\tpub d: String,"), "{}", code);
+		assert!(code.contains("#[serde(with = \"vxi11::xdr::opaque::variable\")]\n\tpub e: Vec<u8>,"), "{}", code);
+
+		// Type aliases can't carry the attribute, so bounded typedefs are newtypes
+		let code = gen("typedef string name<16>; typedef int ids<>;").unwrap();
+		assert!(code.contains("pub struct Name(#[serde(serialize_with"), "{}", code);
+		assert!(code.contains("pub type Ids = Vec<i32>;"), "{}", code);
+
+		assert!(gen("struct s { string a<-1>; };").is_err());
+		assert!(gen("struct s { opaque a<4294967296>; };").is_err());
+	}
+}
@@ -0,0 +1,131 @@
+
+use std::io;
+
+use super::err;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+	Ident(String),
+	Number(i64),
+	Punct(char),
+}
+
+// Split an XDR/RPC language source file into tokens, each tagged with the line it came from.  Comments, rpcgen
+// pass-through lines (starting with %) and preprocessor lines (starting with #) are skipped.
+pub fn tokenize(src:&str) -> io::Result<Vec<(usize, Token)>> {
+	let mut ans:Vec<(usize, Token)> = vec![];
+	let chars:Vec<char> = src.chars().collect();
+	let mut idx:usize = 0;
+	let mut line:usize = 1;
+	let mut line_start:bool = true;
+
+	while idx < chars.len() {
+		let c:char = chars[idx];
+
+		if c == '\n' {
+			line += 1;
+			line_start = true;
+			idx += 1;
+			continue;
+		}
+		if c.is_whitespace() {
+			idx += 1;
+			continue;
+		}
+
+		// Lines that rpcgen would either pass through or hand to the C preprocessor
+		if line_start && (c == '%' || c == '#') {
+			while idx < chars.len() && chars[idx] != '\n' { idx += 1; }
+			continue;
+		}
+		line_start = false;
+
+		if c == '/' && chars.get(idx + 1) == Some(&'*') {
+			idx += 2;
+			while idx < chars.len() && !(chars[idx] == '*' && chars.get(idx + 1) == Some(&'/')) {
+				if chars[idx] == '\n' { line += 1; }
+				idx += 1;
+			}
+			if idx >= chars.len() { return Err(err(line, "Unterminated comment")); }
+			idx += 2;
+		} else if c == '/' && chars.get(idx + 1) == Some(&'/') {
+			while idx < chars.len() && chars[idx] != '\n' { idx += 1; }
+		} else if c.is_ascii_alphabetic() || c == '_' {
+			let start:usize = idx;
+			while idx < chars.len() && (chars[idx].is_ascii_alphanumeric() || chars[idx] == '_') { idx += 1; }
+			ans.push((line, Token::Ident(chars[start..idx].iter().collect())));
+		} else if c.is_ascii_digit() || (c == '-' && chars.get(idx + 1).is_some_and(|d| d.is_ascii_digit())) {
+			let start:usize = idx;
+			idx += 1;
+			while idx < chars.len() && chars[idx].is_ascii_alphanumeric() { idx += 1; }
+			let text:String = chars[start..idx].iter().collect();
+			ans.push((line, Token::Number(parse_number(&text).ok_or_else(|| err(line, &format!("Invalid number {}", text)))?)));
+		} else if "{}()[]<>;,=:*".contains(c) {
+			ans.push((line, Token::Punct(c)));
+			idx += 1;
+		} else {
+			return Err(err(line, &format!("Unexpected character '{}'", c)));
+		}
+	}
+
+	Ok(ans)
+}
+
+// Decimal, hexadecimal (0x...) and octal (leading 0) constants, as in C
+fn parse_number(text:&str) -> Option<i64> {
+	let (neg, digits) = match text.strip_prefix('-') {
+		Some(rest) => (true, rest),
+		None       => (false, text),
+	};
+
+	let value:i64 = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+		i64::from_str_radix(hex, 16).ok()?
+	} else if digits.len() > 1 && digits.starts_with('0') {
+		i64::from_str_radix(&digits[1..], 8).ok()?
+	} else {
+		digits.parse::<i64>().ok()?
+	};
+
+	Some(if neg { -value } else { value })
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn tokens(src:&str) -> Vec<Token> {
+		tokenize(src).unwrap().into_iter().map(|(_, t)| t).collect()
+	}
+
+	#[test]
+	fn comments_and_passthrough_lines_are_skipped() {
+		let src = "%#include <rpc/rpc.h>\n#define X 1\n/* block\n comment */ const A = 1; // rest of the line\n";
+		let ans = tokenize(src).unwrap();
+		assert_eq!(ans, vec![
+			(4, Token::Ident("const".to_owned())),
+			(4, Token::Ident("A".to_owned())),
+			(4, Token::Punct('=')),
+			(4, Token::Number(1)),
+			(4, Token::Punct(';')),
+		]);
+	}
+
+	#[test]
+	fn numbers_in_each_base() {
+		assert_eq!(tokens("10 0x0607AF 0X1f 017 0 -5 -0x10"), vec![
+			Token::Number(10), Token::Number(0x0607AF), Token::Number(0x1f), Token::Number(0o17),
+			Token::Number(0), Token::Number(-5), Token::Number(-16),
+		]);
+		assert!(tokenize("09").is_err());
+		assert!(tokenize("12abc").is_err());
+	}
+
+	#[test]
+	fn errors_give_the_line() {
+		let e = tokenize("const A = 1;\n\n/* never closed").unwrap_err();
+		assert!(e.to_string().starts_with("line 3:"), "{}", e);
+
+		let e = tokenize("struct s {\n\tint a @;\n};").unwrap_err();
+		assert!(e.to_string().starts_with("line 2:"), "{}", e);
+	}
+}
@@ -0,0 +1,467 @@
+
+use std::io;
+
+use super::err;
+use super::lexer::Token;
+
+// Syntax tree for the XDR language (RFC 4506 section 6) plus program definitions (RFC 5531 section 12)
+
+#[derive(Debug, Clone)]
+pub enum Value {
+	Number(i64),
+	Const(String),
+}
+
+#[derive(Debug, Clone)]
+pub enum TypeSpec {
+	Int, UInt,
+	Short, UShort,
+	Char, UChar,
+	Hyper, UHyper,
+	Float, Double,
+	Bool,
+	Named(String),
+}
+
+#[derive(Debug, Clone)]
+pub enum Decl {
+	Void,
+	Simple{ ty:TypeSpec, name:String },
+	FixedArray{ ty:TypeSpec, name:String, len:Value },
+	VarArray{ ty:TypeSpec, name:String, max:Option<Value> },
+	FixedOpaque{ name:String, len:Value },
+	VarOpaque{ name:String, max:Option<Value> },
+	Str{ name:String, max:Option<Value> },
+	Optional{ ty:TypeSpec, name:String },
+}
+
+#[derive(Debug, Clone)]
+pub struct UnionArm {
+	pub cases: Vec<Value>,
+	pub decl: Decl,
+}
+
+#[derive(Debug, Clone)]
+pub struct Procedure {
+	pub name: String,
+	pub number: Value,
+	pub result: Option<TypeSpec>,
+	pub args: Vec<TypeSpec>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Version {
+	pub name: String,
+	pub number: Value,
+	pub procedures: Vec<Procedure>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Definition {
+	Const{ name:String, value:Value },
+	Typedef(Decl),
+	Enum{ name:String, variants:Vec<(String, Value)> },
+	Struct{ name:String, fields:Vec<Decl> },
+	Union{ name:String, discriminant:Decl, arms:Vec<UnionArm>, default:Option<Decl> },
+	Program{ name:String, number:Value, versions:Vec<Version> },
+}
+
+impl Decl {
+	pub fn name(&self) -> Option<&str> {
+		match self {
+			Decl::Void => None,
+			Decl::Simple{ name, .. } | Decl::FixedArray{ name, .. } | Decl::VarArray{ name, .. } |
+			Decl::FixedOpaque{ name, .. } | Decl::VarOpaque{ name, .. } | Decl::Str{ name, .. } |
+			Decl::Optional{ name, .. } => Some(name),
+		}
+	}
+}
+
+pub struct Parser {
+	tokens: Vec<(usize, Token)>,
+	idx: usize,
+}
+
+impl Parser {
+
+	pub fn new(tokens:Vec<(usize, Token)>) -> Self { Self{ tokens, idx: 0 } }
+
+	fn line(&self) -> usize {
+		self.tokens.get(self.idx).or(self.tokens.last()).map(|(line, _)| *line).unwrap_or(0)
+	}
+
+	fn peek(&self) -> Option<&Token> { self.tokens.get(self.idx).map(|(_, t)| t) }
+
+	fn next(&mut self) -> io::Result<Token> {
+		let line:usize = self.line();
+		let ans = self.tokens.get(self.idx).map(|(_, t)| t.clone()).ok_or_else(|| err(line, "Unexpected end of file"))?;
+		self.idx += 1;
+		Ok(ans)
+	}
+
+	fn peek_is_keyword(&self, kw:&str) -> bool {
+		matches!(self.peek(), Some(Token::Ident(s)) if s == kw)
+	}
+
+	fn peek_is_punct(&self, c:char) -> bool { self.peek() == Some(&Token::Punct(c)) }
+
+	fn expect_punct(&mut self, c:char) -> io::Result<()> {
+		let line:usize = self.line();
+		match self.next()? {
+			Token::Punct(p) if p == c => Ok(()),
+			t => Err(err(line, &format!("Expected '{}' but found {:?}", c, t))),
+		}
+	}
+
+	fn expect_keyword(&mut self, kw:&str) -> io::Result<()> {
+		let line:usize = self.line();
+		match self.next()? {
+			Token::Ident(s) if s == kw => Ok(()),
+			t => Err(err(line, &format!("Expected '{}' but found {:?}", kw, t))),
+		}
+	}
+
+	fn ident(&mut self) -> io::Result<String> {
+		let line:usize = self.line();
+		match self.next()? {
+			Token::Ident(s) => Ok(s),
+			t => Err(err(line, &format!("Expected an identifier but found {:?}", t))),
+		}
+	}
+
+	fn value(&mut self) -> io::Result<Value> {
+		let line:usize = self.line();
+		match self.next()? {
+			Token::Number(n) => Ok(Value::Number(n)),
+			Token::Ident(s)  => Ok(Value::Const(s)),
+			t => Err(err(line, &format!("Expected a constant but found {:?}", t))),
+		}
+	}
+
+	pub fn parse(&mut self) -> io::Result<Vec<Definition>> {
+		let mut ans:Vec<Definition> = vec![];
+		while self.peek().is_some() {
+			ans.push(self.definition()?);
+		}
+		Ok(ans)
+	}
+
+	fn definition(&mut self) -> io::Result<Definition> {
+		let line:usize = self.line();
+		let def = match self.ident()?.as_str() {
+			"const" => {
+				let name:String = self.ident()?;
+				self.expect_punct('=')?;
+				Definition::Const{ name, value: self.value()? }
+			},
+			"typedef" => Definition::Typedef(self.declaration()?),
+			"enum" => {
+				let name:String = self.ident()?;
+				Definition::Enum{ name, variants: self.enum_body()? }
+			},
+			"struct" => {
+				let name:String = self.ident()?;
+				Definition::Struct{ name, fields: self.struct_body()? }
+			},
+			"union" => {
+				let name:String = self.ident()?;
+				let (discriminant, arms, default) = self.union_body()?;
+				Definition::Union{ name, discriminant, arms, default }
+			},
+			"program" => self.program()?,
+			s => return Err(err(line, &format!("Expected a definition but found '{}'", s))),
+		};
+		self.expect_punct(';')?;
+		Ok(def)
+	}
+
+	fn enum_body(&mut self) -> io::Result<Vec<(String, Value)>> {
+		self.expect_punct('{')?;
+		let mut ans:Vec<(String, Value)> = vec![];
+		let mut next_value:i64 = 0;
+		loop {
+			let name:String = self.ident()?;
+
+			// rpcgen allows the values to be left off, in which case they count up from the previous one like in C
+			let value:Value = if self.peek_is_punct('=') {
+				self.next()?;
+				self.value()?
+			} else {
+				Value::Number(next_value)
+			};
+			if let Value::Number(n) = value { next_value = n + 1; }
+			ans.push((name, value));
+
+			if self.peek_is_punct(',') { self.next()?; }
+			else { break; }
+		}
+		self.expect_punct('}')?;
+		Ok(ans)
+	}
+
+	fn struct_body(&mut self) -> io::Result<Vec<Decl>> {
+		self.expect_punct('{')?;
+		let mut ans:Vec<Decl> = vec![];
+		while !self.peek_is_punct('}') {
+			ans.push(self.declaration()?);
+			self.expect_punct(';')?;
+		}
+		self.expect_punct('}')?;
+		Ok(ans)
+	}
+
+	fn union_body(&mut self) -> io::Result<(Decl, Vec<UnionArm>, Option<Decl>)> {
+		self.expect_keyword("switch")?;
+		self.expect_punct('(')?;
+		let discriminant:Decl = self.declaration()?;
+		self.expect_punct(')')?;
+		self.expect_punct('{')?;
+
+		let mut arms:Vec<UnionArm> = vec![];
+		let mut default:Option<Decl> = None;
+		while !self.peek_is_punct('}') {
+			if self.peek_is_keyword("default") {
+				self.next()?;
+				self.expect_punct(':')?;
+				default = Some(self.declaration()?);
+				self.expect_punct(';')?;
+				continue;
+			}
+
+			let mut cases:Vec<Value> = vec![];
+			while self.peek_is_keyword("case") {
+				self.next()?;
+				cases.push(self.value()?);
+				self.expect_punct(':')?;
+			}
+			if cases.is_empty() { return Err(err(self.line(), "Expected 'case' or 'default' in union body")); }
+
+			let decl:Decl = self.declaration()?;
+			self.expect_punct(';')?;
+			arms.push(UnionArm{ cases, decl });
+		}
+		self.expect_punct('}')?;
+		Ok((discriminant, arms, default))
+	}
+
+	fn program(&mut self) -> io::Result<Definition> {
+		let name:String = self.ident()?;
+		self.expect_punct('{')?;
+		let mut versions:Vec<Version> = vec![];
+		while !self.peek_is_punct('}') {
+			self.expect_keyword("version")?;
+			let vers_name:String = self.ident()?;
+			self.expect_punct('{')?;
+			let mut procedures:Vec<Procedure> = vec![];
+			while !self.peek_is_punct('}') {
+				procedures.push(self.procedure()?);
+			}
+			self.expect_punct('}')?;
+			self.expect_punct('=')?;
+			let number:Value = self.value()?;
+			self.expect_punct(';')?;
+			versions.push(Version{ name: vers_name, number, procedures });
+		}
+		self.expect_punct('}')?;
+		self.expect_punct('=')?;
+		Ok(Definition::Program{ name, number: self.value()?, versions })
+	}
+
+	fn procedure(&mut self) -> io::Result<Procedure> {
+		let result:Option<TypeSpec> = self.type_spec_or_void()?;
+		let name:String = self.ident()?;
+		self.expect_punct('(')?;
+		let mut args:Vec<TypeSpec> = vec![];
+		loop {
+			if let Some(ty) = self.type_spec_or_void()? { args.push(ty); }
+			if self.peek_is_punct(',') { self.next()?; }
+			else { break; }
+		}
+		self.expect_punct(')')?;
+		self.expect_punct('=')?;
+		let number:Value = self.value()?;
+		self.expect_punct(';')?;
+		Ok(Procedure{ name, number, result, args })
+	}
+
+	fn type_spec_or_void(&mut self) -> io::Result<Option<TypeSpec>> {
+		if self.peek_is_keyword("void") {
+			self.next()?;
+			Ok(None)
+		} else {
+			self.type_spec().map(Some)
+		}
+	}
+
+	fn type_spec(&mut self) -> io::Result<TypeSpec> {
+		let line:usize = self.line();
+		let ty = match self.ident()?.as_str() {
+			"unsigned" => {
+				// "unsigned" on its own means unsigned int
+				let sized:Option<String> = match self.peek() {
+					Some(Token::Ident(s)) if ["int", "long", "short", "char", "hyper"].contains(&s.as_str()) => Some(s.clone()),
+					_ => None,
+				};
+				if sized.is_some() { self.next()?; }
+				match sized.as_deref() {
+					Some("short") => TypeSpec::UShort,
+					Some("char")  => TypeSpec::UChar,
+					Some("hyper") => TypeSpec::UHyper,
+					_             => TypeSpec::UInt,
+				}
+			},
+			"int" | "long" => TypeSpec::Int,
+			"short"        => TypeSpec::Short,
+			"char"         => TypeSpec::Char,
+			"hyper"        => TypeSpec::Hyper,
+			"float"        => TypeSpec::Float,
+			"double"       => TypeSpec::Double,
+			"bool"         => TypeSpec::Bool,
+			"quadruple"    => return Err(err(line, "quadruple has no Rust equivalent")),
+			"struct" | "enum" | "union" => {
+				let name:String = self.ident()?;
+				if self.peek_is_punct('{') || self.peek_is_keyword("switch") {
+					return Err(err(line, "Anonymous nested type definitions aren't supported, give the type a name instead"));
+				}
+				TypeSpec::Named(name)
+			},
+			s => TypeSpec::Named(s.to_owned()),
+		};
+		Ok(ty)
+	}
+
+	fn declaration(&mut self) -> io::Result<Decl> {
+		if self.peek_is_keyword("void") {
+			self.next()?;
+			return Ok(Decl::Void);
+		}
+
+		if self.peek_is_keyword("opaque") {
+			self.next()?;
+			let name:String = self.ident()?;
+			return match self.bounds()? {
+				(true, len)  => Ok(Decl::FixedOpaque{ name, len: len.ok_or_else(|| err(self.line(), "Fixed-length opaque data needs a length"))? }),
+				(false, max) => Ok(Decl::VarOpaque{ name, max }),
+			};
+		}
+
+		if self.peek_is_keyword("string") {
+			self.next()?;
+			let name:String = self.ident()?;
+			return match self.bounds()? {
+				(false, max) => Ok(Decl::Str{ name, max }),
+				(true, _)    => Err(err(self.line(), "Strings must be declared with <>")),
+			};
+		}
+
+		let ty:TypeSpec = self.type_spec()?;
+		if self.peek_is_punct('*') {
+			self.next()?;
+			return Ok(Decl::Optional{ ty, name: self.ident()? });
+		}
+
+		let name:String = self.ident()?;
+		if self.peek_is_punct('[') || self.peek_is_punct('<') {
+			return match self.bounds()? {
+				(true, len)  => Ok(Decl::FixedArray{ ty, name, len: len.ok_or_else(|| err(self.line(), "Fixed-length arrays need a length"))? }),
+				(false, max) => Ok(Decl::VarArray{ ty, name, max }),
+			};
+		}
+		Ok(Decl::Simple{ ty, name })
+	}
+
+	// Either [len] for fixed-length data or <max> for variable-length data, where the maximum is optional
+	fn bounds(&mut self) -> io::Result<(bool, Option<Value>)> {
+		if self.peek_is_punct('[') {
+			self.next()?;
+			let len:Value = self.value()?;
+			self.expect_punct(']')?;
+			Ok((true, Some(len)))
+		} else {
+			self.expect_punct('<')?;
+			if self.peek_is_punct('>') {
+				self.next()?;
+				return Ok((false, None));
+			}
+			let max:Value = self.value()?;
+			self.expect_punct('>')?;
+			Ok((false, Some(max)))
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use super::super::lexer::tokenize;
+
+	fn parse(src:&str) -> io::Result<Vec<Definition>> {
+		Parser::new(tokenize(src)?).parse()
+	}
+
+	#[test]
+	fn enum_values_count_up_from_the_last_one() {
+		let defs = parse("enum e { A, B = 5, C, D = X, E };").unwrap();
+		let variants:Vec<(String, String)> = match &defs[0] {
+			Definition::Enum{ variants, .. } => variants.iter().map(|(n, v)| (n.clone(), format!("{:?}", v))).collect(),
+			d => panic!("{:?}", d),
+		};
+		assert_eq!(variants, vec![
+			("A".to_owned(), "Number(0)".to_owned()),
+			("B".to_owned(), "Number(5)".to_owned()),
+			("C".to_owned(), "Number(6)".to_owned()),
+			("D".to_owned(), "Const(\"X\")".to_owned()),
+			("E".to_owned(), "Number(7)".to_owned()),
+		]);
+	}
+
+	#[test]
+	fn declarations() {
+		let defs = parse("struct s { unsigned a; unsigned short b; opaque c[4]; opaque d<>; string e<10>; int f<>; s *next; };").unwrap();
+		let fields:&[Decl] = match &defs[0] {
+			Definition::Struct{ fields, .. } => fields,
+			d => panic!("{:?}", d),
+		};
+		assert!(matches!(&fields[0], Decl::Simple{ ty: TypeSpec::UInt, name } if name == "a"));
+		assert!(matches!(&fields[1], Decl::Simple{ ty: TypeSpec::UShort, .. }));
+		assert!(matches!(&fields[2], Decl::FixedOpaque{ len: Value::Number(4), .. }));
+		assert!(matches!(&fields[3], Decl::VarOpaque{ max: None, .. }));
+		assert!(matches!(&fields[4], Decl::Str{ max: Some(Value::Number(10)), .. }));
+		assert!(matches!(&fields[5], Decl::VarArray{ ty: TypeSpec::Int, max: None, .. }));
+		assert!(matches!(&fields[6], Decl::Optional{ ty: TypeSpec::Named(t), .. } if t == "s"));
+	}
+
+	#[test]
+	fn union_with_shared_cases_and_default() {
+		let defs = parse("union u switch (int kind) { case 1: case 2: int a; case 3: void; default: void; };").unwrap();
+		match &defs[0] {
+			Definition::Union{ arms, default, .. } => {
+				assert_eq!(arms.len(), 2);
+				assert_eq!(arms[0].cases.len(), 2);
+				assert!(matches!(arms[1].decl, Decl::Void));
+				assert!(matches!(default, Some(Decl::Void)));
+			},
+			d => panic!("{:?}", d),
+		}
+	}
+
+	#[test]
+	fn program_with_several_and_no_arguments() {
+		let defs = parse("program P { version V { void ping(void) = 0; int add(int, int) = 1; } = 2; } = 0x20000000;").unwrap();
+		match &defs[0] {
+			Definition::Program{ versions, number: Value::Number(0x20000000), .. } => {
+				assert!(versions[0].procedures[0].args.is_empty());
+				assert!(versions[0].procedures[0].result.is_none());
+				assert_eq!(versions[0].procedures[1].args.len(), 2);
+			},
+			d => panic!("{:?}", d),
+		}
+	}
+
+	#[test]
+	fn syntax_errors() {
+		for src in ["struct s { int a }", "struct s { string a[4]; };", "union u switch (int k) { int a; };", "typedef quadruple q;", "const A = ;"] {
+			assert!(parse(src).is_err(), "{}", src);
+		}
+	}
+}
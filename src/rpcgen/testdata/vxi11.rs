@@ -0,0 +1,332 @@
+// Generated by rpcgen, do not edit by hand
+
+pub type DeviceLink = i32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ::serde::Serialize, ::serde::Deserialize)]
+pub enum DeviceAddrFamily {
+	#[serde(rename = "0")]
+	DeviceTcp,
+	#[serde(rename = "1")]
+	DeviceUdp,
+}
+
+pub type DeviceFlags = i32;
+
+pub type DeviceErrorCode = i32;
+
+#[derive(Debug, Clone, PartialEq, ::serde::Serialize, ::serde::Deserialize)]
+pub struct DeviceError {
+	pub error: DeviceErrorCode,
+}
+
+#[derive(Debug, Clone, PartialEq, ::serde::Serialize, ::serde::Deserialize)]
+pub struct CreateLinkParms {
+	pub client_id: i32,
+	pub lock_device: bool,
+	pub lock_timeout: u32,
+	pub device: String,
+}
+
+#[derive(Debug, Clone, PartialEq, ::serde::Serialize, ::serde::Deserialize)]
+pub struct CreateLinkResp {
+	pub error: DeviceErrorCode,
+	pub lid: DeviceLink,
+	pub abort_port: u16,
+	pub max_recv_size: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, ::serde::Serialize, ::serde::Deserialize)]
+pub struct DeviceWriteParms {
+	pub lid: DeviceLink,
+	pub io_timeout: u32,
+	pub lock_timeout: u32,
+	pub flags: DeviceFlags,
+	#[serde(with = "crate::xdr::opaque::variable")]
+	pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, ::serde::Serialize, ::serde::Deserialize)]
+pub struct DeviceWriteResp {
+	pub error: DeviceErrorCode,
+	pub size: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, ::serde::Serialize, ::serde::Deserialize)]
+pub struct DeviceReadParms {
+	pub lid: DeviceLink,
+	pub request_size: u32,
+	pub io_timeout: u32,
+	pub lock_timeout: u32,
+	pub flags: DeviceFlags,
+	pub term_char: i8,
+}
+
+#[derive(Debug, Clone, PartialEq, ::serde::Serialize, ::serde::Deserialize)]
+pub struct DeviceReadResp {
+	pub error: DeviceErrorCode,
+	pub reason: i32,
+	#[serde(with = "crate::xdr::opaque::variable")]
+	pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, ::serde::Serialize, ::serde::Deserialize)]
+pub struct DeviceReadStbResp {
+	pub error: DeviceErrorCode,
+	pub stb: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, ::serde::Serialize, ::serde::Deserialize)]
+pub struct DeviceGenericParms {
+	pub lid: DeviceLink,
+	pub flags: DeviceFlags,
+	pub lock_timeout: u32,
+	pub io_timeout: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, ::serde::Serialize, ::serde::Deserialize)]
+pub struct DeviceRemoteFunc {
+	pub host_addr: u32,
+	pub host_port: u16,
+	pub prog_num: u32,
+	pub prog_vers: u32,
+	pub prog_family: DeviceAddrFamily,
+}
+
+#[derive(Debug, Clone, PartialEq, ::serde::Serialize, ::serde::Deserialize)]
+pub struct DeviceEnableSrqParms {
+	pub lid: DeviceLink,
+	pub enable: bool,
+	#[serde(serialize_with = "crate::xdr::bounded::opaque::serialize::<_, 40>", deserialize_with = "crate::xdr::bounded::opaque::deserialize::<_, 40>")]
+	pub handle: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, ::serde::Serialize, ::serde::Deserialize)]
+pub struct DeviceLockParms {
+	pub lid: DeviceLink,
+	pub flags: DeviceFlags,
+	pub lock_timeout: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, ::serde::Serialize, ::serde::Deserialize)]
+pub struct DeviceDocmdParms {
+	pub lid: DeviceLink,
+	pub flags: DeviceFlags,
+	pub io_timeout: u32,
+	pub lock_timeout: u32,
+	pub cmd: i32,
+	pub network_order: bool,
+	pub datasize: i32,
+	#[serde(with = "crate::xdr::opaque::variable")]
+	pub data_in: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, ::serde::Serialize, ::serde::Deserialize)]
+pub struct DeviceDocmdResp {
+	pub error: DeviceErrorCode,
+	#[serde(with = "crate::xdr::opaque::variable")]
+	pub data_out: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, ::serde::Serialize, ::serde::Deserialize)]
+pub struct DeviceSrqParms {
+	#[serde(with = "crate::xdr::opaque::variable")]
+	pub handle: Vec<u8>,
+}
+
+pub const DEVICE_ASYNC:u32 = 395184;
+pub const DEVICE_ASYNC_VERSION:u32 = 1;
+pub const DEVICE_ABORT:u32 = 1;
+
+pub struct DeviceAsyncVersionClient<T: crate::rpc::transport::RpcTransport = crate::rpc::tcp_clients::TcpTransport> {
+	pub client: crate::rpc::client::RpcClient<T>,
+}
+
+impl DeviceAsyncVersionClient<crate::rpc::tcp_clients::TcpTransport> {
+
+	pub fn connect<A: ::std::net::ToSocketAddrs>(addr:A) -> ::std::io::Result<Self> {
+		Ok(Self{ client: crate::rpc::tcp_clients::TcpClient::connect(addr, DEVICE_ASYNC, DEVICE_ASYNC_VERSION)? })
+	}
+}
+
+impl<T: crate::rpc::transport::RpcTransport> DeviceAsyncVersionClient<T> {
+
+	pub fn with_transport(transport:T) -> Self {
+		Self{ client: crate::rpc::client::RpcClient::new(transport, DEVICE_ASYNC, DEVICE_ASYNC_VERSION) }
+	}
+
+	pub fn device_abort(&mut self, arg:&DeviceLink) -> ::std::io::Result<DeviceError> {
+		self.client.start_call(DEVICE_ABORT)?;
+		self.client.packer.pack(arg)?;
+		self.client.do_call()?;
+		Ok(self.client.unpacker.unpack()?)
+	}
+}
+
+pub const DEVICE_CORE:u32 = 395183;
+pub const DEVICE_CORE_VERSION:u32 = 1;
+pub const CREATE_LINK:u32 = 10;
+pub const DEVICE_WRITE:u32 = 11;
+pub const DEVICE_READ:u32 = 12;
+pub const DEVICE_READSTB:u32 = 13;
+pub const DEVICE_TRIGGER:u32 = 14;
+pub const DEVICE_CLEAR:u32 = 15;
+pub const DEVICE_REMOTE:u32 = 16;
+pub const DEVICE_LOCAL:u32 = 17;
+pub const DEVICE_LOCK:u32 = 18;
+pub const DEVICE_UNLOCK:u32 = 19;
+pub const DEVICE_ENABLE_SRQ:u32 = 20;
+pub const DEVICE_DOCMD:u32 = 22;
+pub const DESTROY_LINK:u32 = 23;
+pub const CREATE_INTR_CHAN:u32 = 25;
+pub const DESTROY_INTR_CHAN:u32 = 26;
+
+pub struct DeviceCoreVersionClient<T: crate::rpc::transport::RpcTransport = crate::rpc::tcp_clients::TcpTransport> {
+	pub client: crate::rpc::client::RpcClient<T>,
+}
+
+impl DeviceCoreVersionClient<crate::rpc::tcp_clients::TcpTransport> {
+
+	pub fn connect<A: ::std::net::ToSocketAddrs>(addr:A) -> ::std::io::Result<Self> {
+		Ok(Self{ client: crate::rpc::tcp_clients::TcpClient::connect(addr, DEVICE_CORE, DEVICE_CORE_VERSION)? })
+	}
+}
+
+impl<T: crate::rpc::transport::RpcTransport> DeviceCoreVersionClient<T> {
+
+	pub fn with_transport(transport:T) -> Self {
+		Self{ client: crate::rpc::client::RpcClient::new(transport, DEVICE_CORE, DEVICE_CORE_VERSION) }
+	}
+
+	pub fn create_link(&mut self, arg:&CreateLinkParms) -> ::std::io::Result<CreateLinkResp> {
+		self.client.start_call(CREATE_LINK)?;
+		self.client.packer.pack(arg)?;
+		self.client.do_call()?;
+		Ok(self.client.unpacker.unpack()?)
+	}
+
+	pub fn device_write(&mut self, arg:&DeviceWriteParms) -> ::std::io::Result<DeviceWriteResp> {
+		self.client.start_call(DEVICE_WRITE)?;
+		self.client.packer.pack(arg)?;
+		self.client.do_call()?;
+		Ok(self.client.unpacker.unpack()?)
+	}
+
+	pub fn device_read(&mut self, arg:&DeviceReadParms) -> ::std::io::Result<DeviceReadResp> {
+		self.client.start_call(DEVICE_READ)?;
+		self.client.packer.pack(arg)?;
+		self.client.do_call()?;
+		Ok(self.client.unpacker.unpack()?)
+	}
+
+	pub fn device_readstb(&mut self, arg:&DeviceGenericParms) -> ::std::io::Result<DeviceReadStbResp> {
+		self.client.start_call(DEVICE_READSTB)?;
+		self.client.packer.pack(arg)?;
+		self.client.do_call()?;
+		Ok(self.client.unpacker.unpack()?)
+	}
+
+	pub fn device_trigger(&mut self, arg:&DeviceGenericParms) -> ::std::io::Result<DeviceError> {
+		self.client.start_call(DEVICE_TRIGGER)?;
+		self.client.packer.pack(arg)?;
+		self.client.do_call()?;
+		Ok(self.client.unpacker.unpack()?)
+	}
+
+	pub fn device_clear(&mut self, arg:&DeviceGenericParms) -> ::std::io::Result<DeviceError> {
+		self.client.start_call(DEVICE_CLEAR)?;
+		self.client.packer.pack(arg)?;
+		self.client.do_call()?;
+		Ok(self.client.unpacker.unpack()?)
+	}
+
+	pub fn device_remote(&mut self, arg:&DeviceGenericParms) -> ::std::io::Result<DeviceError> {
+		self.client.start_call(DEVICE_REMOTE)?;
+		self.client.packer.pack(arg)?;
+		self.client.do_call()?;
+		Ok(self.client.unpacker.unpack()?)
+	}
+
+	pub fn device_local(&mut self, arg:&DeviceGenericParms) -> ::std::io::Result<DeviceError> {
+		self.client.start_call(DEVICE_LOCAL)?;
+		self.client.packer.pack(arg)?;
+		self.client.do_call()?;
+		Ok(self.client.unpacker.unpack()?)
+	}
+
+	pub fn device_lock(&mut self, arg:&DeviceLockParms) -> ::std::io::Result<DeviceError> {
+		self.client.start_call(DEVICE_LOCK)?;
+		self.client.packer.pack(arg)?;
+		self.client.do_call()?;
+		Ok(self.client.unpacker.unpack()?)
+	}
+
+	pub fn device_unlock(&mut self, arg:&DeviceLink) -> ::std::io::Result<DeviceError> {
+		self.client.start_call(DEVICE_UNLOCK)?;
+		self.client.packer.pack(arg)?;
+		self.client.do_call()?;
+		Ok(self.client.unpacker.unpack()?)
+	}
+
+	pub fn device_enable_srq(&mut self, arg:&DeviceEnableSrqParms) -> ::std::io::Result<DeviceError> {
+		self.client.start_call(DEVICE_ENABLE_SRQ)?;
+		self.client.packer.pack(arg)?;
+		self.client.do_call()?;
+		Ok(self.client.unpacker.unpack()?)
+	}
+
+	pub fn device_docmd(&mut self, arg:&DeviceDocmdParms) -> ::std::io::Result<DeviceDocmdResp> {
+		self.client.start_call(DEVICE_DOCMD)?;
+		self.client.packer.pack(arg)?;
+		self.client.do_call()?;
+		Ok(self.client.unpacker.unpack()?)
+	}
+
+	pub fn destroy_link(&mut self, arg:&DeviceLink) -> ::std::io::Result<DeviceError> {
+		self.client.start_call(DESTROY_LINK)?;
+		self.client.packer.pack(arg)?;
+		self.client.do_call()?;
+		Ok(self.client.unpacker.unpack()?)
+	}
+
+	pub fn create_intr_chan(&mut self, arg:&DeviceRemoteFunc) -> ::std::io::Result<DeviceError> {
+		self.client.start_call(CREATE_INTR_CHAN)?;
+		self.client.packer.pack(arg)?;
+		self.client.do_call()?;
+		Ok(self.client.unpacker.unpack()?)
+	}
+
+	pub fn destroy_intr_chan(&mut self) -> ::std::io::Result<DeviceError> {
+		self.client.start_call(DESTROY_INTR_CHAN)?;
+		self.client.do_call()?;
+		Ok(self.client.unpacker.unpack()?)
+	}
+}
+
+pub const DEVICE_INTR:u32 = 395185;
+pub const DEVICE_INTR_VERSION:u32 = 1;
+pub const DEVICE_INTR_SRQ:u32 = 30;
+
+pub struct DeviceIntrVersionClient<T: crate::rpc::transport::RpcTransport = crate::rpc::tcp_clients::TcpTransport> {
+	pub client: crate::rpc::client::RpcClient<T>,
+}
+
+impl DeviceIntrVersionClient<crate::rpc::tcp_clients::TcpTransport> {
+
+	pub fn connect<A: ::std::net::ToSocketAddrs>(addr:A) -> ::std::io::Result<Self> {
+		Ok(Self{ client: crate::rpc::tcp_clients::TcpClient::connect(addr, DEVICE_INTR, DEVICE_INTR_VERSION)? })
+	}
+}
+
+impl<T: crate::rpc::transport::RpcTransport> DeviceIntrVersionClient<T> {
+
+	pub fn with_transport(transport:T) -> Self {
+		Self{ client: crate::rpc::client::RpcClient::new(transport, DEVICE_INTR, DEVICE_INTR_VERSION) }
+	}
+
+	pub fn device_intr_srq(&mut self, arg:&DeviceSrqParms) -> ::std::io::Result<()> {
+		self.client.start_call(DEVICE_INTR_SRQ)?;
+		self.client.packer.pack(arg)?;
+		self.client.do_call()?;
+		Ok(())
+	}
+}
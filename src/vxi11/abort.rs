@@ -0,0 +1,45 @@
+
+use std::sync::{Arc, Mutex};
+
+use crate::rpc::client::RpcClient;
+use crate::rpc::tcp_clients::TcpClient;
+use crate::rpc::transport::RpcTransport;
+use crate::error::Result;
+
+use super::{err, device_result, connect_tcp, reply_timeout, LinkOptions, DEVICE_ASYNC_PROG, DEVICE_ASYNC_VERS, DEVICE_ABORT};
+use super::types::DeviceError;
+
+// Sends device_abort for one link over the Device Async channel, which the instrument serves on a port of its own
+// so an abort can get through while the core channel is stuck in a long read or write.  Clones share the
+// connection, so a handle can be passed to another thread and used there.
+#[derive(Clone)]
+pub struct AbortHandle {
+    link_id: i32,
+    client: Arc<Mutex<TcpClient>>,
+}
+
+impl AbortHandle {
+
+    // Connects right away rather than on the first abort, since by then the instrument may be too busy to accept.
+    // The connection gets the connect and write timeouts from options, and a reply has as long as a call with the
+    // I/O timeout would.
+    pub fn connect(host:&str, abort_port:u16, link_id:i32, options:&LinkOptions) -> Result<Self> {
+        let mut transport = connect_tcp(host, abort_port, options)?;
+        transport.set_timeout(reply_timeout(options, options.io_timeout, 0))?;
+        let client:TcpClient = RpcClient::new(transport, DEVICE_ASYNC_PROG, DEVICE_ASYNC_VERS);
+        Ok(Self{ link_id, client: Arc::new(Mutex::new(client)) })
+    }
+
+    pub fn link_id(&self) -> i32 { self.link_id }
+
+    // Makes the call in progress on the link (if any) return with an abort error.  The link stays usable.
+    pub fn abort(&self) -> Result<()> {
+        let mut client = self.client.lock().map_err(|_| err("Abort channel poisoned"))?;
+        client.start_call(DEVICE_ABORT)?;
+        client.packer.pack_i32(self.link_id)?;
+        client.do_call()?;
+
+        let DeviceError{ error } = client.unpacker.unpack()?;
+        device_result(error)
+    }
+}
@@ -0,0 +1,490 @@
+
+use std::future::Future;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::{Mutex as AsyncMutex, MutexGuard as AsyncMutexGuard};
+
+use crate::rpc::lock_ignoring_poison;
+use crate::rpc::async_clients::{AsyncTcpClient, AsyncTcpTransport};
+use crate::rpc::port_mapping::{AsyncPortMapperClient, PMAP_PORT};
+use crate::rpc::rpcbind::{AsyncRpcbindClient, RPCBVERS};
+use crate::rpc::auth::Auth;
+
+use super::*;
+use super::types::{CreateLinkResp, DeviceReadStbResp, DeviceDocmdResp, DeviceError};
+
+// Async version of CoreClient.  Clones share the connection, and any number of links can be open on it at once.
+// Every call can be dropped part way through, for example by wrapping it in tokio::time::timeout, and the connection
+// stays usable for the next one.
+#[derive(Clone)]
+pub struct AsyncCoreClient {
+    shared: Arc<Shared>,
+}
+
+// Async version of DeviceLink.  A link that's dropped without being destroyed is destroyed before the next call on
+// the connection, since there's no way to make the call from drop.
+pub struct AsyncDeviceLink {
+    shared: Arc<Shared>,
+    link: Link,
+    device: String,
+    io_timeout: u32,
+    lock_timeout: u32,
+    destroyed: bool,
+}
+
+struct Shared {
+    conn: AsyncMutex<AsyncConnection>,
+    dropped_links: Mutex<Vec<i32>>,     // Links to destroy before the next call, kept apart so drop can get at them
+}
+
+struct AsyncConnection {
+    client: AsyncTcpClient,
+    client_id: i32,
+    options: LinkOptions,
+    creating: bool,       // A CREATE_LINK went out and its reply hasn't been unpacked
+}
+
+// Gives up on a connection attempt after the connect timeout
+async fn connect<T, F: Future<Output=io::Result<T>>>(options:&LinkOptions, connecting:F) -> Result<T> {
+    match nonzero(options.connect_timeout) {
+        Some(timeout) => tokio::time::timeout(timeout, connecting).await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "Timed out connecting"))?
+            .map_err(Error::from),
+        None => Ok(connecting.await?),
+    }
+}
+
+impl Shared {
+
+    // Locks the connection after tidying up whatever dropped calls and links left behind
+    async fn lock(&self) -> Result<AsyncMutexGuard<'_, AsyncConnection>> {
+        let mut conn = self.conn.lock().await;
+
+        if let Some(link_id) = conn.abandoned_link().await? {
+            lock_ignoring_poison(&self.dropped_links).push(link_id);
+        }
+
+        // Each link stays on the list until it's gone, in case this is dropped too.  Destroying one twice only gets
+        // an error from the device, which is ignored along with any other it sends.
+        loop {
+            let link_id:i32 = match lock_ignoring_poison(&self.dropped_links).last() {
+                Some(link_id) => *link_id,
+                None => return Ok(conn),
+            };
+            match conn.destroy_link(link_id).await {
+                Ok(()) | Err(Error::Device(_)) => { lock_ignoring_poison(&self.dropped_links).pop(); },
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl AsyncConnection {
+
+    async fn do_call(&mut self, io_timeout:u32, lock_timeout:u32) -> Result<()> {
+        self.client.transport.timeout = reply_timeout(&self.options, io_timeout, lock_timeout);
+        self.client.do_call().await?;
+        Ok(())
+    }
+
+    fn unpack_error(&mut self) -> Result<()> {
+        let DeviceError{ error } = self.client.unpacker.unpack()?;
+        device_result(error)
+    }
+
+    async fn create_link(&mut self, device:&str, lock_device:bool, lock_timeout:u32) -> Result<Link> {
+        if !device.is_ascii() {
+            return Err(err("Device names have to be ASCII"));
+        }
+
+        self.client.start_call(CREATE_LINK)?;
+        xdr_pack::pack_create_link_parms(&mut self.client.packer, self.client_id, lock_device, lock_timeout, device)?;
+        self.creating = true;
+        self.do_call(0, lock_timeout).await?;
+        self.creating = false;
+
+        let CreateLinkResp{ error, link } = self.client.unpacker.unpack()?;
+        device_result(error)?;
+        Ok(link)
+    }
+
+    // Waits for the reply to a CREATE_LINK that was dropped or timed out after it went out, and returns the link it
+    // made so that can be destroyed rather than left open on the instrument
+    async fn abandoned_link(&mut self) -> Result<Option<i32>> {
+        if !self.creating { return Ok(None); }
+
+        let result:Result<Option<i32>> = self.read_create_link_reply().await;
+        self.creating = false;
+        result
+    }
+
+    async fn read_create_link_reply(&mut self) -> Result<Option<i32>> {
+        let xid:u32 = self.client.lastxid;
+        if self.client.transport.unanswered() != Some(xid) { return Ok(None); }
+
+        self.client.transport.timeout = reply_timeout(&self.options, 0, self.options.lock_timeout);
+        let reply:Vec<u8> = self.client.transport.reply_to(xid).await?;
+
+        // A rejected credential means no link was made
+        if !self.client.handle_reply(reply)? { return Ok(None); }
+        let CreateLinkResp{ error, link } = self.client.unpacker.unpack()?;
+        Ok(if error == 0 { Some(link.link_id) } else { None })
+    }
+
+    async fn destroy_link(&mut self, link_id:i32) -> Result<()> {
+        self.client.start_call(DESTROY_LINK)?;
+        self.client.packer.pack_i32(link_id)?;
+        self.do_call(0, 0).await?;
+        self.unpack_error()
+    }
+}
+
+impl AsyncCoreClient {
+
+    pub async fn new(host:&str) -> Result<Self> {
+        Self::with_options(host, PMAP_PORT, LinkOptions::default()).await
+    }
+
+    pub async fn with_pmap_port(host:&str, pmap_port:u16) -> Result<Self> {
+        Self::with_options(host, pmap_port, LinkOptions::default()).await
+    }
+
+    // The write timeout isn't used, since the read timeout covers the whole call
+    pub async fn with_options(host:&str, pmap_port:u16, options:LinkOptions) -> Result<Self> {
+        let mut pmap_client = connect(&options, AsyncPortMapperClient::tcp(host, pmap_port)).await?;
+        pmap_client.client.transport.timeout = nonzero(options.read_timeout);
+        let mut port = pmap_client.get_port(&core_mapping()).await?;
+
+        // Same rpcbind fallback as CoreClient
+        if port == 0 {
+            let mut rpcb_client = connect(&options, AsyncRpcbindClient::tcp(host, pmap_port, RPCBVERS)).await?;
+            rpcb_client.client.transport.timeout = nonzero(options.read_timeout);
+            port = core_port(rpcb_client.get_addr(DEVICE_CORE_PROG, DEVICE_CORE_VERS, "tcp").await?)?;
+        }
+
+        let client = connect(&options, AsyncTcpClient::connect((host, port as u16), DEVICE_CORE_PROG, DEVICE_CORE_VERS)).await?;
+        Ok(Self::from_client(client, options))
+    }
+
+    // For a core program on a known port, for example a simulated instrument
+    pub fn with_transport(transport:AsyncTcpTransport) -> Self {
+        Self::from_client(AsyncTcpClient::new(transport, DEVICE_CORE_PROG, DEVICE_CORE_VERS), LinkOptions::default())
+    }
+
+    fn from_client(client:AsyncTcpClient, options:LinkOptions) -> Self {
+        let conn = AsyncConnection{ client, client_id: CLIENT_ID, options, creating: false };
+        AsyncCoreClient{ shared: Arc::new(Shared{ conn: AsyncMutex::new(conn), dropped_links: Mutex::new(vec![]) }) }
+    }
+
+    pub async fn options(&self) -> LinkOptions {
+        self.shared.conn.lock().await.options
+    }
+
+    // The I/O and lock timeouts apply to links created afterwards and the read timeout to the next call.  Connect
+    // and write timeouts don't matter once connected.
+    pub async fn set_options(&self, options:LinkOptions) {
+        self.shared.conn.lock().await.options = options;
+    }
+
+    pub async fn set_auth<A: Auth + 'static>(&self, auth:A) {
+        self.shared.conn.lock().await.client.set_auth(auth);
+    }
+
+    pub async fn set_client_id(&self, client_id:i32) {
+        self.shared.conn.lock().await.client_id = client_id;
+    }
+
+    // Same arguments as CoreClient::create_link.  If this is dropped after the call goes out, the link the instrument
+    // makes is destroyed before the next call.
+    pub async fn create_link(&self, device:&str, lock_device:bool, lock_timeout:u32) -> Result<AsyncDeviceLink> {
+        let mut conn = self.shared.lock().await?;
+        let link:Link = conn.create_link(device, lock_device, lock_timeout).await?;
+        let options:LinkOptions = conn.options;
+        Ok(AsyncDeviceLink{ shared: self.shared.clone(), link, device: device.to_owned(),
+            io_timeout: options.io_timeout, lock_timeout: options.lock_timeout, destroyed: false })
+    }
+}
+
+impl AsyncDeviceLink {
+
+    pub fn link_id(&self) -> i32 { self.link.link_id }
+
+    pub fn link(&self) -> &Link { &self.link }
+
+    pub fn device(&self) -> &str { &self.device }
+
+    pub fn io_timeout(&self) -> u32 { self.io_timeout }
+
+    pub fn lock_timeout(&self) -> u32 { self.lock_timeout }
+
+    pub fn set_io_timeout(&mut self, io_timeout:u32) { self.io_timeout = io_timeout; }
+
+    pub fn set_lock_timeout(&mut self, lock_timeout:u32) { self.lock_timeout = lock_timeout; }
+
+    fn timeouts(&self, io_timeout:Option<u32>, lock_timeout:Option<u32>) -> (u32, u32) {
+        (io_timeout.unwrap_or(self.io_timeout), lock_timeout.unwrap_or(self.lock_timeout))
+    }
+
+    pub async fn ask(&mut self, data:&[u8]) -> Result<Vec<u8>> {
+        self.write(data).await?;
+        self.read().await
+    }
+
+    pub async fn write(&mut self, data:&[u8]) -> Result<()> {
+        self.write_with(data, &CallParms::default()).await
+    }
+
+    pub async fn write_with(&mut self, data:&[u8], parms:&CallParms) -> Result<()> {
+        let mut conn = self.shared.lock().await?;
+        let (io_timeout, lock_timeout) = self.timeouts(parms.io_timeout, parms.lock_timeout);
+
+        let mut calls = WriteCalls::new(&self.link, data, io_timeout, lock_timeout);
+        loop {
+            calls.pack(&mut conn.client)?;
+            conn.do_call(io_timeout, lock_timeout).await?;
+            if calls.unpack(&mut conn.client)? { return Ok(()); }
+        }
+    }
+
+    pub async fn read(&mut self) -> Result<Vec<u8>> {
+        Ok(self.read_with(&ReadParms::default()).await?.0)
+    }
+
+    pub async fn read_with(&mut self, parms:&ReadParms) -> Result<(Vec<u8>, ReadReason)> {
+        let mut conn = self.shared.lock().await?;
+        let (io_timeout, lock_timeout) = self.timeouts(parms.io_timeout, parms.lock_timeout);
+
+        let mut calls = ReadCalls::new(&self.link, parms, io_timeout, lock_timeout);
+        loop {
+            calls.pack(&mut conn.client)?;
+            conn.do_call(io_timeout, lock_timeout).await?;
+            if let Some(ans) = calls.unpack(&mut conn.client)? { return Ok(ans); }
+        }
+    }
+
+    // The link is only marked destroyed once the call finishes, so dropping this part way through still gets it
+    // destroyed before the next call
+    pub async fn destroy(mut self) -> Result<()> {
+        let result:Result<()> = match self.shared.lock().await {
+            Ok(mut conn) => conn.destroy_link(self.link.link_id).await,
+            Err(e) => Err(e),
+        };
+        self.destroyed = true;
+        result
+    }
+
+    async fn generic_call(&self, prc:u32, flags:DeviceFlags, parms:&CallParms) -> Result<AsyncMutexGuard<'_, AsyncConnection>> {
+        let mut conn = self.shared.lock().await?;
+        let (io_timeout, lock_timeout) = self.timeouts(parms.io_timeout, parms.lock_timeout);
+        conn.client.start_call(prc)?;
+        xdr_pack::pack_device_generic_parms(&mut conn.client.packer, self.link.link_id, flags.to_i32(), lock_timeout, io_timeout)?;
+        conn.do_call(io_timeout, lock_timeout).await?;
+        Ok(conn)
+    }
+
+    pub async fn read_stb(&mut self, flags:DeviceFlags) -> Result<u8> {
+        self.read_stb_with(flags, &CallParms::default()).await
+    }
+
+    pub async fn read_stb_with(&mut self, flags:DeviceFlags, parms:&CallParms) -> Result<u8> {
+        let mut conn = self.generic_call(DEVICE_READSTB, flags, parms).await?;
+
+        let DeviceReadStbResp{ error, stb } = conn.client.unpacker.unpack()?;
+        device_result(error)?;
+        Ok(stb)
+    }
+
+    pub async fn trigger(&mut self, flags:DeviceFlags) -> Result<()> {
+        self.trigger_with(flags, &CallParms::default()).await
+    }
+
+    pub async fn trigger_with(&mut self, flags:DeviceFlags, parms:&CallParms) -> Result<()> {
+        self.generic_call(DEVICE_TRIGGER, flags, parms).await?.unpack_error()
+    }
+
+    pub async fn clear(&mut self, flags:DeviceFlags) -> Result<()> {
+        self.clear_with(flags, &CallParms::default()).await
+    }
+
+    pub async fn clear_with(&mut self, flags:DeviceFlags, parms:&CallParms) -> Result<()> {
+        self.generic_call(DEVICE_CLEAR, flags, parms).await?.unpack_error()
+    }
+
+    pub async fn remote(&mut self, flags:DeviceFlags) -> Result<()> {
+        self.remote_with(flags, &CallParms::default()).await
+    }
+
+    pub async fn remote_with(&mut self, flags:DeviceFlags, parms:&CallParms) -> Result<()> {
+        self.generic_call(DEVICE_REMOTE, flags, parms).await?.unpack_error()
+    }
+
+    pub async fn local(&mut self, flags:DeviceFlags) -> Result<()> {
+        self.local_with(flags, &CallParms::default()).await
+    }
+
+    pub async fn local_with(&mut self, flags:DeviceFlags, parms:&CallParms) -> Result<()> {
+        self.generic_call(DEVICE_LOCAL, flags, parms).await?.unpack_error()
+    }
+
+    pub async fn lock(&mut self, flags:DeviceFlags) -> Result<()> {
+        self.lock_with(flags, &CallParms::default()).await
+    }
+
+    // DEVICE_LOCK has no I/O timeout, so only the lock timeout in parms counts
+    pub async fn lock_with(&mut self, flags:DeviceFlags, parms:&CallParms) -> Result<()> {
+        let (_, lock_timeout) = self.timeouts(None, parms.lock_timeout);
+        let mut conn = self.shared.lock().await?;
+        conn.client.start_call(DEVICE_LOCK)?;
+        xdr_pack::pack_device_lock_parms(&mut conn.client.packer, self.link.link_id, flags.to_i32(), lock_timeout)?;
+        conn.do_call(0, lock_timeout).await?;
+        conn.unpack_error()
+    }
+
+    pub async fn unlock(&mut self) -> Result<()> {
+        let mut conn = self.shared.lock().await?;
+        conn.client.start_call(DEVICE_UNLOCK)?;
+        xdr_pack::pack_device_link(&mut conn.client.packer, self.link.link_id)?;
+        conn.do_call(0, 0).await?;
+        conn.unpack_error()
+    }
+
+    pub async fn docmd(&mut self, flags:DeviceFlags, cmd:i32, network_order:bool, datasize:i32, data_in:&[u8]) -> Result<Vec<u8>> {
+        self.docmd_with(flags, cmd, network_order, datasize, data_in, &CallParms::default()).await
+    }
+
+    pub async fn docmd_with(&mut self, flags:DeviceFlags, cmd:i32, network_order:bool, datasize:i32, data_in:&[u8], parms:&CallParms) -> Result<Vec<u8>> {
+        let (io_timeout, lock_timeout) = self.timeouts(parms.io_timeout, parms.lock_timeout);
+        let mut conn = self.shared.lock().await?;
+        conn.client.start_call(DEVICE_DOCMD)?;
+        xdr_pack::pack_device_docmd_parms(&mut conn.client.packer, self.link.link_id, flags.to_i32(), io_timeout, lock_timeout, cmd, network_order, datasize, data_in)?;
+        conn.do_call(io_timeout, lock_timeout).await?;
+
+        let DeviceDocmdResp{ error, data_out } = conn.client.unpacker.unpack()?;
+        device_result(error)?;
+        Ok(data_out)
+    }
+}
+
+impl Drop for AsyncDeviceLink {
+    fn drop(&mut self) {
+        if !self.destroyed {
+            lock_ignoring_poison(&self.shared.dropped_links).push(self.link.link_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicI32, AtomicU64, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    use tokio::time::timeout;
+
+    use crate::xdr::{Packer, Unpacker};
+    use crate::rpc::server::{Server, CallInfo, Outcome};
+    use super::*;
+    use super::super::types::*;
+
+    // Simulated instrument that hands out link ids from 1 and takes delay milliseconds over CREATE_LINK and
+    // DEVICE_WRITE
+    #[derive(Default)]
+    struct Instrument {
+        next_link: AtomicI32,
+        delay: AtomicU64,
+        destroyed: Mutex<Vec<i32>>,
+    }
+
+    impl Instrument {
+        fn pause(&self) {
+            thread::sleep(Duration::from_millis(self.delay.load(Ordering::SeqCst)));
+        }
+    }
+
+    async fn client(instrument:&Arc<Instrument>) -> AsyncCoreClient {
+        let mut server = Server::new();
+
+        let inst = instrument.clone();
+        server.register(DEVICE_CORE_PROG, DEVICE_CORE_VERS, CREATE_LINK, move |_:&CallInfo, args:&mut Unpacker, results:&mut Packer| {
+            let _:CreateLinkParms = args.unpack()?;
+            inst.pause();
+            let link_id:i32 = inst.next_link.fetch_add(1, Ordering::SeqCst) + 1;
+            results.pack(&CreateLinkResp{ error: 0, link: Link{ link_id, abort_port: 0, max_recv_size: 1024 } })?;
+            Ok(Outcome::Reply)
+        });
+        let inst = instrument.clone();
+        server.register(DEVICE_CORE_PROG, DEVICE_CORE_VERS, DESTROY_LINK, move |_:&CallInfo, args:&mut Unpacker, results:&mut Packer| {
+            inst.destroyed.lock().unwrap().push(args.unpack_i32()?);
+            results.pack(&DeviceError{ error: 0 })?;
+            Ok(Outcome::Reply)
+        });
+        let inst = instrument.clone();
+        server.register(DEVICE_CORE_PROG, DEVICE_CORE_VERS, DEVICE_WRITE, move |_:&CallInfo, args:&mut Unpacker, results:&mut Packer| {
+            let parms:DeviceWriteParms = args.unpack()?;
+            inst.pause();
+            results.pack(&DeviceWriteResp{ error: 0, size: parms.data.len() as u32 })?;
+            Ok(Outcome::Reply)
+        });
+        server.register(DEVICE_CORE_PROG, DEVICE_CORE_VERS, DEVICE_READ, |_:&CallInfo, args:&mut Unpacker, results:&mut Packer| {
+            let parms:DeviceReadParms = args.unpack()?;
+            results.pack(&DeviceReadResp{ error: 0, reason: REASON_END, data: parms.lid.to_string().into_bytes() })?;
+            Ok(Outcome::Reply)
+        });
+
+        let addr = Arc::new(server).spawn_tcp("127.0.0.1:0").unwrap();
+        AsyncCoreClient::with_transport(AsyncTcpTransport::connect(addr).await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn several_links_on_one_connection() {
+        let instrument = Arc::new(Instrument::default());
+        let client = client(&instrument).await;
+
+        let mut first = client.create_link("inst0", false, 0).await.unwrap();
+        let mut second = client.create_link("gpib0,5", false, 0).await.unwrap();
+        assert_eq!((first.link_id(), second.link_id()), (1, 2));
+        assert_eq!(first.ask(b"*IDN?").await.unwrap(), b"1");
+        assert_eq!(second.ask(b"*IDN?").await.unwrap(), b"2");
+
+        // One destroyed now and the other when it's dropped, before the next call
+        first.destroy().await.unwrap();
+        drop(second);
+        assert_eq!(*instrument.destroyed.lock().unwrap(), vec![1]);
+        client.create_link("inst0", false, 0).await.unwrap().destroy().await.unwrap();
+        assert_eq!(*instrument.destroyed.lock().unwrap(), vec![1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn cancelled_create_link_still_destroys_the_link() {
+        let instrument = Arc::new(Instrument::default());
+        let client = client(&instrument).await;
+
+        instrument.delay.store(200, Ordering::SeqCst);
+        assert!(timeout(Duration::from_millis(50), client.create_link("inst0", false, 0)).await.is_err());
+
+        // The next call waits for the reply, learns the link was made and destroys it first
+        instrument.delay.store(0, Ordering::SeqCst);
+        let mut link = client.create_link("inst0", false, 0).await.unwrap();
+        assert_eq!(link.link_id(), 2);
+        assert_eq!(*instrument.destroyed.lock().unwrap(), vec![1]);
+        assert_eq!(link.read().await.unwrap(), b"2");
+    }
+
+    #[tokio::test]
+    async fn cancelled_calls_leave_the_connection_usable() {
+        let instrument = Arc::new(Instrument::default());
+        let client = client(&instrument).await;
+        let mut link = client.create_link("inst0", false, 0).await.unwrap();
+
+        instrument.delay.store(200, Ordering::SeqCst);
+        assert!(timeout(Duration::from_millis(50), link.write(b"MEAS?")).await.is_err());
+        assert!(timeout(Duration::from_millis(50), link.destroy()).await.is_err());
+
+        // The link whose destroy was cancelled is destroyed again before the read, and the stale write reply skipped
+        instrument.delay.store(0, Ordering::SeqCst);
+        let mut other = client.create_link("inst0", false, 0).await.unwrap();
+        assert_eq!(other.read().await.unwrap(), b"2");
+        assert_eq!(*instrument.destroyed.lock().unwrap(), vec![1, 1]);
+    }
+}
@@ -0,0 +1,134 @@
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Sender, Receiver};
+
+use rand::Rng;
+
+use crate::xdr::{Packer, Unpacker};
+use crate::rpc::lock_ignoring_poison;
+use crate::rpc::server::{Server, Outcome, CallInfo};
+
+use super::{DEVICE_INTR_PROG, DEVICE_INTR_VERS, DEVICE_INTR_SRQ};
+use super::types::DeviceSrqParms;
+use super::xdr_pack::MAX_HANDLE_SIZE;
+
+lazy_static! {
+    static ref SHARED_SERVERS: Mutex<HashMap<Ipv4Addr, SrqServer>> = Mutex::new(HashMap::new());
+}
+
+type Subscribers = Arc<Mutex<HashMap<Vec<u8>, Sender<()>>>>;
+
+// Serves the Device Interrupt program, which instruments call back with device_intr_srq when they assert SRQ.  Each
+// link that enables SRQ gets its own handle, and every call with that handle wakes the matching receiver.  Clones
+// share the same listener.
+#[derive(Clone)]
+pub struct SrqServer {
+    addr: SocketAddr,
+    subscribers: Subscribers,
+}
+
+impl SrqServer {
+
+    // Listens over TCP on a background thread.  The address has to be reachable from the instruments.
+    pub fn spawn(addr:SocketAddr) -> io::Result<Self> {
+        let subscribers:Subscribers = Arc::new(Mutex::new(HashMap::new()));
+
+        let mut server = Server::new();
+        let subs = subscribers.clone();
+        server.register(DEVICE_INTR_PROG, DEVICE_INTR_VERS, DEVICE_INTR_SRQ, move |_call:&CallInfo, args:&mut Unpacker, _results:&mut Packer| {
+            let DeviceSrqParms{ handle } = args.unpack()?;
+
+            // A receiver that's been dropped means nobody cares about this handle anymore
+            let mut subs = lock_ignoring_poison(&subs);
+            if let Some(tx) = subs.get(&handle) {
+                if tx.send(()).is_err() { subs.remove(&handle); }
+            }
+            Ok(Outcome::Reply)
+        });
+
+        let addr:SocketAddr = Arc::new(server).spawn_tcp(addr)?;
+        Ok(Self{ addr, subscribers })
+    }
+
+    // One server for each local address, shared by all the clients in the process and started the first time it's
+    // needed.  Use the address the instruments are reached from, so the server isn't open on every other interface.
+    pub fn shared(local_ip:Ipv4Addr) -> io::Result<Self> {
+        let mut shared = lock_ignoring_poison(&SHARED_SERVERS);
+        if let Some(server) = shared.get(&local_ip) {
+            return Ok(server.clone());
+        }
+        let server:SrqServer = Self::spawn((local_ip, 0).into())?;
+        shared.insert(local_ip, server.clone());
+        Ok(server)
+    }
+
+    pub fn addr(&self) -> SocketAddr { self.addr }
+
+    pub fn port(&self) -> u16 { self.addr.port() }
+
+    // Random and as long as a handle can be, so nobody else on the network can guess one and fake service requests
+    pub fn new_handle() -> Vec<u8> {
+        let mut handle:Vec<u8> = vec![0; MAX_HANDLE_SIZE as usize];
+        rand::thread_rng().fill_bytes(&mut handle);
+        handle
+    }
+
+    pub fn subscribe(&self, handle:&[u8]) -> Receiver<()> {
+        let (tx, rx) = mpsc::channel();
+        lock_ignoring_poison(&self.subscribers).insert(handle.to_vec(), tx);
+        rx
+    }
+
+    pub fn unsubscribe(&self, handle:&[u8]) {
+        lock_ignoring_poison(&self.subscribers).remove(handle);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::rpc::tcp_clients::TcpClient;
+    use super::*;
+
+    #[test]
+    fn service_requests_get_through_after_a_panic_with_the_lock_held() {
+        let server = SrqServer::spawn(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+        let handle:Vec<u8> = SrqServer::new_handle();
+        let rx:Receiver<()> = server.subscribe(&handle);
+
+        let subscribers:Subscribers = server.subscribers.clone();
+        let _ = thread::spawn(move || {
+            let _guard = subscribers.lock().unwrap();
+            panic!("poisoning the subscribers");
+        }).join();
+        assert!(server.subscribers.is_poisoned());
+
+        let mut client = TcpClient::connect(("127.0.0.1", server.port()), DEVICE_INTR_PROG, DEVICE_INTR_VERS).unwrap();
+        client.start_call(DEVICE_INTR_SRQ).unwrap();
+        client.packer.pack(&DeviceSrqParms{ handle: handle.clone() }).unwrap();
+        client.do_call().unwrap();
+        rx.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        server.unsubscribe(&handle);
+    }
+
+    #[test]
+    fn handles_are_random_and_full_length() {
+        let a:Vec<u8> = SrqServer::new_handle();
+        let b:Vec<u8> = SrqServer::new_handle();
+        assert_eq!(a.len(), MAX_HANDLE_SIZE as usize);
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn shared_servers_listen_on_the_given_address() {
+        let server = SrqServer::shared(Ipv4Addr::LOCALHOST).unwrap();
+        assert_eq!(server.addr().ip(), Ipv4Addr::LOCALHOST);
+        assert_eq!(SrqServer::shared(Ipv4Addr::LOCALHOST).unwrap().port(), server.port());
+    }
+}
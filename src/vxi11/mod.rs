@@ -18,185 +18,1115 @@ pub const DESTROY_LINK:u32      = 23;
 pub const CREATE_INTR_CHAN:u32  = 25;
 pub const DESTROY_INTR_CHAN:u32 = 26;
 
+// Device async
+pub const DEVICE_ASYNC_PROG:u32 = 0x0607b0;
+pub const DEVICE_ASYNC_VERS:u32 = 1;
+pub const DEVICE_ABORT:u32      = 1;
+
+// Device interrupt
+pub const DEVICE_INTR_PROG:u32  = 0x0607b1;
+pub const DEVICE_INTR_VERS:u32  = 1;
+pub const DEVICE_INTR_SRQ:u32   = 30;
+
 pub const CLIENT_ID:i32 = 3333;
 pub const DEFAULT_LOCK_TIMEOUT:u32 = 10000;
+pub const DEFAULT_IO_TIMEOUT:u32   = 10000;
+
+// Defaults for our end of the connection
+pub const DEFAULT_CONNECT_TIMEOUT:Duration = Duration::from_secs(10);
+pub const DEFAULT_READ_TIMEOUT:Duration    = Duration::from_secs(5);
+pub const DEFAULT_WRITE_TIMEOUT:Duration   = Duration::from_secs(10);
+
+// Time a reply gets on top of the timeouts sent with the call, for the network and whatever else slows the instrument
+const REPLY_GRACE:Duration = Duration::from_secs(5);
 
 pub const OPERATION_FLAGS_END_ONLY:i32 = 8;
 
-use std::io::{self, Error, ErrorKind};
+// Device_Flags bits
+pub const FLAG_WAITLOCK:i32   = 1;
+pub const FLAG_END:i32        = 8;
+pub const FLAG_TERMCHRSET:i32 = 128;
+
+// Reason bits in Device_ReadResp
+pub const REASON_REQCNT:i32 = 1;
+pub const REASON_CHR:i32    = 2;
+pub const REASON_END:i32    = 4;
+
+// Largest response read puts together by default, the same as the largest RPC record we accept
+pub const DEFAULT_MAX_READ_SIZE:usize = 1 << 28;
+
+// Device_AddrFamily values for CREATE_INTR_CHAN
+pub const DEVICE_TCP:i32 = 0;
+pub const DEVICE_UDP:i32 = 1;
+
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::mpsc::Receiver;
+use std::time::Duration;
+
+use serde::{Serialize, Deserialize};
 
-use crate::rpc::port_mapping::{TcpPortMapperClient, Mapping, Protocol};
-use crate::rpc::xdr_pack::{pack_callheader_no_auth};
-use crate::rpc::tcp_clients::TcpClient;
+use crate::rpc::port_mapping::{self, PortMapperClient, Mapping, Protocol, PMAP_PORT};
+use crate::rpc::rpcbind::{self, RpcbindClient, RPCBVERS};
+use crate::rpc::client::RpcClient;
+use crate::rpc::transport::RpcTransport;
+use crate::rpc::tcp_clients::TcpTransport;
+use crate::rpc::auth::Auth;
+use crate::error::{Error, Result, DeviceErrorCode};
 
-fn err(msg:&str) -> io::Error { Error::new(ErrorKind::Other, msg) }
+fn err(msg:&str) -> Error { Error::Protocol(msg.to_owned()) }
 
+pub mod types;
 pub mod xdr_pack;
 
-// TODO: implement abort and interrupt clients
+pub mod abort;
+pub use self::abort::AbortHandle;
+
+pub mod interrupt;
+pub use self::interrupt::SrqServer;
+
+#[cfg(feature = "async")]
+pub mod async_client;
+#[cfg(feature = "async")]
+pub use self::async_client::{AsyncCoreClient, AsyncDeviceLink};
+
+use self::types::{CreateLinkResp, DeviceWriteResp, DeviceReadResp, DeviceReadStbResp, DeviceDocmdResp, DeviceError};
+
+// Finds instruments by broadcasting to the portmappers on a network.  Returns the address of each instrument that
+// has the device core program registered along with the TCP port it's on.  The local address picks the interface
+// the broadcast goes out on, as for port_mapping::broadcast_get_port.
+pub fn discover(broadcast_addr:IpAddr, local_addr:Option<IpAddr>, window:Duration) -> Result<Vec<(SocketAddr, u32)>> {
+    let found = port_mapping::broadcast_get_port(broadcast_addr, local_addr, window, &core_mapping())?;
+    Ok(found.into_iter().filter(|(_, port)| *port != 0).collect())
+}
+
+// What to ask a portmapper for to find the core program
+fn core_mapping() -> Mapping {
+    Mapping {
+        program: DEVICE_CORE_PROG,
+        version: DEVICE_CORE_VERS,
+        protocol: Protocol::TCP,
+        port: 0,
+    }
+}
+
+// Port of the core program from rpcbind's answer, for instruments that only registered it with a netid and universal
+// address
+fn core_port(uaddr:Option<String>) -> Result<u32> {
+    let uaddr = uaddr.ok_or_else(|| err("Device core program isn't registered with the portmapper or rpcbind"))?;
+    Ok(rpcbind::parse_uaddr(&uaddr)?.port() as u32)
+}
+
+// Connection state shared by a client and every link opened through it
+struct Connection<T> {
+    client: RpcClient<T>,
+    client_id: i32,
+    host: Option<String>,           // Needed to reach the abort channel, None when the transport isn't TCP to a host
+    intr_chan: Option<SrqServer>,   // Server the instrument calls back with service requests, once the channel is open
+    srq_handles: HashSet<Vec<u8>>,  // Handles of the links with service requests enabled through intr_chan
+    options: LinkOptions,
+}
+
+type SharedConnection<T> = Arc<Mutex<Connection<T>>>;
+
+fn lock<T>(conn:&SharedConnection<T>) -> Result<MutexGuard<'_, Connection<T>>> {
+    conn.lock().map_err(|_| err("Connection poisoned by a panic on another thread"))
+}
+
+// Connection to the device core program.  Clones share the connection, and any number of links can be open on it
+// at once, for example to several instruments behind a LAN/GPIB gateway.
+pub struct CoreClient<T: RpcTransport = TcpTransport> {
+    conn: SharedConnection<T>,
+}
+
+impl<T: RpcTransport> Clone for CoreClient<T> {
+    fn clone(&self) -> Self { Self{ conn: self.conn.clone() } }
+}
+
+// One link to a device, destroyed when dropped.  The connection stays open as long as a link or client uses it.
+pub struct DeviceLink<T: RpcTransport = TcpTransport> {
+    conn: SharedConnection<T>,
+    link: Link,
+    device: String,
+    host: Option<String>,       // Copied from the connection so the abort channel can be reached without locking it
+    options: LinkOptions,       // The client's when the link was created, for the abort channel
+    srq_handle: Option<Vec<u8>>,
+    io_timeout: u32,
+    lock_timeout: u32,
+    destroyed: bool,
+}
+
+// Timeouts for a CoreClient and the links opened through it.  The I/O and lock timeouts are in milliseconds and are
+// sent to the instrument with each call.  The others are for our end of the connection, where None waits forever
+// and so does a zero Duration, which the socket calls won't take.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LinkOptions {
+    pub io_timeout: u32,                     // How long the instrument may take over a read or write
+    pub lock_timeout: u32,                   // How long the instrument waits for a lock held by another link
+    pub connect_timeout: Option<Duration>,   // For each TCP connection, the portmapper's as well as the core program's
+    pub read_timeout: Option<Duration>,      // Shortest wait for a reply, raised to cover the call's own timeouts
+    pub write_timeout: Option<Duration>,     // For sending a call
+}
+
+impl Default for LinkOptions {
+    fn default() -> Self {
+        Self {
+            io_timeout: DEFAULT_IO_TIMEOUT,
+            lock_timeout: DEFAULT_LOCK_TIMEOUT,
+            connect_timeout: Some(DEFAULT_CONNECT_TIMEOUT),
+            read_timeout: Some(DEFAULT_READ_TIMEOUT),
+            write_timeout: Some(DEFAULT_WRITE_TIMEOUT),
+        }
+    }
+}
 
-pub struct CoreClient {
-    client: TcpClient,
-    opt_link: Option<Link>,      // TODO: consider trying to support multiple links at once, in which case this will become a Vec<u32>
+// Flags passed with most Device Core calls
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DeviceFlags {
+    pub waitlock: bool,       // Wait up to the lock timeout for a lock held by another link instead of failing right away
+    pub end: bool,            // Assert END with the last byte of a write
+    pub termchrset: bool,     // Stop reads at the termination character
 }
 
+impl DeviceFlags {
+    pub fn to_i32(&self) -> i32 {
+        let mut ans:i32 = 0;
+        if self.waitlock   { ans |= FLAG_WAITLOCK; }
+        if self.end        { ans |= FLAG_END; }
+        if self.termchrset { ans |= FLAG_TERMCHRSET; }
+        ans
+    }
+}
+
+// Why a read stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadReason {
+    RequestCount,    // Reached the maximum size
+    TermChar,        // Got the termination character
+    End,             // The device asserted END
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ReadParms {
+    pub request_size: u32,           // Most bytes asked for in each DEVICE_READ call
+    pub term_char: Option<u8>,       // Stop after this byte, which sets the termchrset flag
+    pub max_size: usize,             // Most bytes in total before giving up on END
+    pub io_timeout: Option<u32>,     // In place of the link's, for example for a read that waits on an acquisition
+    pub lock_timeout: Option<u32>,
+}
+
+impl Default for ReadParms {
+    fn default() -> Self {
+        Self{ request_size: u32::MAX, term_char: None, max_size: DEFAULT_MAX_READ_SIZE, io_timeout: None, lock_timeout: None }
+    }
+}
+
+// Timeouts in milliseconds for one write, lock, trigger and so on, where None uses the link's timeout
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CallParms {
+    pub io_timeout: Option<u32>,
+    pub lock_timeout: Option<u32>,
+}
+
+impl ReadParms {
+    // termChar is a char in vxi11.x, so bytes past 0x7f go out sign-extended as a C client's would
+    fn flags_and_term_char(&self) -> (i32, i8) {
+        match self.term_char {
+            Some(c) => (FLAG_TERMCHRSET, c as i8),
+            None    => (0, 0),
+        }
+    }
+
+    // Size to ask for in the next call given how much has been read so far
+    fn chunk_size(&self, have:usize) -> u32 {
+        let remaining:usize = self.max_size.saturating_sub(have);
+        (self.request_size.max(1) as usize).min(remaining) as u32
+    }
+
+    // Adds a chunk to what's been read so far, returning the reason if that finishes the read
+    fn add_chunk(&self, ans:&mut Vec<u8>, reason:ReadReason, data:&[u8]) -> Result<Option<ReadReason>> {
+        if data.len() > self.chunk_size(ans.len()) as usize {
+            return Err(err("Device sent more bytes than requested"));
+        }
+        // Asking again would only get the same nothing back, forever
+        if reason == ReadReason::RequestCount && data.is_empty() {
+            return Err(err("Device stopped on the request count without sending any bytes"));
+        }
+        ans.extend_from_slice(data);
+
+        Ok(match reason {
+            ReadReason::End | ReadReason::TermChar => Some(reason),
+            ReadReason::RequestCount if ans.len() >= self.max_size => Some(reason),
+            ReadReason::RequestCount => None,
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Link {
     pub link_id: i32,
-    pub abort_port: u32,
+    pub abort_port: u16,
     pub max_recv_size: u32,
 }
 
-impl CoreClient {
-    
-    fn get_link(&self) -> io::Result<i32> {
-        match self.opt_link {
-            Some(Link{ link_id, .. }) => Ok(link_id),
-            None => Err(err("No link")),
+impl<T: RpcTransport> Connection<T> {
+
+    fn new(client:RpcClient<T>, host:Option<String>, options:LinkOptions) -> SharedConnection<T> {
+        Arc::new(Mutex::new(Connection { client, client_id: CLIENT_ID, host, intr_chan: None, srq_handles: HashSet::new(), options }))
+    }
+
+    // Makes the call that's been packed, waiting for the reply as long as reply_timeout allows
+    fn do_call(&mut self, io_timeout:u32, lock_timeout:u32) -> Result<()> {
+        self.client.transport.set_timeout(reply_timeout(&self.options, io_timeout, lock_timeout))?;
+        self.client.do_call()?;
+        Ok(())
+    }
+
+    fn unpack_error(&mut self) -> Result<()> {
+        let DeviceError{ error } = self.client.unpacker.unpack()?;
+        device_result(error)
+    }
+
+    fn create_link(&mut self, device:&str, lock_device:bool, lock_timeout:u32) -> Result<Link> {
+        if !device.is_ascii() {
+            return Err(err("Device names have to be ASCII"));
         }
+
+        self.client.start_call(CREATE_LINK)?;
+        xdr_pack::pack_create_link_parms(&mut self.client.packer, self.client_id, lock_device, lock_timeout, device)?;
+        self.do_call(0, lock_timeout)?;
+
+        let CreateLinkResp{ error, link } = self.client.unpacker.unpack()?;
+        device_result(error)?;
+        Ok(link)
     }
 
-    pub fn new(host:&str) -> io::Result<Self> {
+    fn destroy_link(&mut self, link_id:i32) -> Result<()> {
+        self.client.start_call(DESTROY_LINK)?;
+        self.client.packer.pack_i32(link_id)?;
+        self.do_call(0, 0)?;
 
-        // Find the port to use for the core program
-        let mut pmap_client = TcpPortMapperClient::new(host)?;
+        let DeviceError{ error } = self.client.unpacker.unpack()?;
+        device_result(error)
+    }
 
-        let mapping = Mapping {
-            program: DEVICE_CORE_PROG,
-            version: DEVICE_CORE_VERS,
-            protocol: Protocol::TCP,
-            port: 0,
+    fn create_intr_chan(&mut self, host_addr:Ipv4Addr, host_port:u16, prog_num:u32, prog_vers:u32, family:Protocol) -> Result<()> {
+        let prog_family:i32 = match family {
+            Protocol::TCP => DEVICE_TCP,
+            Protocol::UDP => DEVICE_UDP,
         };
-        
-        let port = pmap_client.get_port(&mapping)?;
 
-        // Connect on the port specified and create a packer and unpacker
-        let client   = TcpClient::connect((host, port as u16), DEVICE_CORE_PROG, DEVICE_CORE_VERS)?;
+        self.client.start_call(CREATE_INTR_CHAN)?;
+        xdr_pack::pack_device_remote_func_parms(&mut self.client.packer, u32::from(host_addr), host_port, prog_num, prog_vers, prog_family)?;
+        self.do_call(0, 0)?;
+        self.unpack_error()
+    }
+
+    fn destroy_intr_chan(&mut self) -> Result<()> {
+        self.client.start_call(DESTROY_INTR_CHAN)?;
+        self.do_call(0, 0)?;
+        self.unpack_error()
+    }
+
+    fn open_intr_chan(&mut self, server:&SrqServer, host_addr:Ipv4Addr) -> Result<()> {
+        self.create_intr_chan(host_addr, server.port(), DEVICE_INTR_PROG, DEVICE_INTR_VERS, Protocol::TCP)?;
+        self.intr_chan = Some(server.clone());
+        Ok(())
+    }
+}
+
+impl CoreClient<TcpTransport> {
+
+    pub fn new(host:&str) -> Result<Self> {
+        Self::with_options(host, PMAP_PORT, LinkOptions::default())
+    }
 
-        // Build and return the struct
-        Ok(CoreClient {client, opt_link: None })
+    // For instruments (or stand-ins for them) whose portmapper isn't on the standard port
+    pub fn with_pmap_port(host:&str, pmap_port:u16) -> Result<Self> {
+        Self::with_options(host, pmap_port, LinkOptions::default())
     }
 
-    pub fn create_link(&mut self) -> io::Result<()> {
-        if self.opt_link.is_some() {
-            return Err(err("Already connected to a link"));
+    pub fn with_options(host:&str, pmap_port:u16, options:LinkOptions) -> Result<Self> {
+
+        // Find the port to use for the core program
+        let mut pmap_client = PortMapperClient::with_transport(host, connect_tcp(host, pmap_port, &options)?);
+        let mut port = pmap_client.get_port(&core_mapping())?;
+
+        // Instruments running rpcbind may only have registered the program with a netid and universal address
+        if port == 0 {
+            let mut rpcb_client = RpcbindClient::with_transport(host, connect_tcp(host, pmap_port, &options)?, RPCBVERS);
+            port = core_port(rpcb_client.get_addr(DEVICE_CORE_PROG, DEVICE_CORE_VERS, "tcp")?)?;
         }
 
-        self.client.lastxid += 1;
-        self.client.packer.reset();
-        
-        pack_callheader_no_auth(&mut self.client.packer, self.client.lastxid, DEVICE_CORE_PROG, DEVICE_CORE_VERS, CREATE_LINK)?;
-        xdr_pack::pack_create_link_parms(&mut self.client.packer, CLIENT_ID, false, DEFAULT_LOCK_TIMEOUT, "inst0")?;
-        
-        self.client.do_call()?;
+        Self::with_core_port(host, port as u16, options)
+    }
+
+    // For a core program whose port is already known, for example from discover, without asking the portmapper again
+    pub fn with_core_port(host:&str, port:u16, options:LinkOptions) -> Result<Self> {
+        let client = RpcClient::new(connect_tcp(host, port, &options)?, DEVICE_CORE_PROG, DEVICE_CORE_VERS);
+        Ok(CoreClient { conn: Connection::new(client, Some(host.to_owned()), options) })
+    }
+}
+
+fn connect_tcp(host:&str, port:u16, options:&LinkOptions) -> Result<TcpTransport> {
+    let mut transport = match nonzero(options.connect_timeout) {
+        Some(timeout) => TcpTransport::connect_timeout((host, port), timeout)?,
+        None          => TcpTransport::connect((host, port))?,
+    };
+    transport.stream.set_write_timeout(nonzero(options.write_timeout))?;
+    transport.set_timeout(nonzero(options.read_timeout))?;
+    Ok(transport)
+}
+
+// A zero timeout in LinkOptions means no timeout, as it does for SO_RCVTIMEO
+fn nonzero(timeout:Option<Duration>) -> Option<Duration> {
+    timeout.filter(|t| !t.is_zero())
+}
+
+impl<T: RpcTransport> CoreClient<T> {
+
+    // For talking to the core program over something other than a plain TCP connection, for example a
+    // LoopbackTransport into a simulated instrument
+    pub fn with_transport(transport:T) -> Self {
+        let client = RpcClient::new(transport, DEVICE_CORE_PROG, DEVICE_CORE_VERS);
+        CoreClient { conn: Connection::new(client, None, LinkOptions::default()) }
+    }
+
+    pub fn options(&self) -> Result<LinkOptions> {
+        Ok(lock(&self.conn)?.options)
+    }
+
+    // The I/O and lock timeouts apply to links created afterwards and the read timeout to the next call.  The connect
+    // and write timeouts only matter when connecting.
+    pub fn set_options(&self, options:LinkOptions) -> Result<()> {
+        lock(&self.conn)?.options = options;
+        Ok(())
+    }
+
+    // For instruments behind RPC gateways that require credentials, for example AUTH_SYS
+    pub fn set_auth<A: Auth + 'static>(&self, auth:A) -> Result<()> {
+        lock(&self.conn)?.client.set_auth(auth);
+        Ok(())
+    }
+
+    // The client ID sent with each new link, which some instruments show to say who's connected
+    pub fn set_client_id(&self, client_id:i32) -> Result<()> {
+        lock(&self.conn)?.client_id = client_id;
+        Ok(())
+    }
+
+    // Opens a link to a device, for example "inst0" for the instrument itself or "gpib0,5" for address 5 on a
+    // gateway's GPIB bus.  With lock_device set the link also takes the device's lock, waiting up to lock_timeout
+    // milliseconds for it.
+    pub fn create_link(&self, device:&str, lock_device:bool, lock_timeout:u32) -> Result<DeviceLink<T>> {
+        let mut conn = lock(&self.conn)?;
+        let link:Link = conn.create_link(device, lock_device, lock_timeout)?;
+        let options:LinkOptions = conn.options;
+        Ok(DeviceLink{ conn: self.conn.clone(), link, device: device.to_owned(), host: conn.host.clone(), options, srq_handle: None,
+            io_timeout: options.io_timeout, lock_timeout: options.lock_timeout, destroyed: false })
+    }
 
-        let error:i32         = self.client.unpacker.unpack_i32()?;
-        let link_id:i32       = self.client.unpacker.unpack_i32()?;
-        let abort_port:u32    = self.client.unpacker.unpack_u32()?;
-        let max_recv_size:u32 = self.client.unpacker.unpack_u32()?;
+    // Asks the device to open an interrupt channel back to a Device Interrupt server at host_addr:host_port.  This
+    // applies to the whole connection rather than a link.
+    pub fn create_intr_chan(&self, host_addr:Ipv4Addr, host_port:u16, prog_num:u32, prog_vers:u32, family:Protocol) -> Result<()> {
+        lock(&self.conn)?.create_intr_chan(host_addr, host_port, prog_num, prog_vers, family)
+    }
+
+    pub fn destroy_intr_chan(&self) -> Result<()> {
+        lock(&self.conn)?.destroy_intr_chan()
+    }
+
+    // Has the instrument open an interrupt channel to an SrqServer, which it reaches at host_addr
+    pub fn open_intr_chan(&self, server:&SrqServer, host_addr:Ipv4Addr) -> Result<()> {
+        lock(&self.conn)?.open_intr_chan(server, host_addr)
+    }
+
+    // Links with service requests enabled have to unsubscribe first, or the instrument would go on asserting SRQ with
+    // nowhere to send it
+    pub fn close_intr_chan(&self) -> Result<()> {
+        let mut conn = lock(&self.conn)?;
+        if !conn.srq_handles.is_empty() {
+            return Err(err("Links still have service requests enabled, unsubscribe them first"));
+        }
+        conn.intr_chan = None;
+        conn.destroy_intr_chan()
+    }
+}
 
-        self.opt_link = Some(Link{ link_id, abort_port, max_recv_size });
+impl DeviceLink<TcpTransport> {
 
-        match error {
-            0  => Ok(()),
-            1  => Err(err("Syntax error")),
-            3  => Err(err("Device not accessible")),
-            9  => Err(err("Out of resources")),
-            11 => Err(err("Device locked by another link")),
-            21 => Err(err("Invalid address")),
-            _  => Err(err("Unknown error"))
+    // Opens the interrupt channel to the process-wide SrqServer if it isn't open yet, then enables service requests
+    // on the link.  The receiver gets a message each time the instrument asserts SRQ.
+    pub fn listen_srq(&mut self) -> Result<Receiver<()>> {
+        {
+            let mut conn = lock(&self.conn)?;
+            if conn.intr_chan.is_none() {
+                // The instrument calls back on whichever of our addresses it's talking to now
+                let local_ip:Ipv4Addr = match conn.client.transport.stream.local_addr()?.ip() {
+                    IpAddr::V4(ip) => ip,
+                    IpAddr::V6(_)  => return Err(err("Interrupt channels only work over IPv4")),
+                };
+                conn.open_intr_chan(&SrqServer::shared(local_ip)?, local_ip)?;
+            }
         }
+        self.subscribe_srq()
+    }
+}
+
+impl<T: RpcTransport> DeviceLink<T> {
+
+    pub fn link_id(&self) -> i32 { self.link.link_id }
+
+    pub fn link(&self) -> &Link { &self.link }
+
+    // The name the link was created with
+    pub fn device(&self) -> &str { &self.device }
+
+    // Timeouts in milliseconds for reads and writes that don't override them, starting from the client's options
+    pub fn io_timeout(&self) -> u32 { self.io_timeout }
+
+    pub fn lock_timeout(&self) -> u32 { self.lock_timeout }
+
+    pub fn set_io_timeout(&mut self, io_timeout:u32) { self.io_timeout = io_timeout; }
+
+    pub fn set_lock_timeout(&mut self, lock_timeout:u32) { self.lock_timeout = lock_timeout; }
+
+    // The I/O and lock timeouts for one call, from the overrides or the link's own
+    fn timeouts(&self, io_timeout:Option<u32>, lock_timeout:Option<u32>) -> (u32, u32) {
+        (io_timeout.unwrap_or(self.io_timeout), lock_timeout.unwrap_or(self.lock_timeout))
+    }
+
+    // Connects to the abort channel for this link.  The handle can be cloned and used from another thread to cancel
+    // a read or write that's taking too long.  This doesn't need the connection, so it can be called while another
+    // thread has a call in progress on it.
+    pub fn abort_handle(&self) -> Result<AbortHandle> {
+        let host:&str = self.host.as_deref().ok_or_else(|| err("Abort channel needs a client connected to a host"))?;
+        let options = LinkOptions{ io_timeout: self.io_timeout, ..self.options };
+        AbortHandle::connect(host, self.link.abort_port, self.link.link_id, &options)
     }
 
     // TODO: consider moving this to the device level
-    pub fn ask(&mut self, data:&[u8]) -> io::Result<Vec<u8>> {
+    pub fn ask(&mut self, data:&[u8]) -> Result<Vec<u8>> {
         self.write(data)?;
         self.read()
     }
 
-    pub fn write(&mut self, data:&[u8]) -> io::Result<()> {
-        self.client.lastxid += 1;
-        self.client.packer.reset();
+    // Sends the data in as many DEVICE_WRITE calls as the link's max_recv_size calls for, with END on the last one
+    pub fn write(&mut self, data:&[u8]) -> Result<()> {
+        self.write_with(data, &CallParms::default())
+    }
 
-        let link_id:i32 = self.get_link()?;
-        pack_callheader_no_auth(&mut self.client.packer, self.client.lastxid, DEVICE_CORE_PROG, DEVICE_CORE_VERS, DEVICE_WRITE)?;
-        xdr_pack::pack_device_write_parms(&mut self.client.packer, link_id, DEFAULT_LOCK_TIMEOUT, DEFAULT_LOCK_TIMEOUT, OPERATION_FLAGS_END_ONLY, data)?;
-        
-        self.client.do_call()?;
-    
-        let error:i32 = self.client.unpacker.unpack_i32()?;
-        let size:u32  = self.client.unpacker.unpack_u32()?;
+    pub fn write_with(&mut self, data:&[u8], parms:&CallParms) -> Result<()> {
+        let mut conn = lock(&self.conn)?;
+        let (io_timeout, lock_timeout) = self.timeouts(parms.io_timeout, parms.lock_timeout);
 
-        if size as usize != data.len() {
-            return Err(Error::new(ErrorKind::Other, "Number of bytes in confirmation doesn't match number of bytes sent"));
+        let mut calls = WriteCalls::new(&self.link, data, io_timeout, lock_timeout);
+        loop {
+            calls.pack(&mut conn.client)?;
+            conn.do_call(io_timeout, lock_timeout)?;
+            if calls.unpack(&mut conn.client)? { return Ok(()); }
         }
+    }
+
+    // Reads until the device asserts END
+    pub fn read(&mut self) -> Result<Vec<u8>> {
+        Ok(self.read_with(&ReadParms::default())?.0)
+    }
 
-        match error {
-            0  => Ok(()),
-            4  => Err(err("Invalid link identifier")),
-            5  => Err(err("Parameter error")),
-            11 => Err(err("Device locked by another link")),
-            15 => Err(err("I/O timeout")),
-            17 => Err(err("I/O error")),
-            23 => Err(err("Abort")),
-            _  => Err(err("Unknown error")),
+    // Reads as many chunks as it takes to get END, the termination character or the maximum size, whichever comes
+    // first, and returns everything read along with which one it was
+    pub fn read_with(&mut self, parms:&ReadParms) -> Result<(Vec<u8>, ReadReason)> {
+        let mut conn = lock(&self.conn)?;
+        let (io_timeout, lock_timeout) = self.timeouts(parms.io_timeout, parms.lock_timeout);
+
+        let mut calls = ReadCalls::new(&self.link, parms, io_timeout, lock_timeout);
+        loop {
+            calls.pack(&mut conn.client)?;
+            conn.do_call(io_timeout, lock_timeout)?;
+            if let Some(ans) = calls.unpack(&mut conn.client)? { return Ok(ans); }
         }
+    }
 
+    // Destroys the link now rather than when it's dropped, so any error can be seen
+    pub fn destroy(mut self) -> Result<()> {
+        self.destroy_inner()
     }
 
-    pub fn read(&mut self) -> io::Result<Vec<u8>> {
-        self.client.lastxid += 1;
-        self.client.packer.reset();
-        
-        let link_id:i32 = self.get_link()?;
-        pack_callheader_no_auth(&mut self.client.packer, self.client.lastxid, DEVICE_CORE_PROG, DEVICE_CORE_VERS, DEVICE_READ)?;
-        xdr_pack::pack_device_read_parms(&mut self.client.packer, link_id, std::u32::MAX, DEFAULT_LOCK_TIMEOUT, DEFAULT_LOCK_TIMEOUT, 0, 0)?;
-        self.client.do_call()?;
+    fn destroy_inner(&mut self) -> Result<()> {
+        self.destroyed = true;
+
+        // The link goes either way, so a failure to turn service requests off shouldn't stop it being destroyed
+        let srq_result:Result<()> = if self.srq_handle.is_some() { self.unsubscribe_srq() } else { Ok(()) };
+        let destroy_result:Result<()> = lock(&self.conn).and_then(|mut conn| conn.destroy_link(self.link.link_id));
+        srq_result.and(destroy_result)
+    }
+
+    // Sends Device_GenericParms, which READSTB, TRIGGER, CLEAR, REMOTE and LOCAL all take, and leaves the connection
+    // locked with the reply in the unpacker
+    fn generic_call(&self, prc:u32, flags:DeviceFlags, parms:&CallParms) -> Result<MutexGuard<'_, Connection<T>>> {
+        let mut conn = lock(&self.conn)?;
+        let (io_timeout, lock_timeout) = self.timeouts(parms.io_timeout, parms.lock_timeout);
+        conn.client.start_call(prc)?;
+        xdr_pack::pack_device_generic_parms(&mut conn.client.packer, self.link.link_id, flags.to_i32(), lock_timeout, io_timeout)?;
+        conn.do_call(io_timeout, lock_timeout)?;
+        Ok(conn)
+    }
+
+    // Reads the status byte.  This and the calls below use the link's timeouts, or the ones in parms for the _with
+    // versions.
+    pub fn read_stb(&mut self, flags:DeviceFlags) -> Result<u8> {
+        self.read_stb_with(flags, &CallParms::default())
+    }
+
+    pub fn read_stb_with(&mut self, flags:DeviceFlags, parms:&CallParms) -> Result<u8> {
+        let mut conn = self.generic_call(DEVICE_READSTB, flags, parms)?;
+
+        let DeviceReadStbResp{ error, stb } = conn.client.unpacker.unpack()?;
+        device_result(error)?;
+        Ok(stb)
+    }
+
+    // Sends a group execute trigger to the device
+    pub fn trigger(&mut self, flags:DeviceFlags) -> Result<()> {
+        self.trigger_with(flags, &CallParms::default())
+    }
+
+    pub fn trigger_with(&mut self, flags:DeviceFlags, parms:&CallParms) -> Result<()> {
+        self.generic_call(DEVICE_TRIGGER, flags, parms)?.unpack_error()
+    }
+
+    pub fn clear(&mut self, flags:DeviceFlags) -> Result<()> {
+        self.clear_with(flags, &CallParms::default())
+    }
+
+    pub fn clear_with(&mut self, flags:DeviceFlags, parms:&CallParms) -> Result<()> {
+        self.generic_call(DEVICE_CLEAR, flags, parms)?.unpack_error()
+    }
+
+    // Locks out the front panel
+    pub fn remote(&mut self, flags:DeviceFlags) -> Result<()> {
+        self.remote_with(flags, &CallParms::default())
+    }
+
+    pub fn remote_with(&mut self, flags:DeviceFlags, parms:&CallParms) -> Result<()> {
+        self.generic_call(DEVICE_REMOTE, flags, parms)?.unpack_error()
+    }
+
+    // Gives control back to the front panel
+    pub fn local(&mut self, flags:DeviceFlags) -> Result<()> {
+        self.local_with(flags, &CallParms::default())
+    }
 
-        let error:i32    = self.client.unpacker.unpack_i32()?;
-        let reason:i32   = self.client.unpacker.unpack_i32()?;
-        let data:Vec<u8> = self.client.unpacker.unpack_variable_len_opaque()?;
-        
-        match error {
-            0  => {
-                match reason {
-                    0 => Err(err("Expected one of three reason bits to be set")),
-                    1 => Err(err("End of read due to requested byte count reached")),
-                    2 => Err(err("End of read due to termination character")),
-                    3 => Err(err("Unexpected combination of reason bits")),
-                    4 => Ok(data),
-                    _ => Err(err("Bit in reason code that should be zero aren't zero")),
+    pub fn local_with(&mut self, flags:DeviceFlags, parms:&CallParms) -> Result<()> {
+        self.generic_call(DEVICE_LOCAL, flags, parms)?.unpack_error()
+    }
+
+    // Takes an exclusive lock on the device, waiting up to the lock timeout if flags.waitlock is set
+    pub fn lock(&mut self, flags:DeviceFlags) -> Result<()> {
+        self.lock_with(flags, &CallParms::default())
+    }
+
+    // DEVICE_LOCK has no I/O timeout, so only the lock timeout in parms counts
+    pub fn lock_with(&mut self, flags:DeviceFlags, parms:&CallParms) -> Result<()> {
+        let (_, lock_timeout) = self.timeouts(None, parms.lock_timeout);
+        let mut conn = lock(&self.conn)?;
+        conn.client.start_call(DEVICE_LOCK)?;
+        xdr_pack::pack_device_lock_parms(&mut conn.client.packer, self.link.link_id, flags.to_i32(), lock_timeout)?;
+        conn.do_call(0, lock_timeout)?;
+        conn.unpack_error()
+    }
+
+    pub fn unlock(&mut self) -> Result<()> {
+        let mut conn = lock(&self.conn)?;
+        conn.client.start_call(DEVICE_UNLOCK)?;
+        xdr_pack::pack_device_link(&mut conn.client.packer, self.link.link_id)?;
+        conn.do_call(0, 0)?;
+        conn.unpack_error()
+    }
+
+    // Turns service requests on or off.  The handle (at most 40 bytes) comes back in each device_intr_srq call so
+    // the interrupt channel can tell links apart.
+    pub fn enable_srq(&mut self, enable:bool, handle:&[u8]) -> Result<()> {
+        if handle.len() > xdr_pack::MAX_HANDLE_SIZE as usize {
+            return Err(err("SRQ handle longer than 40 bytes"));
+        }
+
+        let mut conn = lock(&self.conn)?;
+        conn.client.start_call(DEVICE_ENABLE_SRQ)?;
+        xdr_pack::pack_device_enable_srq_parms(&mut conn.client.packer, self.link.link_id, enable, handle)?;
+        conn.do_call(0, 0)?;
+        conn.unpack_error()
+    }
+
+    // Sends a device-specific command, for example a GPIB bus command through a gateway.  datasize is the size of
+    // each element of data_in, which network_order says to byte swap if needed.
+    pub fn docmd(&mut self, flags:DeviceFlags, cmd:i32, network_order:bool, datasize:i32, data_in:&[u8]) -> Result<Vec<u8>> {
+        self.docmd_with(flags, cmd, network_order, datasize, data_in, &CallParms::default())
+    }
+
+    pub fn docmd_with(&mut self, flags:DeviceFlags, cmd:i32, network_order:bool, datasize:i32, data_in:&[u8], parms:&CallParms) -> Result<Vec<u8>> {
+        let (io_timeout, lock_timeout) = self.timeouts(parms.io_timeout, parms.lock_timeout);
+        let mut conn = lock(&self.conn)?;
+        conn.client.start_call(DEVICE_DOCMD)?;
+        xdr_pack::pack_device_docmd_parms(&mut conn.client.packer, self.link.link_id, flags.to_i32(), io_timeout, lock_timeout, cmd, network_order, datasize, data_in)?;
+        conn.do_call(io_timeout, lock_timeout)?;
+
+        let DeviceDocmdResp{ error, data_out } = conn.client.unpacker.unpack()?;
+        device_result(error)?;
+        Ok(data_out)
+    }
+
+    // Enables service requests on the link with a fresh handle, returning a receiver that gets a message for each one
+    pub fn subscribe_srq(&mut self) -> Result<Receiver<()>> {
+        let handle:Vec<u8> = SrqServer::new_handle();
+        let server:SrqServer = {
+            let mut conn = lock(&self.conn)?;
+            let server:SrqServer = conn.intr_chan.clone().ok_or_else(|| err("No interrupt channel open"))?;
+            if let Some(old) = self.srq_handle.take() {
+                conn.srq_handles.remove(&old);
+                server.unsubscribe(&old);
+            }
+            conn.srq_handles.insert(handle.clone());
+            server
+        };
+
+        let rx:Receiver<()> = server.subscribe(&handle);
+        if let Err(e) = self.enable_srq(true, &handle) {
+            server.unsubscribe(&handle);
+            lock(&self.conn)?.srq_handles.remove(&handle);
+            return Err(e);
+        }
+        self.srq_handle = Some(handle);
+        Ok(rx)
+    }
+
+    pub fn unsubscribe_srq(&mut self) -> Result<()> {
+        let handle:Vec<u8> = self.srq_handle.take().ok_or_else(|| err("Service requests aren't enabled"))?;
+        {
+            let mut conn = lock(&self.conn)?;
+            conn.srq_handles.remove(&handle);
+            if let Some(server) = &conn.intr_chan { server.unsubscribe(&handle); }
+        }
+        self.enable_srq(false, &handle)
+    }
+}
+
+impl<T: RpcTransport> Drop for DeviceLink<T> {
+    fn drop(&mut self) {
+        // Nothing useful to do with an error here; call destroy instead to see it
+        if !self.destroyed { let _ = self.destroy_inner(); }
+    }
+}
+
+// How long to wait for the reply to a call the instrument may spend up to io_timeout plus lock_timeout milliseconds
+// on: that long and REPLY_GRACE on top, but no less than the read timeout
+// The DEVICE_WRITE calls that make up one write, shared by DeviceLink and AsyncDeviceLink, which each make the calls
+// in between packing and unpacking
+struct WriteCalls<'a> {
+    data: &'a [u8],
+    sent: usize,
+    chunk_len: usize,     // Size of the chunk in the call last packed
+    link_id: i32,
+    max_recv_size: u32,
+    io_timeout: u32,
+    lock_timeout: u32,
+}
+
+impl<'a> WriteCalls<'a> {
+
+    fn new(link:&Link, data:&'a [u8], io_timeout:u32, lock_timeout:u32) -> Self {
+        Self{ data, sent: 0, chunk_len: 0, link_id: link.link_id, max_recv_size: link.max_recv_size, io_timeout, lock_timeout }
+    }
+
+    fn pack<T>(&mut self, client:&mut RpcClient<T>) -> Result<()> {
+        let (chunk, flags) = write_chunk(self.data, self.sent, self.max_recv_size);
+        self.chunk_len = chunk.len();
+        client.start_call(DEVICE_WRITE)?;
+        xdr_pack::pack_device_write_parms(&mut client.packer, self.link_id, self.io_timeout, self.lock_timeout, flags, chunk)?;
+        Ok(())
+    }
+
+    // Returns true once the device has taken all the data
+    fn unpack<T>(&mut self, client:&mut RpcClient<T>) -> Result<bool> {
+        let DeviceWriteResp{ error, size } = client.unpacker.unpack()?;
+        self.sent += write_result(error, size, self.chunk_len)?;
+        Ok(self.sent == self.data.len())
+    }
+}
+
+// The DEVICE_READ calls that make up one read, in the same way as WriteCalls
+struct ReadCalls<'a> {
+    parms: &'a ReadParms,
+    ans: Vec<u8>,
+    link_id: i32,
+    io_timeout: u32,
+    lock_timeout: u32,
+}
+
+impl<'a> ReadCalls<'a> {
+
+    fn new(link:&Link, parms:&'a ReadParms, io_timeout:u32, lock_timeout:u32) -> Self {
+        Self{ parms, ans: vec![], link_id: link.link_id, io_timeout, lock_timeout }
+    }
+
+    fn pack<T>(&self, client:&mut RpcClient<T>) -> Result<()> {
+        let (flags, term_char) = self.parms.flags_and_term_char();
+        client.start_call(DEVICE_READ)?;
+        xdr_pack::pack_device_read_parms(&mut client.packer, self.link_id, self.parms.chunk_size(self.ans.len()), self.io_timeout, self.lock_timeout, flags, term_char)?;
+        Ok(())
+    }
+
+    // Returns everything read and why it stopped once the read is finished
+    fn unpack<T>(&mut self, client:&mut RpcClient<T>) -> Result<Option<(Vec<u8>, ReadReason)>> {
+        let DeviceReadResp{ error, reason, data } = client.unpacker.unpack()?;
+        Ok(self.parms.add_chunk(&mut self.ans, read_result(error, reason)?, &data)?
+            .map(|reason| (std::mem::take(&mut self.ans), reason)))
+    }
+}
+
+fn reply_timeout(options:&LinkOptions, io_timeout:u32, lock_timeout:u32) -> Option<Duration> {
+    let allowed:Duration = Duration::from_millis(io_timeout as u64 + lock_timeout as u64) + REPLY_GRACE;
+    nonzero(options.read_timeout).map(|t| t.max(allowed))
+}
+
+// Turning the responses into results is shared with the async client
+
+// Error codes from the VXI-11 specification, for the calls that don't interpret them any differently
+fn device_result(error:i32) -> Result<()> {
+    match DeviceErrorCode::from_code(error) {
+        None       => Ok(()),
+        Some(code) => Err(Error::Device(code)),
+    }
+}
+
+// The next piece of a write starting at offset sent, and the flags to send it with.  A max_recv_size of zero is taken
+// to mean there's no limit.
+fn write_chunk(data:&[u8], sent:usize, max_recv_size:u32) -> (&[u8], i32) {
+    let max:usize = if max_recv_size == 0 { usize::MAX } else { max_recv_size as usize };
+    let end:usize = sent + (data.len() - sent).min(max);
+    let flags:i32 = if end == data.len() { OPERATION_FLAGS_END_ONLY } else { 0 };
+    (&data[sent..end], flags)
+}
+
+// Checks one DEVICE_WRITE reply, returning how many bytes the device took.  Anything it didn't take gets sent again.
+fn write_result(error:i32, size:u32, chunk_len:usize) -> Result<usize> {
+    let size:usize = size as usize;
+    if size > chunk_len {
+        return Err(err("Device acknowledged more bytes than were sent"));
+    }
+    if size == 0 && chunk_len > 0 && error == 0 {
+        return Err(err("Device accepted none of the bytes sent"));
+    }
+
+    device_result(error)?;
+    Ok(size)
+}
+
+// Checks one DEVICE_READ reply, returning the most important reason bit
+fn read_result(error:i32, reason:i32) -> Result<ReadReason> {
+    device_result(error)?;
+
+    if reason & !(REASON_REQCNT | REASON_CHR | REASON_END) != 0 {
+        Err(err("Bit in reason code that should be zero aren't zero"))
+    } else if reason & REASON_END != 0 {
+        Ok(ReadReason::End)
+    } else if reason & REASON_CHR != 0 {
+        Ok(ReadReason::TermChar)
+    } else if reason & REASON_REQCNT != 0 {
+        Ok(ReadReason::RequestCount)
+    } else {
+        Err(err("Expected one of three reason bits to be set"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::thread;
+
+    use crate::xdr::{Packer, Unpacker};
+    use crate::rpc::server::{Server, CallInfo, Outcome};
+    use crate::rpc::transport::ChannelTransport;
+    use crate::rpc::port_mapping::PortMapperServer;
+    use super::*;
+    use super::types::*;
+
+    const LINK_ID:i32 = 7;
+    const MAX_RECV_SIZE:u32 = 4;
+
+    type Written = Arc<Mutex<Vec<(Vec<u8>, usize, i32)>>>;      // Data, how much of it was taken and the flags
+    type ToRead = Arc<Mutex<Vec<(&'static [u8], i32)>>>;        // Data and reason, last first
+
+    // Simulated instrument with the given handlers, plus CREATE_LINK and DESTROY_LINK, on the other end of a channel
+    fn client(server:Server) -> CoreClient<ChannelTransport> {
+        let server:Server = instrument(server);
+        let (transport, peer) = ChannelTransport::pair();
+        thread::spawn(move || {
+            for call in peer.calls.iter() {
+                if let Some(reply) = server.dispatch(call, SocketAddr::from(([127, 0, 0, 1], 0))) {
+                    if peer.replies.send(reply).is_err() { break; }
                 }
-            },
-            4  => Err(err("Invalid link identifier")),
-            11 => Err(err("Device locked by another link")),
-            15 => Err(err("I/O timeout")),
-            17 => Err(err("I/O error")),
-            23 => Err(err("Abort")),
-            _  => Err(err("Unknown error")),
+            }
+        });
+        CoreClient::with_transport(transport)
+    }
+
+    fn instrument(mut server:Server) -> Server {
+        server.register(DEVICE_CORE_PROG, DEVICE_CORE_VERS, CREATE_LINK, |_:&CallInfo, args:&mut Unpacker, results:&mut Packer| {
+            let parms:CreateLinkParms = args.unpack()?;
+            let error:i32 = if parms.device == "inst0" { 0 } else { 3 };
+            results.pack(&CreateLinkResp{ error, link: Link{ link_id: LINK_ID, abort_port: 0, max_recv_size: MAX_RECV_SIZE } })?;
+            Ok(Outcome::Reply)
+        });
+        server.register(DEVICE_CORE_PROG, DEVICE_CORE_VERS, DESTROY_LINK, |_:&CallInfo, args:&mut Unpacker, results:&mut Packer| {
+            let error:i32 = if args.unpack_i32()? == LINK_ID { 0 } else { 4 };
+            results.pack(&DeviceError{ error })?;
+            Ok(Outcome::Reply)
+        });
+        server
+    }
+
+    fn link(server:Server) -> DeviceLink<ChannelTransport> {
+        client(server).create_link("inst0", false, 0).unwrap()
+    }
+
+    #[test]
+    fn create_and_destroy_link() {
+        let link = link(Server::new());
+        assert_eq!(link.link_id(), LINK_ID);
+        assert_eq!(link.device(), "inst0");
+        assert_eq!(link.link().max_recv_size, MAX_RECV_SIZE);
+        link.destroy().unwrap();
+    }
+
+    #[test]
+    fn core_program_found_through_the_portmapper() {
+        let core:SocketAddr = Arc::new(instrument(Server::new())).spawn_tcp("127.0.0.1:0").unwrap();
+        let pmap = PortMapperServer::new();
+        let pmap_port:u16 = pmap.spawn(SocketAddr::from((Ipv4Addr::LOCALHOST, 0))).unwrap().port();
+
+        // Not registered yet, so the rpcbind fallback finds nothing either
+        assert!(CoreClient::with_pmap_port("127.0.0.1", pmap_port).is_err());
+
+        let mut pmap_client = PortMapperClient::tcp("127.0.0.1", pmap_port).unwrap();
+        assert!(pmap_client.set(&Mapping{ port: core.port() as u32, ..core_mapping() }).unwrap());
+        assert_eq!(pmap_client.get_port(&core_mapping()).unwrap(), core.port() as u32);
+        assert!(pmap_client.dump().unwrap().contains(&Mapping{ port: core.port() as u32, ..core_mapping() }));
+
+        let client = CoreClient::with_pmap_port("127.0.0.1", pmap_port).unwrap();
+        client.create_link("inst0", false, 0).unwrap().destroy().unwrap();
+    }
+
+    #[test]
+    fn core_program_on_a_known_port() {
+        let core:SocketAddr = Arc::new(instrument(Server::new())).spawn_tcp("127.0.0.1:0").unwrap();
+        let client = CoreClient::with_core_port("127.0.0.1", core.port(), LinkOptions::default()).unwrap();
+        client.create_link("inst0", false, 0).unwrap().destroy().unwrap();
+    }
+
+    #[test]
+    fn create_link_error_code() {
+        let e = client(Server::new()).create_link("gpib0,5", false, 0).err().unwrap();
+        assert_eq!(e.device_code(), Some(DeviceErrorCode::DeviceNotAccessible));
+    }
+
+    #[test]
+    fn write_in_chunks_with_end_on_the_last_byte() {
+        let received:Written = Arc::new(Mutex::new(vec![]));
+        let mut server = Server::new();
+        let r = received.clone();
+        server.register(DEVICE_CORE_PROG, DEVICE_CORE_VERS, DEVICE_WRITE, move |_:&CallInfo, args:&mut Unpacker, results:&mut Packer| {
+            let parms:DeviceWriteParms = args.unpack()?;
+            assert_eq!(parms.lid, LINK_ID);
+            assert!(parms.data.len() <= MAX_RECV_SIZE as usize);
+
+            // Only ever takes three bytes, so every chunk after the first starts with a byte sent again
+            let size:usize = parms.data.len().min(3);
+            r.lock().unwrap().push((parms.data.clone(), size, parms.flags));
+            results.pack(&DeviceWriteResp{ error: 0, size: size as u32 })?;
+            Ok(Outcome::Reply)
+        });
+
+        link(server).write(b"0123456789").unwrap();
+
+        let received = received.lock().unwrap();
+        let taken:Vec<u8> = received.iter().flat_map(|(data, size, _)| data[..*size].to_vec()).collect();
+        assert_eq!(taken, b"0123456789");
+
+        // END goes with whichever chunks reach the last byte
+        for (data, _, flags) in received.iter() {
+            assert_eq!(flags & FLAG_END != 0, data.ends_with(b"9"), "flags {} on {:?}", flags, data);
         }
+    }
+
+    type Asked = Arc<Mutex<Vec<DeviceReadParms>>>;
 
+    // Answers DEVICE_READ with the chunks in turn, keeping the parameters of each call
+    fn reader(chunks:ToRead, asked:Asked) -> Server {
+        let mut server = Server::new();
+        server.register(DEVICE_CORE_PROG, DEVICE_CORE_VERS, DEVICE_READ, move |_:&CallInfo, args:&mut Unpacker, results:&mut Packer| {
+            asked.lock().unwrap().push(args.unpack()?);
+            let (data, reason) = chunks.lock().unwrap().pop().ok_or_else(|| io::Error::other("Read too many times"))?;
+            results.pack(&DeviceReadResp{ error: 0, reason, data: data.to_vec() })?;
+            Ok(Outcome::Reply)
+        });
+        server
     }
 
-    pub fn destroy_link(&mut self) -> io::Result<()> {
-        if self.opt_link.is_none() {
-            return Err(err("No link to destroy"));
+    #[test]
+    fn read_in_chunks_until_end() {
+        let chunks:ToRead = Arc::new(Mutex::new(vec![(b"ef", REASON_END), (b"cd", REASON_REQCNT), (b"ab", REASON_REQCNT)]));
+        let asked:Asked = Arc::new(Mutex::new(vec![]));
+
+        let parms = ReadParms{ request_size: 2, ..Default::default() };
+        assert_eq!(link(reader(chunks, asked.clone())).read_with(&parms).unwrap(), (b"abcdef".to_vec(), ReadReason::End));
+        assert!(asked.lock().unwrap().iter().all(|p| p.request_size == 2 && p.flags & FLAG_TERMCHRSET == 0));
+    }
+
+    #[test]
+    fn read_until_the_term_char() {
+        let chunks:ToRead = Arc::new(Mutex::new(vec![(b"\n", REASON_CHR), (b"1.5", REASON_REQCNT)]));
+        let asked:Asked = Arc::new(Mutex::new(vec![]));
+
+        let parms = ReadParms{ request_size: 3, term_char: Some(b'\n'), ..Default::default() };
+        assert_eq!(link(reader(chunks, asked.clone())).read_with(&parms).unwrap(), (b"1.5\n".to_vec(), ReadReason::TermChar));
+        for p in asked.lock().unwrap().iter() {
+            assert_eq!((p.flags & FLAG_TERMCHRSET, p.term_char), (FLAG_TERMCHRSET, b'\n' as i8));
         }
+    }
 
-        self.client.lastxid += 1;
-        self.client.packer.reset();
-        
-        let link_id:i32 = self.get_link()?;
-        pack_callheader_no_auth(&mut self.client.packer, self.client.lastxid, DEVICE_CORE_PROG, DEVICE_CORE_VERS, DESTROY_LINK)?;
-        self.client.packer.pack_i32(link_id)?;
-        self.client.do_call()?;
+    #[test]
+    fn read_stops_at_max_size() {
+        let chunks:ToRead = Arc::new(Mutex::new(vec![(b"de", REASON_REQCNT), (b"abc", REASON_REQCNT)]));
+        let asked:Asked = Arc::new(Mutex::new(vec![]));
+
+        // The last call only asks for what's left, and the read ends there even without END
+        let parms = ReadParms{ request_size: 3, max_size: 5, ..Default::default() };
+        assert_eq!(link(reader(chunks, asked.clone())).read_with(&parms).unwrap(), (b"abcde".to_vec(), ReadReason::RequestCount));
+        assert_eq!(asked.lock().unwrap().iter().map(|p| p.request_size).collect::<Vec<u32>>(), vec![3, 2]);
+    }
+
+    #[test]
+    fn more_bytes_than_requested_is_an_error() {
+        let chunks:ToRead = Arc::new(Mutex::new(vec![(b"abc", REASON_END)]));
+        let parms = ReadParms{ request_size: 2, ..Default::default() };
+        assert!(matches!(link(reader(chunks, Arc::default())).read_with(&parms), Err(Error::Protocol(_))));
+    }
+
+    #[test]
+    fn empty_request_count_chunk_is_an_error() {
+        let chunks:ToRead = Arc::new(Mutex::new(vec![(b"", REASON_REQCNT), (b"ab", REASON_REQCNT)]));
+        let parms = ReadParms{ request_size: 2, ..Default::default() };
+        assert!(matches!(link(reader(chunks, Arc::default())).read_with(&parms), Err(Error::Protocol(_))));
+    }
 
-        let device_error:i32 = self.client.unpacker.unpack_i32()?;
-        match device_error {
-            0 => Ok(()),
-            4 => Err(err("Invalid link identifier")),
-            _ => Err(err("Unknown error code")),
+    #[test]
+    fn device_error_codes_come_back_typed() {
+        let mut server = Server::new();
+        server.register(DEVICE_CORE_PROG, DEVICE_CORE_VERS, DEVICE_LOCK, |_:&CallInfo, args:&mut Unpacker, results:&mut Packer| {
+            let _:DeviceLockParms = args.unpack()?;
+            results.pack(&DeviceError{ error: 11 })?;
+            Ok(Outcome::Reply)
+        });
+        server.register(DEVICE_CORE_PROG, DEVICE_CORE_VERS, DEVICE_READ, |_:&CallInfo, args:&mut Unpacker, results:&mut Packer| {
+            let _:DeviceReadParms = args.unpack()?;
+            results.pack(&DeviceReadResp{ error: 15, reason: 0, data: vec![] })?;
+            Ok(Outcome::Reply)
+        });
+
+        let mut link = link(server);
+        match link.lock(DeviceFlags::default()) {
+            Err(Error::Device(DeviceErrorCode::DeviceLockedByAnotherLink)) => {},
+            other => panic!("Expected a lock error, got {:?}", other),
         }
+        assert_eq!(link.read().err().and_then(|e| e.device_code()), Some(DeviceErrorCode::IoTimeout));
+
+        // Procedures the instrument doesn't have are an RPC error rather than a device error
+        assert!(matches!(link.trigger(DeviceFlags::default()), Err(Error::Rpc(_))));
+    }
+
+    #[test]
+    fn calls_use_the_link_timeouts_unless_overridden() {
+        let sent:Arc<Mutex<Vec<(u32, u32)>>> = Arc::new(Mutex::new(vec![]));
+        let mut server = Server::new();
+        let s = sent.clone();
+        server.register(DEVICE_CORE_PROG, DEVICE_CORE_VERS, DEVICE_TRIGGER, move |_:&CallInfo, args:&mut Unpacker, results:&mut Packer| {
+            let parms:DeviceGenericParms = args.unpack()?;
+            s.lock().unwrap().push((parms.io_timeout, parms.lock_timeout));
+            results.pack(&DeviceError{ error: 0 })?;
+            Ok(Outcome::Reply)
+        });
+        let s = sent.clone();
+        server.register(DEVICE_CORE_PROG, DEVICE_CORE_VERS, DEVICE_LOCK, move |_:&CallInfo, args:&mut Unpacker, results:&mut Packer| {
+            let parms:DeviceLockParms = args.unpack()?;
+            s.lock().unwrap().push((0, parms.lock_timeout));
+            results.pack(&DeviceError{ error: 0 })?;
+            Ok(Outcome::Reply)
+        });
+
+        let mut link = link(server);
+        link.trigger(DeviceFlags::default()).unwrap();
+        link.set_io_timeout(100);
+        link.set_lock_timeout(200);
+        link.trigger(DeviceFlags::default()).unwrap();
+        link.trigger_with(DeviceFlags::default(), &CallParms{ io_timeout: Some(1), lock_timeout: None }).unwrap();
+        link.lock(DeviceFlags::default()).unwrap();
+        link.lock_with(DeviceFlags::default(), &CallParms{ io_timeout: Some(1), lock_timeout: Some(2) }).unwrap();
 
+        assert_eq!(*sent.lock().unwrap(), vec![(DEFAULT_IO_TIMEOUT, DEFAULT_LOCK_TIMEOUT), (100, 200), (1, 200), (0, 200), (0, 2)]);
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn zero_timeouts_wait_forever() {
+        let options = LinkOptions{ read_timeout: Some(Duration::ZERO), ..Default::default() };
+        assert_eq!(reply_timeout(&options, 1000, 0), None);
+
+        let options = LinkOptions{ read_timeout: Some(Duration::from_secs(1)), ..Default::default() };
+        assert_eq!(reply_timeout(&options, 1000, 500), Some(Duration::from_millis(1500) + REPLY_GRACE));
+        assert_eq!(reply_timeout(&options, 0, 0), Some(REPLY_GRACE));
+    }
+
+    #[test]
+    fn abort_handle_while_the_connection_is_busy() {
+        let mut abort_server = Server::new();
+        abort_server.register(DEVICE_ASYNC_PROG, DEVICE_ASYNC_VERS, DEVICE_ABORT, |_:&CallInfo, args:&mut Unpacker, results:&mut Packer| {
+            let error:i32 = if args.unpack_i32()? == LINK_ID { 0 } else { 4 };
+            results.pack(&DeviceError{ error })?;
+            Ok(Outcome::Reply)
+        });
+        let addr:SocketAddr = Arc::new(abort_server).spawn_tcp("127.0.0.1:0").unwrap();
+
+        let mut link = link(Server::new());
+        link.host = Some("127.0.0.1".to_owned());
+        link.link.abort_port = addr.port();
+
+        // As if another thread were in the middle of a long read
+        let conn = link.conn.clone();
+        let _busy = lock(&conn).unwrap();
+        link.abort_handle().unwrap().abort().unwrap();
+    }
+
+    #[test]
+    fn intr_chan_stays_open_while_links_have_srq_enabled() {
+        let mut server = Server::new();
+        for prc in [CREATE_INTR_CHAN, DESTROY_INTR_CHAN, DEVICE_ENABLE_SRQ] {
+            server.register(DEVICE_CORE_PROG, DEVICE_CORE_VERS, prc, |_:&CallInfo, args:&mut Unpacker, results:&mut Packer| {
+                args.take_remaining();      // The arguments don't matter here
+                results.pack(&DeviceError{ error: 0 })?;
+                Ok(Outcome::Reply)
+            });
+        }
+        let client = client(server);
+        let srq_server = SrqServer::spawn(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+        client.open_intr_chan(&srq_server, Ipv4Addr::LOCALHOST).unwrap();
+
+        let mut link = client.create_link("inst0", false, 0).unwrap();
+        let _rx = link.subscribe_srq().unwrap();
+        let _rx = link.subscribe_srq().unwrap();
+        assert!(matches!(client.close_intr_chan(), Err(Error::Protocol(_))));
+
+        link.unsubscribe_srq().unwrap();
+        client.close_intr_chan().unwrap();
+    }
+}
@@ -0,0 +1,127 @@
+
+// Argument and result types for the Device Core, Device Async and Device Interrupt programs, as described in the
+// VXI-11 specification.  Each of these packs and unpacks as XDR through xdr::Packer::pack and xdr::Unpacker::unpack.
+//
+// vxi11.x is the authoritative definition.  These are written by hand so CreateLinkResp can carry a Link and the
+// typedefs stay plain integers, but the field types match what rpcgen makes of vxi11.x, which the rpcgen tests check.
+
+use serde::{Serialize, Deserialize};
+
+use super::Link;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeviceError {
+	pub error: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateLinkParms {
+	pub client_id: i32,
+	pub lock_device: bool,
+	pub lock_timeout: u32,
+	pub device: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateLinkResp {
+	pub error: i32,
+	pub link: Link,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeviceWriteParms {
+	pub lid: i32,
+	pub io_timeout: u32,
+	pub lock_timeout: u32,
+	pub flags: i32,
+	#[serde(with = "crate::xdr::opaque::variable")]
+	pub data: Vec<u8>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeviceWriteResp {
+	pub error: i32,
+	pub size: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeviceReadParms {
+	pub lid: i32,
+	pub request_size: u32,
+	pub io_timeout: u32,
+	pub lock_timeout: u32,
+	pub flags: i32,
+	pub term_char: i8,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeviceReadResp {
+	pub error: i32,
+	pub reason: i32,
+	#[serde(with = "crate::xdr::opaque::variable")]
+	pub data: Vec<u8>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeviceReadStbResp {
+	pub error: i32,
+	pub stb: u8,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeviceGenericParms {
+	pub lid: i32,
+	pub flags: i32,
+	pub lock_timeout: u32,
+	pub io_timeout: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeviceRemoteFunc {
+	pub host_addr: u32,
+	pub host_port: u16,
+	pub prog_num: u32,
+	pub prog_vers: u32,
+	pub prog_family: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeviceEnableSrqParms {
+	pub lid: i32,
+	pub enable: bool,
+	#[serde(with = "crate::xdr::opaque::variable")]
+	pub handle: Vec<u8>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeviceLockParms {
+	pub lid: i32,
+	pub flags: i32,
+	pub lock_timeout: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeviceDocmdParms {
+	pub lid: i32,
+	pub flags: i32,
+	pub io_timeout: u32,
+	pub lock_timeout: u32,
+	pub cmd: i32,
+	pub network_order: bool,
+	pub datasize: i32,
+	#[serde(with = "crate::xdr::opaque::variable")]
+	pub data_in: Vec<u8>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeviceDocmdResp {
+	pub error: i32,
+	#[serde(with = "crate::xdr::opaque::variable")]
+	pub data_out: Vec<u8>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeviceSrqParms {
+	#[serde(with = "crate::xdr::opaque::variable")]
+	pub handle: Vec<u8>,
+}
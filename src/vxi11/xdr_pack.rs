@@ -1,20 +1,28 @@
-use std::io;
+use crate::xdr::{Packer, Result, XdrError, UNBOUNDED};
+use super::Link;
+use super::types::*;
 
-use crate::xdr::Packer;
+// Longest SRQ handle, from opaque handle<40> in vxi11.x
+pub const MAX_HANDLE_SIZE:u32 = 40;
 
-pub fn pack_device_link(packer:&mut Packer, link:i32) -> io::Result<()> {
+// Calls that carry a string or opaque data are packed a field at a time in the order vxi11.x gives, rather than
+// copying the data into the owned structs in types
+
+pub fn pack_device_link(packer:&mut Packer, link:i32) -> Result<()> {
 	packer.pack_i32(link)
 }
 
-pub fn pack_create_link_parms(packer:&mut Packer, id:i32, lock_device:bool, lock_timeout:u32, device:&str) -> io::Result<()> {
-	assert!(device.chars().all(|c| c.is_ascii()));
+pub fn pack_create_link_parms(packer:&mut Packer, id:i32, lock_device:bool, lock_timeout:u32, device:&str) -> Result<()> {
+	if !device.is_ascii() {
+		return Err(XdrError::Custom(format!("Device name {:?} isn't ASCII", device)));
+	}
 	packer.pack_i32(id)?;
 	packer.pack_bool(lock_device)?;
 	packer.pack_u32(lock_timeout)?;
-	packer.pack_variable_len_opaque(device.as_bytes())
+	packer.pack_string(device, UNBOUNDED)
 }
 
-pub fn pack_device_write_parms(packer:&mut Packer, link:i32, timeout:u32, lock_timeout:u32, flags:i32, data:&[u8]) -> io::Result<()> {
+pub fn pack_device_write_parms(packer:&mut Packer, link:i32, timeout:u32, lock_timeout:u32, flags:i32, data:&[u8]) -> Result<()> {
 	packer.pack_i32(link)?;
 	packer.pack_u32(timeout)?;
 	packer.pack_u32(lock_timeout)?;
@@ -22,86 +30,105 @@ pub fn pack_device_write_parms(packer:&mut Packer, link:i32, timeout:u32, lock_t
 	packer.pack_variable_len_opaque(data)
 }
 
-pub fn pack_device_read_parms(packer:&mut Packer, link:i32, request_size:u32, timeout:u32, lock_timeout:u32, flags:i32, term_char:i32) -> io::Result<()> {
-    packer.pack_i32(link)?;
-    packer.pack_u32(request_size)?;
-    packer.pack_u32(timeout)?;
-    packer.pack_u32(lock_timeout)?;
-    packer.pack_i32(flags)?;
-    packer.pack_i32(term_char)
+pub fn pack_device_read_parms(packer:&mut Packer, link:i32, request_size:u32, timeout:u32, lock_timeout:u32, flags:i32, term_char:i8) -> Result<()> {
+    packer.pack(&DeviceReadParms{ lid: link, request_size, io_timeout: timeout, lock_timeout, flags, term_char })
 }
 
-pub fn pack_device_generic_parms(packer:&mut Packer, link:i32, flags:i32, lock_timeout:u32, timeout:u32) -> io::Result<()> {
-    packer.pack_i32(link)?;
-    packer.pack_i32(flags)?;
-    packer.pack_u32(lock_timeout)?;
-    packer.pack_u32(timeout)
+pub fn pack_device_generic_parms(packer:&mut Packer, link:i32, flags:i32, lock_timeout:u32, timeout:u32) -> Result<()> {
+    packer.pack(&DeviceGenericParms{ lid: link, flags, lock_timeout, io_timeout: timeout })
 }
 
-pub fn pack_device_remote_func_parms(packer:&mut Packer, host_addr:u32, host_port:u32, prog_num:u32, prog_vers:u32, prog_family:i32) -> io::Result<()> {
-    packer.pack_u32(host_addr)?;
-    packer.pack_u32(host_port)?;
-    packer.pack_u32(prog_num)?;
-    packer.pack_u32(prog_vers)?;
-    packer.pack_i32(prog_family)
+pub fn pack_device_remote_func_parms(packer:&mut Packer, host_addr:u32, host_port:u16, prog_num:u32, prog_vers:u32, prog_family:i32) -> Result<()> {
+    packer.pack(&DeviceRemoteFunc{ host_addr, host_port, prog_num, prog_vers, prog_family })
 }
 
-pub fn pack_device_enable_srq_parms(packer:&mut Packer, link:i32, enable:bool, handle:&[u8]) -> io::Result<()> {
-	assert!(handle.len() < 40);
+pub fn pack_device_enable_srq_parms(packer:&mut Packer, link:i32, enable:bool, handle:&[u8]) -> Result<()> {
 	packer.pack_i32(link)?;
 	packer.pack_bool(enable)?;
-	packer.pack_variable_len_opaque(handle)
+	packer.pack_variable_len_opaque_max(handle, MAX_HANDLE_SIZE)
 }
 
-pub fn pack_device_lock_parms(packer:&mut Packer, link:i32, flags:i32, lock_timeout:u32) -> io::Result<()> {
-    packer.pack_i32(link)?;
-    packer.pack_i32(flags)?;
-    packer.pack_u32(lock_timeout)
+pub fn pack_device_lock_parms(packer:&mut Packer, link:i32, flags:i32, lock_timeout:u32) -> Result<()> {
+    packer.pack(&DeviceLockParms{ lid: link, flags, lock_timeout })
 }
 
-pub fn pack_device_docmd_parms(packer:&mut Packer, link:i32, flags:i32, timeout:u32, lock_timeout:u32, cmd:i32, network_order:bool, datasize:i32, data_in:&[u8]) -> io::Result<()> {
-    packer.pack_i32(link)?;
-    packer.pack_i32(flags)?;
-    packer.pack_u32(timeout)?;
-    packer.pack_u32(lock_timeout)?;
-    packer.pack_i32(cmd)?;
-    packer.pack_bool(network_order)?;
-    packer.pack_i32(datasize)?;
-    packer.pack_variable_len_opaque(data_in)
+#[allow(clippy::too_many_arguments)]
+pub fn pack_device_docmd_parms(packer:&mut Packer, link:i32, flags:i32, timeout:u32, lock_timeout:u32, cmd:i32, network_order:bool, datasize:i32, data_in:&[u8]) -> Result<()> {
+	packer.pack_i32(link)?;
+	packer.pack_i32(flags)?;
+	packer.pack_u32(timeout)?;
+	packer.pack_u32(lock_timeout)?;
+	packer.pack_i32(cmd)?;
+	packer.pack_bool(network_order)?;
+	packer.pack_i32(datasize)?;
+	packer.pack_variable_len_opaque(data_in)
 }
 
-pub fn pack_device_error(packer:&mut Packer, error:i32) -> io::Result<()> {
-	packer.pack_i32(error)
+pub fn pack_device_error(packer:&mut Packer, error:i32) -> Result<()> {
+	packer.pack(&DeviceError{ error })
 }
 
-pub fn pack_device_srq_parms(packer:&mut Packer, handle:&[u8]) -> io::Result<()> {
-    packer.pack_variable_len_opaque(handle)
+pub fn pack_device_srq_parms(packer:&mut Packer, handle:&[u8]) -> Result<()> {
+	packer.pack_variable_len_opaque_max(handle, MAX_HANDLE_SIZE)
 }
 
-pub fn pack_create_link_resp(packer:&mut Packer, error:i32, link:i32, abort_port:u32, max_recv_size:u32) -> io::Result<()> {
-    packer.pack_i32(error)?;
-    packer.pack_i32(link)?;
-    packer.pack_u32(abort_port)?;
-    packer.pack_u32(max_recv_size)
+pub fn pack_create_link_resp(packer:&mut Packer, error:i32, link:i32, abort_port:u16, max_recv_size:u32) -> Result<()> {
+    packer.pack(&CreateLinkResp{ error, link: Link{ link_id: link, abort_port, max_recv_size } })
 }
 
-pub fn pack_device_write_resp(packer:&mut Packer, error:i32, size:u32) -> io::Result<()> {
-    packer.pack_i32(error)?;
-    packer.pack_u32(size)
+pub fn pack_device_write_resp(packer:&mut Packer, error:i32, size:u32) -> Result<()> {
+    packer.pack(&DeviceWriteResp{ error, size })
 }
 
-pub fn pack_device_read_resp(packer:&mut Packer, error:i32, reason:i32, data:&[u8]) -> io::Result<()> {
-    packer.pack_i32(error)?;
-    packer.pack_i32(reason)?;
-    packer.pack_variable_len_opaque(data)
+pub fn pack_device_read_resp(packer:&mut Packer, error:i32, reason:i32, data:&[u8]) -> Result<()> {
+	packer.pack_i32(error)?;
+	packer.pack_i32(reason)?;
+	packer.pack_variable_len_opaque(data)
 }
 
-pub fn pack_device_read_stb_resp(packer:&mut Packer, error:i32, stb:u32) -> io::Result<()> {
-    packer.pack_i32(error)?;
-    packer.pack_u32(stb)
+pub fn pack_device_read_stb_resp(packer:&mut Packer, error:i32, stb:u8) -> Result<()> {
+    packer.pack(&DeviceReadStbResp{ error, stb })
 }
 
-pub fn pack_device_docmd_resp(packer:&mut Packer, error:i32, data_out:&[u8]) -> io::Result<()> {
-    packer.pack_i32(error)?;
-    packer.pack_variable_len_opaque(data_out)
+pub fn pack_device_docmd_resp(packer:&mut Packer, error:i32, data_out:&[u8]) -> Result<()> {
+	packer.pack_i32(error)?;
+	packer.pack_variable_len_opaque(data_out)
+}
+
+#[cfg(test)]
+mod tests {
+	use serde::Serialize;
+
+	use super::*;
+
+	fn packed<F: FnOnce(&mut Packer) -> Result<()>>(f:F) -> Vec<u8> {
+		let mut packer = Packer::new();
+		f(&mut packer).unwrap();
+		packer.buff
+	}
+
+	fn serialized<T: Serialize>(value:&T) -> Vec<u8> { packed(|p| p.pack(value)) }
+
+	#[test]
+	fn field_by_field_matches_the_types() {
+		assert_eq!(packed(|p| pack_create_link_parms(p, 3, true, 10, "inst0")),
+			serialized(&CreateLinkParms{ client_id: 3, lock_device: true, lock_timeout: 10, device: "inst0".to_owned() }));
+		assert_eq!(packed(|p| pack_device_write_parms(p, 1, 2, 3, 8, b"abcde")),
+			serialized(&DeviceWriteParms{ lid: 1, io_timeout: 2, lock_timeout: 3, flags: 8, data: b"abcde".to_vec() }));
+		assert_eq!(packed(|p| pack_device_enable_srq_parms(p, 1, true, b"h")),
+			serialized(&DeviceEnableSrqParms{ lid: 1, enable: true, handle: b"h".to_vec() }));
+		assert_eq!(packed(|p| pack_device_docmd_parms(p, 1, 8, 2, 3, 0x20000, true, 1, b"xy")),
+			serialized(&DeviceDocmdParms{ lid: 1, flags: 8, io_timeout: 2, lock_timeout: 3, cmd: 0x20000, network_order: true, datasize: 1, data_in: b"xy".to_vec() }));
+		assert_eq!(packed(|p| pack_device_srq_parms(p, b"handle")), serialized(&DeviceSrqParms{ handle: b"handle".to_vec() }));
+		assert_eq!(packed(|p| pack_device_read_resp(p, 0, 4, b"abc")), serialized(&DeviceReadResp{ error: 0, reason: 4, data: b"abc".to_vec() }));
+		assert_eq!(packed(|p| pack_device_docmd_resp(p, 0, b"out")), serialized(&DeviceDocmdResp{ error: 0, data_out: b"out".to_vec() }));
+	}
+
+	#[test]
+	fn bad_arguments_are_errors() {
+		let mut packer = Packer::new();
+		assert!(matches!(pack_create_link_parms(&mut packer, 3, false, 0, "inst\u{e9}"), Err(XdrError::Custom(_))));
+		assert!(matches!(pack_device_enable_srq_parms(&mut packer, 1, true, &[0; 41]), Err(XdrError::LengthExceedsBound{ len: 41, max: 40, .. })));
+		assert!(matches!(pack_device_srq_parms(&mut packer, &[0; 41]), Err(XdrError::LengthExceedsBound{ len: 41, max: 40, .. })));
+	}
 }
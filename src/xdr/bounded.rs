@@ -0,0 +1,64 @@
+
+// Helpers for variable-length data with a maximum size, for use with #[serde(serialize_with = "...",
+// deserialize_with = "...")] and the bound as a const parameter, for example
+// "xdr::bounded::string::serialize::<_, 255>".  Data over the bound is an error either way.
+
+use std::fmt::Display;
+
+fn too_long(len:usize, max:u32) -> impl Display {
+	format!("Length {} exceeds the bound of {}", len, max)
+}
+
+// opaque identifier<MAX>
+pub mod opaque {
+	use serde::{Serializer, Deserializer};
+	use serde::de::Error as _;
+	use serde::ser::Error as _;
+
+	pub fn serialize<S: Serializer, const MAX:u32>(data:&[u8], s:S) -> Result<S::Ok, S::Error> {
+		if data.len() > MAX as usize { return Err(S::Error::custom(super::too_long(data.len(), MAX))); }
+		super::super::opaque::variable::serialize(data, s)
+	}
+
+	pub fn deserialize<'de, D: Deserializer<'de>, const MAX:u32>(d:D) -> Result<Vec<u8>, D::Error> {
+		let data:Vec<u8> = super::super::opaque::variable::deserialize(d)?;
+		if data.len() > MAX as usize { return Err(D::Error::custom(super::too_long(data.len(), MAX))); }
+		Ok(data)
+	}
+}
+
+// string identifier<MAX>
+pub mod string {
+	use serde::{Serialize, Deserialize, Serializer, Deserializer};
+	use serde::de::Error as _;
+	use serde::ser::Error as _;
+
+	pub fn serialize<S: Serializer, const MAX:u32>(data:&str, s:S) -> Result<S::Ok, S::Error> {
+		if data.len() > MAX as usize { return Err(S::Error::custom(super::too_long(data.len(), MAX))); }
+		data.serialize(s)
+	}
+
+	pub fn deserialize<'de, D: Deserializer<'de>, const MAX:u32>(d:D) -> Result<String, D::Error> {
+		let data:String = String::deserialize(d)?;
+		if data.len() > MAX as usize { return Err(D::Error::custom(super::too_long(data.len(), MAX))); }
+		Ok(data)
+	}
+}
+
+// type identifier<MAX>
+pub mod array {
+	use serde::{Serialize, Deserialize, Serializer, Deserializer};
+	use serde::de::Error as _;
+	use serde::ser::Error as _;
+
+	pub fn serialize<S: Serializer, T: Serialize, const MAX:u32>(data:&[T], s:S) -> Result<S::Ok, S::Error> {
+		if data.len() > MAX as usize { return Err(S::Error::custom(super::too_long(data.len(), MAX))); }
+		data.serialize(s)
+	}
+
+	pub fn deserialize<'de, D: Deserializer<'de>, T: Deserialize<'de>, const MAX:u32>(d:D) -> Result<Vec<T>, D::Error> {
+		let data:Vec<T> = Vec::deserialize(d)?;
+		if data.len() > MAX as usize { return Err(D::Error::custom(super::too_long(data.len(), MAX))); }
+		Ok(data)
+	}
+}
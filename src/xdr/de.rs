@@ -0,0 +1,236 @@
+
+// Serde deserializer that reads XDR (RFC 4506) out of an Unpacker
+//
+// XDR isn't self-describing, so the type being deserialized has to drive the process and deserialize_any isn't
+// supported.  See xdr::ser for how Rust types map onto XDR, including how enum discriminants are chosen.
+
+use std::convert::TryFrom;
+
+use serde::de::{self, Visitor, DeserializeSeed, DeserializeOwned, IntoDeserializer};
+
+use super::{Unpacker, XdrError, Result, UNBOUNDED};
+use super::opaque::FIXED_OPAQUE_TOKEN;
+
+pub struct Deserializer<'a> {
+	unpacker: &'a mut Unpacker,
+}
+
+impl<'a> Deserializer<'a> {
+	pub fn new(unpacker:&'a mut Unpacker) -> Self { Self{ unpacker } }
+
+	// For a value that was just unpacked as a four byte integer but doesn't fit in the target type
+	fn out_of_range(&self, value:i64, target:&'static str) -> XdrError {
+		XdrError::OutOfRange{ offset: self.unpacker.position() - 4, value, target }
+	}
+}
+
+pub fn unpack<T: DeserializeOwned>(unpacker:&mut Unpacker) -> Result<T> {
+	T::deserialize(&mut Deserializer::new(unpacker))
+}
+
+pub fn from_bytes<T: DeserializeOwned>(data:&[u8]) -> Result<T> {
+	let mut unpacker = Unpacker::new();
+	unpacker.reset(data);
+	let ans:T = unpack(&mut unpacker)?;
+
+	if unpacker.all_data_consumed() { Ok(ans) }
+	else { Err(XdrError::TrailingData{ offset: unpacker.position(), len: unpacker.remaining().len() }) }
+}
+
+// Figure out which variant of an enum or union a discriminant refers to.  If any of the variants are named with an
+// integer, the discriminant is matched against those names, otherwise the discriminant is the variant index.
+fn variant_index(offset:usize, disc:i32, variants:&'static [&'static str]) -> Result<u32> {
+	let numbered:bool = variants.iter().any(|v| v.parse::<i32>().is_ok());
+	let idx:Option<usize> = if numbered {
+		variants.iter().position(|v| v.parse::<i32>() == Ok(disc))
+	} else if disc >= 0 && (disc as usize) < variants.len() {
+		Some(disc as usize)
+	} else {
+		None
+	};
+
+	idx.map(|i| i as u32).ok_or(XdrError::InvalidDiscriminant{ offset, value: disc })
+}
+
+impl<'de, 'a, 'b> de::Deserializer<'de> for &'b mut Deserializer<'a> {
+	type Error = XdrError;
+
+	fn deserialize_any<V: Visitor<'de>>(self, _visitor:V) -> Result<V::Value> {
+		Err(XdrError::Unsupported("self-describing deserialization"))
+	}
+
+	fn deserialize_bool<V: Visitor<'de>>(self, visitor:V) -> Result<V::Value> {
+		visitor.visit_bool(self.unpacker.unpack_bool()?)
+	}
+
+	fn deserialize_i8<V: Visitor<'de>>(self, visitor:V) -> Result<V::Value> {
+		let x:i32 = self.unpacker.unpack_i32()?;
+		visitor.visit_i8(i8::try_from(x).map_err(|_| self.out_of_range(x as i64, "i8"))?)
+	}
+
+	fn deserialize_i16<V: Visitor<'de>>(self, visitor:V) -> Result<V::Value> {
+		let x:i32 = self.unpacker.unpack_i32()?;
+		visitor.visit_i16(i16::try_from(x).map_err(|_| self.out_of_range(x as i64, "i16"))?)
+	}
+
+	fn deserialize_i32<V: Visitor<'de>>(self, visitor:V) -> Result<V::Value> { visitor.visit_i32(self.unpacker.unpack_i32()?) }
+	fn deserialize_i64<V: Visitor<'de>>(self, visitor:V) -> Result<V::Value> { visitor.visit_i64(self.unpacker.unpack_i64()?) }
+
+	fn deserialize_u8<V: Visitor<'de>>(self, visitor:V) -> Result<V::Value> {
+		let x:u32 = self.unpacker.unpack_u32()?;
+		visitor.visit_u8(u8::try_from(x).map_err(|_| self.out_of_range(x as i64, "u8"))?)
+	}
+
+	fn deserialize_u16<V: Visitor<'de>>(self, visitor:V) -> Result<V::Value> {
+		let x:u32 = self.unpacker.unpack_u32()?;
+		visitor.visit_u16(u16::try_from(x).map_err(|_| self.out_of_range(x as i64, "u16"))?)
+	}
+
+	fn deserialize_u32<V: Visitor<'de>>(self, visitor:V) -> Result<V::Value> { visitor.visit_u32(self.unpacker.unpack_u32()?) }
+	fn deserialize_u64<V: Visitor<'de>>(self, visitor:V) -> Result<V::Value> { visitor.visit_u64(self.unpacker.unpack_u64()?) }
+
+	fn deserialize_f32<V: Visitor<'de>>(self, visitor:V) -> Result<V::Value> { visitor.visit_f32(self.unpacker.unpack_f32()?) }
+	fn deserialize_f64<V: Visitor<'de>>(self, visitor:V) -> Result<V::Value> { visitor.visit_f64(self.unpacker.unpack_f64()?) }
+
+	fn deserialize_char<V: Visitor<'de>>(self, visitor:V) -> Result<V::Value> {
+		let x:u32 = self.unpacker.unpack_u32()?;
+		visitor.visit_char(char::from_u32(x).ok_or_else(|| self.out_of_range(x as i64, "char"))?)
+	}
+
+	fn deserialize_str<V: Visitor<'de>>(self, visitor:V) -> Result<V::Value> { self.deserialize_string(visitor) }
+	fn deserialize_string<V: Visitor<'de>>(self, visitor:V) -> Result<V::Value> {
+		visitor.visit_string(self.unpacker.unpack_string(UNBOUNDED)?)
+	}
+
+	fn deserialize_bytes<V: Visitor<'de>>(self, visitor:V) -> Result<V::Value> {
+		visitor.visit_bytes(self.unpacker.unpack_variable_len_opaque_ref(UNBOUNDED)?)
+	}
+	fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor:V) -> Result<V::Value> {
+		visitor.visit_byte_buf(self.unpacker.unpack_variable_len_opaque()?)
+	}
+
+	fn deserialize_option<V: Visitor<'de>>(self, visitor:V) -> Result<V::Value> {
+		if self.unpacker.unpack_bool()? { visitor.visit_some(self) }
+		else { visitor.visit_none() }
+	}
+
+	fn deserialize_unit<V: Visitor<'de>>(self, visitor:V) -> Result<V::Value> { visitor.visit_unit() }
+	fn deserialize_unit_struct<V: Visitor<'de>>(self, _name:&'static str, visitor:V) -> Result<V::Value> { visitor.visit_unit() }
+
+	fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name:&'static str, visitor:V) -> Result<V::Value> {
+		visitor.visit_newtype_struct(self)
+	}
+
+	fn deserialize_seq<V: Visitor<'de>>(self, visitor:V) -> Result<V::Value> {
+		let len:u32 = self.unpacker.unpack_u32()?;
+		visitor.visit_seq(Access{ de: self, len: len as usize })
+	}
+
+	fn deserialize_tuple<V: Visitor<'de>>(self, len:usize, visitor:V) -> Result<V::Value> {
+		visitor.visit_seq(Access{ de: self, len })
+	}
+
+	fn deserialize_tuple_struct<V: Visitor<'de>>(self, name:&'static str, len:usize, visitor:V) -> Result<V::Value> {
+		if name == FIXED_OPAQUE_TOKEN {
+			let data:Vec<u8> = self.unpacker.unpack_fixed_len_opaque(len)?;
+			visitor.visit_byte_buf(data)
+		} else {
+			visitor.visit_seq(Access{ de: self, len })
+		}
+	}
+
+	fn deserialize_map<V: Visitor<'de>>(self, visitor:V) -> Result<V::Value> {
+		let len:u32 = self.unpacker.unpack_u32()?;
+		visitor.visit_map(Access{ de: self, len: len as usize })
+	}
+
+	fn deserialize_struct<V: Visitor<'de>>(self, _name:&'static str, fields:&'static [&'static str], visitor:V) -> Result<V::Value> {
+		visitor.visit_seq(Access{ de: self, len: fields.len() })
+	}
+
+	fn deserialize_enum<V: Visitor<'de>>(self, _name:&'static str, variants:&'static [&'static str], visitor:V) -> Result<V::Value> {
+		let offset:usize = self.unpacker.position();
+		let disc:i32 = self.unpacker.unpack_enum()?;
+		let index:u32 = variant_index(offset, disc, variants)?;
+		visitor.visit_enum(Enum{ de: self, index })
+	}
+
+	fn deserialize_identifier<V: Visitor<'de>>(self, _visitor:V) -> Result<V::Value> {
+		Err(XdrError::Unsupported("identifiers"))
+	}
+
+	fn deserialize_ignored_any<V: Visitor<'de>>(self, _visitor:V) -> Result<V::Value> {
+		Err(XdrError::Unsupported("skipping over unknown data"))
+	}
+
+	fn is_human_readable(&self) -> bool { false }
+}
+
+// Access to a known number of consecutive elements, used for arrays, structs and maps
+struct Access<'a, 'b> {
+	de: &'b mut Deserializer<'a>,
+	len: usize,
+}
+
+impl<'de, 'a, 'b> de::SeqAccess<'de> for Access<'a, 'b> {
+	type Error = XdrError;
+
+	fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed:T) -> Result<Option<T::Value>> {
+		if self.len == 0 { return Ok(None); }
+		self.len -= 1;
+		seed.deserialize(&mut *self.de).map(Some)
+	}
+
+	fn size_hint(&self) -> Option<usize> { Some(self.len) }
+}
+
+impl<'de, 'a, 'b> de::MapAccess<'de> for Access<'a, 'b> {
+	type Error = XdrError;
+
+	fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed:K) -> Result<Option<K::Value>> {
+		if self.len == 0 { return Ok(None); }
+		self.len -= 1;
+		seed.deserialize(&mut *self.de).map(Some)
+	}
+
+	fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed:V) -> Result<V::Value> {
+		seed.deserialize(&mut *self.de)
+	}
+
+	fn size_hint(&self) -> Option<usize> { Some(self.len) }
+}
+
+// Access to the arm of a union once the discriminant has been read
+struct Enum<'a, 'b> {
+	de: &'b mut Deserializer<'a>,
+	index: u32,
+}
+
+impl<'de, 'a, 'b> de::EnumAccess<'de> for Enum<'a, 'b> {
+	type Error = XdrError;
+	type Variant = Self;
+
+	fn variant_seed<V: DeserializeSeed<'de>>(self, seed:V) -> Result<(V::Value, Self)> {
+		let index:u32 = self.index;
+		let value = seed.deserialize(IntoDeserializer::<XdrError>::into_deserializer(index))?;
+		Ok((value, self))
+	}
+}
+
+impl<'de, 'a, 'b> de::VariantAccess<'de> for Enum<'a, 'b> {
+	type Error = XdrError;
+
+	fn unit_variant(self) -> Result<()> { Ok(()) }
+
+	fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed:T) -> Result<T::Value> {
+		seed.deserialize(self.de)
+	}
+
+	fn tuple_variant<V: Visitor<'de>>(self, len:usize, visitor:V) -> Result<V::Value> {
+		visitor.visit_seq(Access{ de: self.de, len })
+	}
+
+	fn struct_variant<V: Visitor<'de>>(self, fields:&'static [&'static str], visitor:V) -> Result<V::Value> {
+		visitor.visit_seq(Access{ de: self.de, len: fields.len() })
+	}
+}
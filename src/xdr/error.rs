@@ -0,0 +1,74 @@
+
+use std::fmt;
+use std::io::{self, ErrorKind};
+
+// Everything that can go wrong while packing or unpacking XDR.  Decoding errors record the byte offset into the
+// message where the problem was found.
+#[derive(Debug)]
+pub enum XdrError {
+	UnexpectedEof{ offset:usize, needed:usize, available:usize },
+	InvalidBool{ offset:usize, value:i32 },
+	InvalidDiscriminant{ offset:usize, value:i32 },
+	LengthExceedsBound{ offset:usize, len:usize, max:u32 },
+	FixedLengthMismatch{ offset:usize, len:usize, expected:usize },
+	MisalignedPadding{ offset:usize, len:usize },
+	NonZeroPadding{ offset:usize },
+	InvalidUtf8{ offset:usize },
+	OutOfRange{ offset:usize, value:i64, target:&'static str },
+	TrailingData{ offset:usize, len:usize },
+	Unsupported(&'static str),
+	Custom(String),
+}
+
+pub type Result<T> = std::result::Result<T, XdrError>;
+
+impl fmt::Display for XdrError {
+	fn fmt(&self, f:&mut fmt::Formatter) -> fmt::Result {
+		match self {
+			XdrError::UnexpectedEof{ offset, needed, available } =>
+				write!(f, "Needed {} bytes at offset {} but only {} are available", needed, offset, available),
+			XdrError::InvalidBool{ offset, value } =>
+				write!(f, "Expected 0 or 1 for a bool at offset {} but got {}", offset, value),
+			XdrError::InvalidDiscriminant{ offset, value } =>
+				write!(f, "Unrecognized discriminant {} at offset {}", value, offset),
+			XdrError::LengthExceedsBound{ offset, len, max } =>
+				write!(f, "Length {} at offset {} exceeds the maximum of {}", len, offset, max),
+			XdrError::FixedLengthMismatch{ offset, len, expected } =>
+				write!(f, "Expected {} elements at offset {} but got {}", expected, offset, len),
+			XdrError::MisalignedPadding{ offset, len } =>
+				write!(f, "Skipping {} bytes at offset {} would break four-byte alignment", len, offset),
+			XdrError::NonZeroPadding{ offset } =>
+				write!(f, "Non-zero padding byte at offset {}", offset),
+			XdrError::InvalidUtf8{ offset } =>
+				write!(f, "String at offset {} isn't valid UTF-8", offset),
+			XdrError::OutOfRange{ offset, value, target } =>
+				write!(f, "Value {} at offset {} is out of range for {}", value, offset, target),
+			XdrError::TrailingData{ offset, len } =>
+				write!(f, "{} bytes unexpectedly left over at offset {}", len, offset),
+			XdrError::Unsupported(s) =>
+				write!(f, "XDR has no representation for {}", s),
+			XdrError::Custom(msg) =>
+				write!(f, "{}", msg),
+		}
+	}
+}
+
+impl std::error::Error for XdrError {}
+
+impl serde::ser::Error for XdrError {
+	fn custom<T: fmt::Display>(msg:T) -> Self { XdrError::Custom(msg.to_string()) }
+}
+
+impl serde::de::Error for XdrError {
+	fn custom<T: fmt::Display>(msg:T) -> Self { XdrError::Custom(msg.to_string()) }
+}
+
+// The XdrError can be recovered from the io::Error with get_ref and downcast_ref
+impl From<XdrError> for io::Error {
+	fn from(e:XdrError) -> Self {
+		match e {
+			XdrError::UnexpectedEof{ .. } => io::Error::new(ErrorKind::UnexpectedEof, e),
+			_                             => io::Error::new(ErrorKind::InvalidData, e),
+		}
+	}
+}
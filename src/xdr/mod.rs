@@ -0,0 +1,424 @@
+
+use std::io;
+
+use serde::{Serialize, de::DeserializeOwned};
+
+pub mod error;
+pub use self::error::{XdrError, Result};
+
+// Serde support, so that types which derive Serialize and Deserialize can be packed and unpacked as XDR
+pub mod ser;
+pub mod de;
+pub mod opaque;
+pub mod bounded;
+
+pub use self::ser::to_bytes;
+pub use self::de::from_bytes;
+
+// Bound to use for strings, opaque data and arrays declared without a maximum size, like string<> or opaque<>
+pub const UNBOUNDED:u32 = u32::MAX;
+
+#[derive(Default)]
+pub struct Packer{
+	pub buff:Vec<u8>
+}
+
+// Reads from an owned buffer by advancing a read position, so nothing gets shifted around as data is consumed
+#[derive(Default)]
+pub struct Unpacker {
+	buff:Vec<u8>,
+	pos:usize,
+}
+
+fn check_bound(offset:usize, len:usize, max:u32) -> Result<()> {
+	if len > max as usize { Err(XdrError::LengthExceedsBound{ offset, len, max }) }
+	else { Ok(()) }
+}
+
+impl Packer {
+
+	pub fn new() -> Self { Packer{buff: Vec::new()} }
+
+	pub fn reset(&mut self) { self.buff.clear(); }
+
+	pub fn get_buf(&mut self) -> io::Result<Vec<u8>> {
+		Ok(self.buff.clone())
+	}
+
+	// Packing methods that can only add multiples of four bytes, so if we started off with the correct
+	// padding, we'll end up with the correct padding
+	pub fn pack_u32(&mut self, x:u32) -> Result<()> { self.buff.extend_from_slice(&x.to_be_bytes()); Ok(()) }
+	pub fn pack_i32(&mut self, x:i32) -> Result<()> { self.buff.extend_from_slice(&x.to_be_bytes()); Ok(()) }
+
+	pub fn pack_bool(&mut self, b:bool) -> Result<()> {
+		if b { self.pack_i32(1) }
+		else { self.pack_i32(0) }
+	}
+
+	pub fn pack_enum(&mut self, x:i32) -> Result<()> { self.pack_i32(x) }
+
+	pub fn pack_u64(&mut self, x:u64) -> Result<()> { self.buff.extend_from_slice(&x.to_be_bytes()); Ok(()) }
+	pub fn pack_i64(&mut self, x:i64) -> Result<()> { self.buff.extend_from_slice(&x.to_be_bytes()); Ok(()) }
+	pub fn pack_f32(&mut self, x:f32) -> Result<()> { self.pack_u32(x.to_bits()) }
+	pub fn pack_f64(&mut self, x:f64) -> Result<()> { self.pack_u64(x.to_bits()) }
+
+	// Packing methods that require padding checks at the end
+	pub fn pack_fixed_len_opaque(&mut self, data:&[u8]) -> Result<()> {
+		self.buff.extend_from_slice(data);
+
+		// Ensure alignment
+		while !self.buff.len().is_multiple_of(4) { self.buff.push(0); }
+		Ok(())
+	}
+
+	// Data longer than a u32 can count is an error even without a bound
+	pub fn pack_variable_len_opaque(&mut self, data:&[u8]) -> Result<()> {
+		self.pack_variable_len_opaque_max(data, UNBOUNDED)
+	}
+
+	pub fn pack_variable_len_opaque_max(&mut self, data:&[u8], max:u32) -> Result<()> {
+		self.pack_len(data.len(), max)?;
+		self.pack_fixed_len_opaque(data)
+	}
+
+	// Length prefix for opaque data, strings, arrays and maps
+	pub(crate) fn pack_len(&mut self, len:usize, max:u32) -> Result<()> {
+		check_bound(self.buff.len(), len, max)?;
+		self.pack_u32(len as u32)
+	}
+
+	pub fn pack_string(&mut self, s:&str, max:u32) -> Result<()> {
+		self.pack_variable_len_opaque_max(s.as_bytes(), max)
+	}
+
+	// Arrays and optional-data, where each element is packed by the function provided
+	pub fn pack_fixed_array<T, F, E>(&mut self, items:&[T], n:usize, mut f:F) -> std::result::Result<(), E>
+		where F: FnMut(&mut Self, &T) -> std::result::Result<(), E>, E: From<XdrError>
+	{
+		if items.len() != n {
+			return Err(XdrError::FixedLengthMismatch{ offset: self.buff.len(), len: items.len(), expected: n }.into());
+		}
+		for item in items { f(self, item)?; }
+		Ok(())
+	}
+
+	pub fn pack_variable_array<T, F, E>(&mut self, items:&[T], max:u32, f:F) -> std::result::Result<(), E>
+		where F: FnMut(&mut Self, &T) -> std::result::Result<(), E>, E: From<XdrError>
+	{
+		self.pack_len(items.len(), max)?;
+		self.pack_fixed_array(items, items.len(), f)
+	}
+
+	pub fn pack_optional<T, F, E>(&mut self, opt:Option<&T>, mut f:F) -> std::result::Result<(), E>
+		where F: FnMut(&mut Self, &T) -> std::result::Result<(), E>, E: From<XdrError>
+	{
+		match opt {
+			Some(x) => { self.pack_bool(true)?; f(self, x) },
+			None    => Ok(self.pack_bool(false)?),
+		}
+	}
+
+	// Pack anything that implements Serialize
+	pub fn pack<T: Serialize + ?Sized>(&mut self, value:&T) -> Result<()> {
+		ser::pack(self, value)
+	}
+
+}
+
+impl Unpacker {
+
+	pub fn new() -> Self { Unpacker{buff: Vec::new(), pos: 0} }
+
+	// Copies the data, so anything that already owns its buffer should use reset_owned
+	pub fn reset(&mut self, data:&[u8]) {
+		self.buff.clear();
+		self.buff.extend_from_slice(data);
+		self.pos = 0;
+	}
+
+	// Same as reset but takes ownership of the data instead of copying it
+	pub fn reset_owned(&mut self, data:Vec<u8>) {
+		self.buff = data;
+		self.pos = 0;
+	}
+
+	pub fn position(&self) -> usize { self.pos }
+	pub fn remaining(&self) -> &[u8] { &self.buff[self.pos..] }
+
+	pub fn all_data_consumed(&self) -> bool { self.pos >= self.buff.len() }
+	pub fn drop(&mut self, n:usize) -> Result<()> {
+		if !n.is_multiple_of(4) {
+			return Err(XdrError::MisalignedPadding{ offset: self.pos, len: n });
+		}
+		self.take(n).map(|_| ())
+	}
+	pub fn peek(&self, idx:usize) -> Result<u8> {
+		self.remaining().get(idx).copied()
+			.ok_or(XdrError::UnexpectedEof{ offset: self.pos, needed: idx + 1, available: self.remaining().len() })
+	}
+	pub fn get_remaining_bytes(&mut self) -> io::Result<Vec<u8>> {
+		Ok(self.remaining().to_vec())
+	}
+
+	// Hands over the bytes not read yet, leaving the unpacker empty.  They're moved to the front of the buffer
+	// rather than copied into a new one.
+	pub fn take_remaining(&mut self) -> Vec<u8> {
+		let mut ans:Vec<u8> = std::mem::take(&mut self.buff);
+		ans.drain(..self.pos);
+		self.pos = 0;
+		ans
+	}
+
+	// Hands back the whole buffer, read or not, for callers that reuse it
+	pub fn take_buffer(&mut self) -> Vec<u8> {
+		self.pos = 0;
+		std::mem::take(&mut self.buff)
+	}
+
+	// Advance the read position by n bytes and return the bytes passed over
+	fn take(&mut self, n:usize) -> Result<&[u8]> {
+		let available:usize = self.buff.len() - self.pos;
+		if available < n {
+			return Err(XdrError::UnexpectedEof{ offset: self.pos, needed: n, available });
+		}
+		let start:usize = self.pos;
+		self.pos += n;
+		Ok(&self.buff[start..self.pos])
+	}
+
+	fn take_array<const N:usize>(&mut self) -> Result<[u8; N]> {
+		let mut ans = [0u8; N];
+		ans.copy_from_slice(self.take(N)?);
+		Ok(ans)
+	}
+
+	pub fn unpack_u32(&mut self) -> Result<u32> { self.take_array().map(u32::from_be_bytes) }
+	pub fn unpack_i32(&mut self) -> Result<i32> { self.take_array().map(i32::from_be_bytes) }
+
+	// An enum is just an i32 with a restricted set of values.  We can't check that this value is in the restricted set at this
+	// level because it depends on the application, so for our purposes here, an enum is the same as an i32
+	pub fn unpack_enum(&mut self) -> Result<i32> { self.unpack_i32() }
+
+	pub fn unpack_bool(&mut self) -> Result<bool> {
+		let offset:usize = self.pos;
+		match self.unpack_i32()? {
+			0 => Ok(false),
+			1 => Ok(true),
+			value => Err(XdrError::InvalidBool{ offset, value }),
+		}
+	}
+
+	pub fn unpack_u64(&mut self) -> Result<u64> { self.take_array().map(u64::from_be_bytes) }
+	pub fn unpack_i64(&mut self) -> Result<i64> { self.take_array().map(i64::from_be_bytes) }
+	pub fn unpack_f32(&mut self) -> Result<f32> { self.unpack_u32().map(f32::from_bits) }
+	pub fn unpack_f64(&mut self) -> Result<f64> { self.unpack_u64().map(f64::from_bits) }
+
+	// Borrowing versions of the opaque unpacking methods, which return a slice of the buffer without copying
+	pub fn unpack_fixed_len_opaque_ref(&mut self, n:usize) -> Result<&[u8]> {
+		let start:usize = self.pos;
+		self.take(n)?;
+
+		// The padding has to be there even at the end of a message
+		let pad_start:usize = self.pos;
+		let pad:&[u8] = self.take(n.div_ceil(4) * 4 - n)?;
+		if let Some(idx) = pad.iter().position(|b| *b != 0) {
+			return Err(XdrError::NonZeroPadding{ offset: pad_start + idx });
+		}
+		Ok(&self.buff[start..(start + n)])
+	}
+
+	pub fn unpack_variable_len_opaque_ref(&mut self, max:u32) -> Result<&[u8]> {
+		let offset:usize = self.pos;
+		let n:u32 = self.unpack_u32()?;
+		check_bound(offset, n as usize, max)?;
+		self.unpack_fixed_len_opaque_ref(n as usize)
+	}
+
+	pub fn unpack_fixed_len_opaque(&mut self, n:usize) -> Result<Vec<u8>> {
+		self.unpack_fixed_len_opaque_ref(n).map(|data| data.to_vec())
+	}
+
+	pub fn unpack_variable_len_opaque(&mut self) -> Result<Vec<u8>> {
+		self.unpack_variable_len_opaque_max(UNBOUNDED)
+	}
+
+	pub fn unpack_variable_len_opaque_max(&mut self, max:u32) -> Result<Vec<u8>> {
+		self.unpack_variable_len_opaque_ref(max).map(|data| data.to_vec())
+	}
+
+	pub fn unpack_string(&mut self, max:u32) -> Result<String> {
+		let offset:usize = self.pos + 4;
+		let bytes:&[u8] = self.unpack_variable_len_opaque_ref(max)?;
+		std::str::from_utf8(bytes).map(|s| s.to_owned()).map_err(|_| XdrError::InvalidUtf8{ offset })
+	}
+
+	// Arrays and optional-data, where each element is unpacked by the function provided
+	pub fn unpack_fixed_array<T, F, E>(&mut self, n:usize, mut f:F) -> std::result::Result<Vec<T>, E>
+		where F: FnMut(&mut Self) -> std::result::Result<T, E>
+	{
+		// Every element takes up at least four bytes, so don't let a bogus length allocate more than that
+		let mut ans:Vec<T> = Vec::with_capacity(n.min(self.remaining().len() / 4));
+		for _ in 0..n { ans.push(f(self)?); }
+		Ok(ans)
+	}
+
+	pub fn unpack_variable_array<T, F, E>(&mut self, max:u32, f:F) -> std::result::Result<Vec<T>, E>
+		where F: FnMut(&mut Self) -> std::result::Result<T, E>, E: From<XdrError>
+	{
+		let offset:usize = self.pos;
+		let n:u32 = self.unpack_u32()?;
+		check_bound(offset, n as usize, max)?;
+		self.unpack_fixed_array(n as usize, f)
+	}
+
+	pub fn unpack_optional<T, F, E>(&mut self, mut f:F) -> std::result::Result<Option<T>, E>
+		where F: FnMut(&mut Self) -> std::result::Result<T, E>, E: From<XdrError>
+	{
+		if self.unpack_bool()? { f(self).map(Some) }
+		else { Ok(None) }
+	}
+
+	// Unpack anything that implements Deserialize
+	pub fn unpack<T: DeserializeOwned>(&mut self) -> Result<T> {
+		de::unpack(self)
+	}
+
+}
+
+#[cfg(test)]
+mod tests {
+	use serde::{Serialize, Deserialize};
+
+	use super::*;
+
+	fn unpacker(data:&[u8]) -> Unpacker {
+		let mut unpacker = Unpacker::new();
+		unpacker.reset(data);
+		unpacker
+	}
+
+	#[test]
+	fn bools_other_than_zero_or_one() {
+		assert!(matches!(unpacker(&[0, 0, 0, 2]).unpack_bool(), Err(XdrError::InvalidBool{ offset: 0, value: 2 })));
+		assert!(matches!(from_bytes::<bool>(&[0xff, 0xff, 0xff, 0xff]), Err(XdrError::InvalidBool{ offset: 0, value: -1 })));
+	}
+
+	#[test]
+	fn padding_has_to_be_zero_and_present() {
+		assert!(matches!(unpacker(&[0, 0, 0, 1, 7, 0, 1, 0]).unpack_variable_len_opaque(), Err(XdrError::NonZeroPadding{ offset: 6 })));
+		assert!(matches!(unpacker(&[0, 0, 0, 1, 7]).unpack_variable_len_opaque(), Err(XdrError::UnexpectedEof{ offset: 5, needed: 3, available: 0 })));
+		assert_eq!(unpacker(&[0, 0, 0, 1, 7, 0, 0, 0]).unpack_variable_len_opaque().unwrap(), vec![7]);
+	}
+
+	#[test]
+	fn lengths_over_the_bound() {
+		let mut packer = Packer::new();
+		assert!(matches!(packer.pack_string("abcd", 3), Err(XdrError::LengthExceedsBound{ offset: 0, len: 4, max: 3 })));
+		assert!(matches!(packer.pack_variable_array(&[1u32, 2], 1, |p, x| p.pack_u32(*x)), Err(XdrError::LengthExceedsBound{ len: 2, max: 1, .. })));
+		assert!(packer.buff.is_empty());
+
+		let mut unpacker = unpacker(&[0, 0, 0, 4, b'a', b'b', b'c', b'd']);
+		assert!(matches!(unpacker.unpack_string(3), Err(XdrError::LengthExceedsBound{ offset: 0, len: 4, max: 3 })));
+		assert!(matches!(self::unpacker(&[0, 0, 0, 2]).unpack_variable_array(1, |u| u.unpack_u32()), Err(XdrError::LengthExceedsBound{ len: 2, max: 1, .. })));
+	}
+
+	#[test]
+	fn trailing_data() {
+		assert!(matches!(from_bytes::<u32>(&[0, 0, 0, 1, 0, 0, 0, 2]), Err(XdrError::TrailingData{ offset: 4, len: 4 })));
+	}
+
+	#[test]
+	fn hyper_float_and_double() {
+		assert_eq!(to_bytes(&-2i64).unwrap(), vec![0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe]);
+		assert_eq!(to_bytes(&(1u64 << 32)).unwrap(), vec![0, 0, 0, 1, 0, 0, 0, 0]);
+		assert_eq!(to_bytes(&1.5f32).unwrap(), vec![0x3f, 0xc0, 0, 0]);
+		assert_eq!(to_bytes(&-2.0f64).unwrap(), vec![0xc0, 0, 0, 0, 0, 0, 0, 0]);
+
+		assert_eq!(from_bytes::<i64>(&to_bytes(&i64::MIN).unwrap()).unwrap(), i64::MIN);
+		assert_eq!(from_bytes::<u64>(&to_bytes(&u64::MAX).unwrap()).unwrap(), u64::MAX);
+		assert_eq!(from_bytes::<f32>(&to_bytes(&1.5f32).unwrap()).unwrap(), 1.5);
+		assert_eq!(from_bytes::<f64>(&to_bytes(&-2.0f64).unwrap()).unwrap(), -2.0);
+	}
+
+	#[test]
+	fn optional_data() {
+		assert_eq!(to_bytes(&Some(5u32)).unwrap(), vec![0, 0, 0, 1, 0, 0, 0, 5]);
+		assert_eq!(to_bytes(&None::<u32>).unwrap(), vec![0, 0, 0, 0]);
+		assert_eq!(from_bytes::<Option<u32>>(&[0, 0, 0, 1, 0, 0, 0, 5]).unwrap(), Some(5));
+		assert_eq!(from_bytes::<Option<u32>>(&[0, 0, 0, 0]).unwrap(), None);
+		assert!(matches!(from_bytes::<Option<u32>>(&[0, 0, 0, 3]), Err(XdrError::InvalidBool{ offset: 0, value: 3 })));
+
+		let mut packer = Packer::new();
+		packer.pack_optional(Some(&7u32), |p, x| p.pack_u32(*x)).unwrap();
+		packer.pack_optional(None::<&u32>, |p, x| p.pack_u32(*x)).unwrap();
+		let mut unpacker = unpacker(&packer.buff);
+		assert_eq!(unpacker.unpack_optional(|u| u.unpack_u32()).unwrap(), Some(7));
+		assert_eq!(unpacker.unpack_optional(|u| u.unpack_u32()).unwrap(), None);
+	}
+
+	#[test]
+	fn owned_buffers_are_used_in_place() {
+		let data:Vec<u8> = vec![0, 0, 0, 3, b'a', b'b', b'c', 0, 1, 2, 3, 4];
+		let ptr:*const u8 = data.as_ptr();
+
+		let mut unpacker = Unpacker::new();
+		unpacker.reset_owned(data);
+		assert_eq!(unpacker.remaining().as_ptr(), ptr);
+
+		// Borrowed opaque data points into the same buffer
+		let abc:&[u8] = unpacker.unpack_variable_len_opaque_ref(UNBOUNDED).unwrap();
+		assert_eq!(abc, b"abc");
+		assert_eq!(abc.as_ptr(), ptr.wrapping_add(4));
+
+		// What's left moves to the front of the same allocation rather than into a new one
+		let rest:Vec<u8> = unpacker.take_remaining();
+		assert_eq!(rest, vec![1, 2, 3, 4]);
+		assert_eq!(rest.as_ptr(), ptr);
+		assert!(unpacker.all_data_consumed());
+	}
+
+	#[test]
+	fn borrowed_opaque_data() {
+		let mut unpacker = unpacker(&[1, 2, 3, 4, 5, 0, 0, 0, 0, 0, 0, 2, 6, 7, 0, 0]);
+		assert_eq!(unpacker.unpack_fixed_len_opaque_ref(5).unwrap(), &[1, 2, 3, 4, 5]);
+		assert_eq!(unpacker.unpack_variable_len_opaque_ref(2).unwrap(), &[6, 7]);
+		assert!(unpacker.all_data_consumed());
+
+		assert!(matches!(self::unpacker(&[1, 2]).unpack_fixed_len_opaque_ref(3), Err(XdrError::UnexpectedEof{ offset: 0, needed: 3, available: 2 })));
+		assert!(matches!(self::unpacker(&[0, 0]).unpack_variable_len_opaque_ref(UNBOUNDED), Err(XdrError::UnexpectedEof{ offset: 0, needed: 4, available: 2 })));
+		assert!(matches!(self::unpacker(&[0, 0, 0, 8, 1, 2, 3, 4]).unpack_variable_len_opaque_ref(UNBOUNDED), Err(XdrError::UnexpectedEof{ offset: 4, needed: 8, available: 4 })));
+		assert!(matches!(self::unpacker(&[0, 0, 0, 8]).unpack_variable_len_opaque_ref(4), Err(XdrError::LengthExceedsBound{ offset: 0, len: 8, max: 4 })));
+	}
+
+	#[derive(Debug, PartialEq, Serialize, Deserialize)]
+	enum Numbered {
+		#[serde(rename = "3")]  Three(u32),
+		#[serde(rename = "17")] Seventeen,
+		#[serde(rename = "-1")] MinusOne{ x: bool },
+	}
+
+	#[test]
+	fn numbered_union_discriminants() {
+		let cases = [
+			(Numbered::Three(9), vec![0, 0, 0, 3, 0, 0, 0, 9]),
+			(Numbered::Seventeen, vec![0, 0, 0, 17]),
+			(Numbered::MinusOne{ x: true }, vec![0xff, 0xff, 0xff, 0xff, 0, 0, 0, 1]),
+		];
+		for (value, bytes) in cases {
+			assert_eq!(to_bytes(&value).unwrap(), bytes);
+			assert_eq!(from_bytes::<Numbered>(&bytes).unwrap(), value);
+		}
+
+		// The declaration index doesn't count once the arms are numbered
+		assert!(matches!(from_bytes::<Numbered>(&[0, 0, 0, 0]), Err(XdrError::InvalidDiscriminant{ offset: 0, value: 0 })));
+	}
+
+	#[cfg(target_pointer_width = "64")]
+	#[test]
+	fn lengths_past_u32_are_errors_even_unbounded() {
+		let mut packer = Packer::new();
+		let len:usize = u32::MAX as usize + 1;
+		assert!(matches!(packer.pack_len(len, UNBOUNDED), Err(XdrError::LengthExceedsBound{ len: l, max: UNBOUNDED, .. }) if l == len));
+		assert!(packer.buff.is_empty());
+	}
+}
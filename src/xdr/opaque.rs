@@ -0,0 +1,71 @@
+
+// Helpers for opaque data, for use with #[serde(with = "...")]
+//
+// By default serde treats Vec<u8> and [u8; N] as sequences of integers, which XDR would encode with four bytes per
+// element.  Use variable for opaque<> fields and fixed for opaque[N] fields instead.
+
+use std::fmt;
+
+use serde::Deserializer;
+use serde::de::{self, Visitor, SeqAccess};
+
+// Marker recognized by the XDR serializer and deserializer.  Other data formats just see a newtype around bytes.
+pub(crate) const FIXED_OPAQUE_TOKEN:&str = "$xdr::FixedOpaque";
+
+struct BytesVisitor;
+
+impl<'de> Visitor<'de> for BytesVisitor {
+	type Value = Vec<u8>;
+
+	fn expecting(&self, f:&mut fmt::Formatter) -> fmt::Result { write!(f, "opaque data") }
+
+	fn visit_bytes<E: de::Error>(self, v:&[u8]) -> Result<Vec<u8>, E> { Ok(v.to_vec()) }
+	fn visit_byte_buf<E: de::Error>(self, v:Vec<u8>) -> Result<Vec<u8>, E> { Ok(v) }
+
+	fn visit_seq<A: SeqAccess<'de>>(self, mut seq:A) -> Result<Vec<u8>, A::Error> {
+		let mut ans:Vec<u8> = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+		while let Some(b) = seq.next_element::<u8>()? { ans.push(b); }
+		Ok(ans)
+	}
+
+	fn visit_newtype_struct<D: Deserializer<'de>>(self, d:D) -> Result<Vec<u8>, D::Error> {
+		d.deserialize_byte_buf(BytesVisitor)
+	}
+}
+
+// opaque identifier<>
+pub mod variable {
+	use serde::{Serializer, Deserializer};
+
+	pub fn serialize<S: Serializer>(data:&[u8], s:S) -> Result<S::Ok, S::Error> {
+		s.serialize_bytes(data)
+	}
+
+	pub fn deserialize<'de, D: Deserializer<'de>>(d:D) -> Result<Vec<u8>, D::Error> {
+		d.deserialize_byte_buf(super::BytesVisitor)
+	}
+}
+
+// opaque identifier[N]
+pub mod fixed {
+	use std::convert::TryInto;
+
+	use serde::{Serialize, Serializer, Deserializer};
+	use serde::de::Error;
+
+	struct Bytes<'a>(&'a [u8]);
+
+	impl<'a> Serialize for Bytes<'a> {
+		fn serialize<S: Serializer>(&self, s:S) -> Result<S::Ok, S::Error> { s.serialize_bytes(self.0) }
+	}
+
+	pub fn serialize<S: Serializer, const N:usize>(data:&[u8; N], s:S) -> Result<S::Ok, S::Error> {
+		s.serialize_newtype_struct(super::FIXED_OPAQUE_TOKEN, &Bytes(data))
+	}
+
+	pub fn deserialize<'de, D: Deserializer<'de>, const N:usize>(d:D) -> Result<[u8; N], D::Error> {
+		let data:Vec<u8> = d.deserialize_tuple_struct(super::FIXED_OPAQUE_TOKEN, N, super::BytesVisitor)?;
+		let len:usize = data.len();
+		data.try_into().map_err(|_| D::Error::invalid_length(len, &"fixed-length opaque data"))
+	}
+}
@@ -0,0 +1,193 @@
+
+// Serde serializer that writes XDR (RFC 4506) into a Packer
+//
+// Rust types map onto XDR as follows:
+//   bool                          -> bool
+//   i8, i16, i32 / u8, u16, u32   -> int / unsigned int
+//   i64 / u64                     -> hyper / unsigned hyper
+//   f32 / f64                     -> float / double
+//   char                          -> unsigned int
+//   String, &str                  -> string
+//   bytes (see xdr::opaque)       -> variable or fixed-length opaque
+//   Option<T>                     -> optional-data (bool followed by T if present)
+//   Vec<T>, slices, maps          -> variable-length array (maps become arrays of key/value pairs)
+//   [T; N], tuples                -> fixed-length array
+//   structs                       -> struct (fields in declaration order)
+//   enums                         -> enum or discriminated union
+//
+// Enum discriminants are the declaration index of the variant unless the variant is renamed to an integer, for
+// example #[serde(rename = "17")], in which case that integer is used.  This makes it possible to describe unions
+// like the RPC reply_stat whose arms aren't numbered from zero.
+
+use serde::ser::{self, Serialize};
+
+use super::{Packer, XdrError, Result, UNBOUNDED};
+use super::opaque::FIXED_OPAQUE_TOKEN;
+
+pub struct Serializer<'a> {
+	packer: &'a mut Packer,
+	fixed_opaque: bool,
+}
+
+impl<'a> Serializer<'a> {
+	pub fn new(packer:&'a mut Packer) -> Self { Self{ packer, fixed_opaque: false } }
+}
+
+pub fn pack<T: Serialize + ?Sized>(packer:&mut Packer, value:&T) -> Result<()> {
+	value.serialize(&mut Serializer::new(packer))
+}
+
+pub fn to_bytes<T: Serialize + ?Sized>(value:&T) -> Result<Vec<u8>> {
+	let mut packer = Packer::new();
+	pack(&mut packer, value)?;
+	Ok(packer.buff)
+}
+
+pub(crate) fn discriminant(variant_index:u32, variant:&str) -> i32 {
+	variant.parse::<i32>().unwrap_or(variant_index as i32)
+}
+
+impl<'a, 'b> ser::Serializer for &'b mut Serializer<'a> {
+	type Ok = ();
+	type Error = XdrError;
+
+	type SerializeSeq = Self;
+	type SerializeTuple = Self;
+	type SerializeTupleStruct = Self;
+	type SerializeTupleVariant = Self;
+	type SerializeMap = Self;
+	type SerializeStruct = Self;
+	type SerializeStructVariant = Self;
+
+	fn serialize_bool(self, v:bool) -> Result<()> { self.packer.pack_bool(v) }
+
+	fn serialize_i8(self, v:i8)   -> Result<()> { self.serialize_i32(v as i32) }
+	fn serialize_i16(self, v:i16) -> Result<()> { self.serialize_i32(v as i32) }
+	fn serialize_i32(self, v:i32) -> Result<()> { self.packer.pack_i32(v) }
+	fn serialize_i64(self, v:i64) -> Result<()> { self.packer.pack_i64(v) }
+
+	fn serialize_u8(self, v:u8)   -> Result<()> { self.serialize_u32(v as u32) }
+	fn serialize_u16(self, v:u16) -> Result<()> { self.serialize_u32(v as u32) }
+	fn serialize_u32(self, v:u32) -> Result<()> { self.packer.pack_u32(v) }
+	fn serialize_u64(self, v:u64) -> Result<()> { self.packer.pack_u64(v) }
+
+	fn serialize_f32(self, v:f32) -> Result<()> { self.packer.pack_f32(v) }
+	fn serialize_f64(self, v:f64) -> Result<()> { self.packer.pack_f64(v) }
+
+	fn serialize_char(self, v:char) -> Result<()> { self.serialize_u32(v as u32) }
+	fn serialize_str(self, v:&str)  -> Result<()> { self.packer.pack_string(v, UNBOUNDED) }
+
+	fn serialize_bytes(self, v:&[u8]) -> Result<()> {
+		if self.fixed_opaque {
+			// Fixed-length opaque data has no length prefix, just the data and padding
+			self.fixed_opaque = false;
+			self.packer.pack_fixed_len_opaque(v)
+		} else {
+			self.packer.pack_variable_len_opaque(v)
+		}
+	}
+
+	fn serialize_none(self) -> Result<()> { self.serialize_bool(false) }
+	fn serialize_some<T: Serialize + ?Sized>(self, value:&T) -> Result<()> {
+		self.packer.pack_bool(true)?;
+		value.serialize(self)
+	}
+
+	// Void in XDR takes up no space
+	fn serialize_unit(self) -> Result<()> { Ok(()) }
+	fn serialize_unit_struct(self, _name:&'static str) -> Result<()> { Ok(()) }
+
+	fn serialize_unit_variant(self, _name:&'static str, variant_index:u32, variant:&'static str) -> Result<()> {
+		self.packer.pack_enum(discriminant(variant_index, variant))
+	}
+
+	fn serialize_newtype_struct<T: Serialize + ?Sized>(self, name:&'static str, value:&T) -> Result<()> {
+		if name == FIXED_OPAQUE_TOKEN { self.fixed_opaque = true; }
+		value.serialize(self)
+	}
+
+	fn serialize_newtype_variant<T: Serialize + ?Sized>(self, _name:&'static str, variant_index:u32, variant:&'static str, value:&T) -> Result<()> {
+		self.packer.pack_enum(discriminant(variant_index, variant))?;
+		value.serialize(self)
+	}
+
+	fn serialize_seq(self, len:Option<usize>) -> Result<Self::SerializeSeq> {
+		let n = len.ok_or(XdrError::Unsupported("sequences of unknown length"))?;
+		self.packer.pack_len(n, UNBOUNDED)?;
+		Ok(self)
+	}
+
+	fn serialize_tuple(self, _len:usize) -> Result<Self::SerializeTuple> { Ok(self) }
+
+	fn serialize_tuple_struct(self, _name:&'static str, _len:usize) -> Result<Self::SerializeTupleStruct> { Ok(self) }
+
+	fn serialize_tuple_variant(self, _name:&'static str, variant_index:u32, variant:&'static str, _len:usize) -> Result<Self::SerializeTupleVariant> {
+		self.packer.pack_enum(discriminant(variant_index, variant))?;
+		Ok(self)
+	}
+
+	fn serialize_map(self, len:Option<usize>) -> Result<Self::SerializeMap> {
+		let n = len.ok_or(XdrError::Unsupported("maps of unknown length"))?;
+		self.packer.pack_len(n, UNBOUNDED)?;
+		Ok(self)
+	}
+
+	fn serialize_struct(self, _name:&'static str, _len:usize) -> Result<Self::SerializeStruct> { Ok(self) }
+
+	fn serialize_struct_variant(self, _name:&'static str, variant_index:u32, variant:&'static str, _len:usize) -> Result<Self::SerializeStructVariant> {
+		self.packer.pack_enum(discriminant(variant_index, variant))?;
+		Ok(self)
+	}
+
+	fn is_human_readable(&self) -> bool { false }
+}
+
+impl<'a, 'b> ser::SerializeSeq for &'b mut Serializer<'a> {
+	type Ok = ();
+	type Error = XdrError;
+	fn serialize_element<T: Serialize + ?Sized>(&mut self, value:&T) -> Result<()> { value.serialize(&mut **self) }
+	fn end(self) -> Result<()> { Ok(()) }
+}
+
+impl<'a, 'b> ser::SerializeTuple for &'b mut Serializer<'a> {
+	type Ok = ();
+	type Error = XdrError;
+	fn serialize_element<T: Serialize + ?Sized>(&mut self, value:&T) -> Result<()> { value.serialize(&mut **self) }
+	fn end(self) -> Result<()> { Ok(()) }
+}
+
+impl<'a, 'b> ser::SerializeTupleStruct for &'b mut Serializer<'a> {
+	type Ok = ();
+	type Error = XdrError;
+	fn serialize_field<T: Serialize + ?Sized>(&mut self, value:&T) -> Result<()> { value.serialize(&mut **self) }
+	fn end(self) -> Result<()> { Ok(()) }
+}
+
+impl<'a, 'b> ser::SerializeTupleVariant for &'b mut Serializer<'a> {
+	type Ok = ();
+	type Error = XdrError;
+	fn serialize_field<T: Serialize + ?Sized>(&mut self, value:&T) -> Result<()> { value.serialize(&mut **self) }
+	fn end(self) -> Result<()> { Ok(()) }
+}
+
+impl<'a, 'b> ser::SerializeMap for &'b mut Serializer<'a> {
+	type Ok = ();
+	type Error = XdrError;
+	fn serialize_key<T: Serialize + ?Sized>(&mut self, key:&T) -> Result<()> { key.serialize(&mut **self) }
+	fn serialize_value<T: Serialize + ?Sized>(&mut self, value:&T) -> Result<()> { value.serialize(&mut **self) }
+	fn end(self) -> Result<()> { Ok(()) }
+}
+
+impl<'a, 'b> ser::SerializeStruct for &'b mut Serializer<'a> {
+	type Ok = ();
+	type Error = XdrError;
+	fn serialize_field<T: Serialize + ?Sized>(&mut self, _key:&'static str, value:&T) -> Result<()> { value.serialize(&mut **self) }
+	fn end(self) -> Result<()> { Ok(()) }
+}
+
+impl<'a, 'b> ser::SerializeStructVariant for &'b mut Serializer<'a> {
+	type Ok = ();
+	type Error = XdrError;
+	fn serialize_field<T: Serialize + ?Sized>(&mut self, _key:&'static str, value:&T) -> Result<()> { value.serialize(&mut **self) }
+	fn end(self) -> Result<()> { Ok(()) }
+}